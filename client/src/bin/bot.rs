@@ -0,0 +1,25 @@
+use std::time::Duration;
+
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::log::{Level, LogPlugin};
+use bevy::prelude::*;
+use clap::Parser;
+
+use mangovillage_client::bot::{BotPlugin, Cli};
+
+/// Frames per second to poll connections at, above any sensible server send rate so snapshots are timed accurately
+const POLL_RATE: f64 = 240.0;
+
+fn main() {
+    let settings = Cli::parse().settings().unwrap_or_else(|e| {
+        eprintln!("[bot] {}", e);
+        std::process::exit(1);
+    });
+    println!("[bot] Connecting {} bots to {}", settings.count, settings.server_addr);
+
+    App::new()
+        .add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / POLL_RATE))))
+        .add_plugins(LogPlugin { filter: "info,mangovillage_client=info,durian=warn".to_string(), level: Level::INFO })
+        .add_plugins(BotPlugin { settings })
+        .run();
+}
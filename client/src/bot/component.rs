@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::{Component, Vec2};
+use durian::{Packet, PacketBuilder};
+
+use mangovillage_common::networking::transport::Transport;
+
+use crate::player::resource::ReceivedSnapshots;
+
+/// Movements kept around waiting for the server to process them, so a stalled server doesn't grow them forever
+const MAX_PENDING_INPUTS: usize = 256;
+
+/// One simulated player with its own connection to the server
+#[derive(Component)]
+pub struct Bot {
+    pub index: usize,
+    pub transport: Box<dyn Transport>,
+    /// Local seconds the Connect was sent at
+    pub connect_started: f64,
+    /// Local seconds the server accepted the bot at, once it has
+    pub joined_at: Option<f64>,
+    /// Persistent id of the bot's player, from ConnectAck
+    pub id: u32,
+    pub snapshots: ReceivedSnapshots,
+    /// Players packets received, including ones that couldn't be reconstructed
+    pub snapshots_received: u32,
+    /// Local seconds the last snapshot arrived at
+    pub last_heard: f64,
+    pub last_sequence: u32,
+    /// Sequence of each Movement the server hasn't processed yet, and the local seconds it was sent at
+    pub pending_inputs: VecDeque<(u32, f64)>,
+    /// Direction the bot is walking in
+    pub direction: Vec2,
+    /// Local seconds to change direction at
+    pub next_turn: f64,
+}
+
+impl Bot {
+    pub fn new(index: usize, transport: Box<dyn Transport>, now: f64) -> Self {
+        Bot {
+            index,
+            transport,
+            connect_started: now,
+            joined_at: None,
+            id: 0,
+            snapshots: ReceivedSnapshots::default(),
+            snapshots_received: 0,
+            last_heard: now,
+            last_sequence: 0,
+            pending_inputs: VecDeque::new(),
+            direction: Vec2::X,
+            next_turn: now,
+        }
+    }
+
+    /// Packets of one type from the server.  Connection problems show up as the server going quiet.
+    pub fn received<T: Packet + Send + 'static, B: PacketBuilder<T> + 'static>(&mut self) -> Vec<T> {
+        self.transport.received::<T, B>(false).ok().flatten().unwrap_or_default()
+    }
+
+    /// Track a Movement sent at `now`, returning its sequence number
+    pub fn push_input(&mut self, now: f64) -> u32 {
+        self.last_sequence += 1;
+        if self.pending_inputs.len() >= MAX_PENDING_INPUTS {
+            self.pending_inputs.pop_front();
+        }
+        self.pending_inputs.push_back((self.last_sequence, now));
+        self.last_sequence
+    }
+
+    /// Drop inputs the server has processed, returning how many seconds each took
+    pub fn acknowledge(&mut self, last_processed: u32, now: f64) -> Vec<f64> {
+        let mut latencies = Vec::new();
+        while let Some(&(sequence, sent_at)) = self.pending_inputs.front() {
+            if sequence > last_processed {
+                break;
+            }
+            self.pending_inputs.pop_front();
+            latencies.push(now - sent_at);
+        }
+        latencies
+    }
+
+    /// Snapshots per second received since joining
+    pub fn snapshot_rate(&self, now: f64) -> Option<f64> {
+        let connected_for = now - self.joined_at?;
+        (connected_for > 0.0).then(|| self.snapshots_received as f64 / connected_for)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mangovillage_common::networking::transport::{ChannelNetwork, LinkConditions};
+
+    use super::*;
+
+    fn bot() -> Bot {
+        let network = ChannelNetwork::new(LinkConditions::default(), 0);
+        let server_addr = "127.0.0.1:28154".parse().unwrap();
+        let _server = network.listen(server_addr).unwrap();
+        let transport = network.connect("127.0.0.1:6000".parse().unwrap(), server_addr).unwrap();
+        Bot::new(0, Box::new(transport), 0.0)
+    }
+
+    #[test]
+    fn acknowledge_measures_processed_inputs() {
+        let mut bot = bot();
+        assert_eq!(bot.push_input(1.0), 1);
+        assert_eq!(bot.push_input(2.0), 2);
+        assert_eq!(bot.push_input(3.0), 3);
+
+        assert_eq!(bot.acknowledge(2, 3.5), vec![2.5, 1.5]);
+        assert_eq!(bot.pending_inputs, VecDeque::from([(3, 3.0)]));
+        // Already processed
+        assert!(bot.acknowledge(2, 4.0).is_empty());
+        assert_eq!(bot.acknowledge(3, 4.0), vec![1.0]);
+        assert!(bot.pending_inputs.is_empty());
+    }
+
+    #[test]
+    fn pending_inputs_are_capped() {
+        let mut bot = bot();
+        for i in 0..MAX_PENDING_INPUTS + 10 {
+            bot.push_input(i as f64);
+        }
+        assert_eq!(bot.pending_inputs.len(), MAX_PENDING_INPUTS);
+        assert_eq!(bot.pending_inputs.front(), Some(&(11, 10.0)));
+        assert_eq!(bot.acknowledge(bot.last_sequence, 1000.0).len(), MAX_PENDING_INPUTS);
+    }
+}
@@ -0,0 +1,306 @@
+//! Headless simulated players for load testing a server, see the `bot` binary
+
+use std::net::IpAddr;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use clap::Parser;
+use rand::Rng;
+
+use mangovillage_common::networking::client_packets::{Disconnect, Movement, Pong, ReplicationAck, SnapshotAck};
+use mangovillage_common::networking::diagnostics::resource::NetworkStats;
+use mangovillage_common::networking::server_packets::{
+    ConnectAck, ConnectAckPacketBuilder, ConnectReject, ConnectRejectPacketBuilder, Ping, PingPacketBuilder, Player, Players, PlayersLeft,
+    PlayersLeftPacketBuilder, PlayersPacketBuilder, Replication, ReplicationPacketBuilder, SpawnScene, SpawnScenePacketBuilder,
+};
+use mangovillage_common::networking::transport::NetworkConditions;
+
+use crate::bot::component::Bot;
+use crate::bot::resource::{BotSettings, BotStats, Distribution, MovePattern};
+use crate::networking;
+use crate::networking::resource::{Connector, ReconnectSettings};
+
+pub mod component;
+pub mod resource;
+
+/// Clicks are this far from the window center, which only sets the direction to walk in
+const CLICK_DISTANCE: f32 = 100.0;
+
+#[derive(Parser)]
+#[command(about = "Headless bots for load testing a Mango Village server")]
+pub struct Cli {
+    /// Number of bots to connect
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub bots: usize,
+    /// Server to connect to
+    #[arg(short, long, default_value = "127.0.0.1:28154")]
+    pub server: String,
+    /// Local ip to bind bots to
+    #[arg(long, default_value = "0.0.0.0")]
+    pub bind_ip: IpAddr,
+    /// Bots bind to consecutive ports starting at this one
+    #[arg(long, default_value_t = 6000)]
+    pub first_port: u16,
+    /// Bots log in as this followed by their number, registering the account if needed
+    #[arg(long, default_value = "bot_")]
+    pub username_prefix: String,
+    /// Password of every bot account.  Prefer the environment variable, as arguments are visible to other users.
+    #[arg(long, env = "MANGOVILLAGE_BOT_PASSWORD", hide_env_values = true)]
+    pub password: String,
+    /// Seconds between connecting each bot
+    #[arg(long, default_value_t = 0.05)]
+    pub spawn_interval: f64,
+    /// Seconds to run for before reporting
+    #[arg(short, long, default_value_t = 60.0)]
+    pub duration: f64,
+    /// Seconds to wait for the server to accept a bot
+    #[arg(long, default_value_t = 10.0)]
+    pub connect_timeout: f64,
+    #[arg(long, value_enum, default_value_t = MovePattern::Random)]
+    pub pattern: MovePattern,
+    /// Seconds between changes of direction
+    #[arg(long, default_value_t = 2.0)]
+    pub turn_interval: f64,
+    /// Movement packets each bot sends per second, should match the server's tick rate like real clients
+    #[arg(long, default_value_t = 60)]
+    pub input_rate: u32,
+}
+
+impl Cli {
+    pub fn settings(self) -> Result<BotSettings, String> {
+        if self.bots == 0 {
+            return Err("--bots must be at least 1".to_string());
+        }
+        if self.first_port as usize + self.bots > u16::MAX as usize + 1 {
+            return Err(format!("not enough ports above {} for {} bots", self.first_port, self.bots));
+        }
+        if self.input_rate == 0 {
+            return Err("--input-rate must be at least 1".to_string());
+        }
+        Ok(BotSettings {
+            count: self.bots,
+            server_addr: self.server,
+            bind_ip: self.bind_ip,
+            first_port: self.first_port,
+            username_prefix: self.username_prefix,
+            password: self.password,
+            spawn_interval: self.spawn_interval,
+            duration: self.duration,
+            connect_timeout: self.connect_timeout,
+            pattern: self.pattern,
+            turn_interval: self.turn_interval,
+            input_rate: self.input_rate,
+        })
+    }
+}
+
+/// Connects bots one at a time, walks them around, and reports on how the server kept up once the run is over
+pub struct BotPlugin {
+    pub settings: BotSettings,
+}
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<Connector>() {
+            app.insert_resource(Connector(Box::new(networking::connect_durian)));
+        }
+        app.insert_resource(self.settings.clone())
+            .insert_resource(FixedTime::new_from_secs(1.0 / self.settings.input_rate as f32))
+            .init_resource::<NetworkConditions>()
+            .init_resource::<ReconnectSettings>()
+            .init_resource::<BotStats>()
+            // Shared by every bot, so only the totals mean anything
+            .init_resource::<NetworkStats>()
+            .add_systems(Update, (spawn_bots, join_bots, update_bots, finish).chain())
+            .add_systems(FixedUpdate, move_bots);
+    }
+}
+
+fn spawn_bots(
+    mut commands: Commands,
+    settings: Res<BotSettings>,
+    mut stats: ResMut<BotStats>,
+    connector: Res<Connector>,
+    conditions: Res<NetworkConditions>,
+    network_stats: Res<NetworkStats>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_seconds_f64();
+    while stats.spawned < settings.count && now >= stats.next_spawn {
+        let index = stats.spawned;
+        stats.spawned += 1;
+        stats.next_spawn = now + settings.spawn_interval;
+        match networking::connect(&settings.client_info(index), None, &connector, &conditions, &network_stats) {
+            Ok(transport) => {
+                commands.spawn(Bot::new(index, transport, now));
+            }
+            Err(e) => {
+                warn!("[bot] Bot {} could not connect: {}", index, e);
+                stats.failed.push(e);
+            }
+        }
+    }
+}
+
+/// Wait for the server to accept or reject each new bot
+fn join_bots(mut commands: Commands, mut bots: Query<(Entity, &mut Bot)>, settings: Res<BotSettings>, mut stats: ResMut<BotStats>, time: Res<Time>) {
+    let now = time.elapsed_seconds_f64();
+    for (entity, mut bot) in bots.iter_mut() {
+        if bot.joined_at.is_some() {
+            continue;
+        }
+        if let Some(reject) = bot.received::<ConnectReject, ConnectRejectPacketBuilder>().pop() {
+            warn!("[bot] Bot {} rejected: {}", bot.index, reject.reason);
+            *stats.rejected.entry(reject.reason.to_string()).or_default() += 1;
+            let _ = bot.transport.send(Disconnect);
+            commands.entity(entity).despawn();
+        } else if let Some(ack) = bot.received::<ConnectAck, ConnectAckPacketBuilder>().pop() {
+            debug!("[bot] Bot {} joined as player {}", bot.index, ack.id);
+            bot.joined_at = Some(now);
+            bot.last_heard = now;
+            bot.id = ack.id;
+            bot.direction = settings.pattern.turn(Vec2::X, &mut rand::thread_rng());
+            stats.connected += 1;
+            stats.connect_times.push(now - bot.connect_started);
+        } else if now - bot.connect_started > settings.connect_timeout {
+            warn!("[bot] Bot {} timed out waiting for the server", bot.index);
+            stats.timed_out += 1;
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Acknowledge snapshots and answer pings like a real client so the server keeps sending deltas, and measure how
+/// quickly our inputs show up in them
+fn update_bots(
+    mut commands: Commands,
+    mut bots: Query<(Entity, &mut Bot)>,
+    reconnect_settings: Res<ReconnectSettings>,
+    mut stats: ResMut<BotStats>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_seconds_f64();
+    for (entity, mut bot) in bots.iter_mut() {
+        if bot.joined_at.is_none() {
+            continue;
+        }
+        let bot = &mut *bot;
+        // Not used, but they would pile up otherwise
+        bot.received::<SpawnScene, SpawnScenePacketBuilder>();
+        bot.received::<PlayersLeft, PlayersLeftPacketBuilder>();
+        // Acknowledged anyway, or the server would keep resending everything
+        if let Some(tick) = bot.received::<Replication, ReplicationPacketBuilder>().iter().map(|replication| replication.tick).max() {
+            let _ = bot.transport.send(ReplicationAck { tick });
+        }
+
+        for ping in bot.received::<Ping, PingPacketBuilder>() {
+            let _ = bot.transport.send(Pong { id: ping.id });
+            stats.rtts.extend(ping.rtt_ms.map(f64::from));
+        }
+
+        let packets = bot.received::<Players, PlayersPacketBuilder>();
+        bot.snapshots_received += packets.len() as u32;
+        let mut latest: Option<(u32, HashMap<u32, Player>)> = None;
+        for packet in packets {
+            let tick = packet.tick;
+            if let Some(players) = bot.snapshots.reconstruct(packet) {
+                latest = Some((tick, players));
+            }
+        }
+        if let Some((tick, players)) = latest {
+            bot.last_heard = now;
+            let _ = bot.transport.send(SnapshotAck { tick });
+            if let Some(me) = players.get(&bot.id) {
+                let latencies = bot.acknowledge(me.last_input, now);
+                stats.input_latencies.extend(latencies);
+            }
+        } else if now - bot.last_heard > reconnect_settings.timeout {
+            warn!("[bot] Bot {} lost connection", bot.index);
+            stats.lost += 1;
+            stats.snapshot_rates.extend(bot.snapshot_rate(bot.last_heard));
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Hold the move button down like a player, changing direction every so often
+fn move_bots(mut bots: Query<&mut Bot>, settings: Res<BotSettings>, time: Res<Time>) {
+    let now = time.elapsed_seconds_f64();
+    let mut rng = rand::thread_rng();
+    for mut bot in bots.iter_mut() {
+        if bot.joined_at.is_none() {
+            continue;
+        }
+        if now >= bot.next_turn {
+            bot.direction = settings.pattern.turn(bot.direction, &mut rng);
+            // Spread turns out so bots don't all turn on the same frame
+            bot.next_turn = now + settings.turn_interval * rng.gen_range(0.5..1.5);
+        }
+        let translation = (bot.direction * CLICK_DISTANCE).to_array();
+        let sequence = bot.push_input(now);
+        let _ = bot.transport.send(Movement { translation, sequence });
+    }
+}
+
+/// Disconnect every bot and print the report once the run is over, or once every bot has dropped out
+fn finish(
+    mut bots: Query<&mut Bot>,
+    settings: Res<BotSettings>,
+    mut stats: ResMut<BotStats>,
+    network_stats: Res<NetworkStats>,
+    time: Res<Time>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let now = time.elapsed_seconds_f64();
+    let all_gone = stats.spawned == settings.count && bots.is_empty();
+    if now < settings.duration && !all_gone {
+        return;
+    }
+    for mut bot in bots.iter_mut() {
+        stats.snapshot_rates.extend(bot.snapshot_rate(now));
+        let _ = bot.transport.send(Disconnect);
+    }
+    report(&settings, &stats, &network_stats, now);
+    exit.send(AppExit);
+}
+
+fn report(settings: &BotSettings, stats: &BotStats, network_stats: &NetworkStats, elapsed: f64) {
+    println!("[bot] Ran {} bots against {} for {:.1}s", settings.count, settings.server_addr, elapsed);
+    println!(
+        "[bot] Connected {}/{}, rejected {}, failed {}, timed out {}, lost connection {}",
+        stats.connected,
+        settings.count,
+        stats.rejected.values().sum::<usize>(),
+        stats.failed.len(),
+        stats.timed_out,
+        stats.lost
+    );
+    for (reason, count) in stats.rejected.iter() {
+        println!("[bot]   {} rejected: {}", count, reason);
+    }
+    if let Some(error) = stats.failed.first() {
+        println!("[bot]   First connection error: {}", error);
+    }
+    let summaries = [
+        ("Connect time (ms)", Distribution::of(&stats.connect_times).map(|distribution| distribution.scaled(1000.0))),
+        ("Snapshots per second per bot", Distribution::of(&stats.snapshot_rates)),
+        ("Input latency (ms)", Distribution::of(&stats.input_latencies).map(|distribution| distribution.scaled(1000.0))),
+        ("RTT (ms)", Distribution::of(&stats.rtts)),
+    ];
+    for (name, distribution) in summaries {
+        match distribution {
+            Some(distribution) => println!("[bot] {}: {}", name, distribution),
+            None => println!("[bot] {}: no samples", name),
+        }
+    }
+    let totals = network_stats.totals();
+    println!(
+        "[bot] Sent {} packets ({:.1} KiB/s), received {} packets ({:.1} KiB/s), dropped {}",
+        totals.sent.packets,
+        totals.sent.bytes as f64 / 1024.0 / elapsed,
+        totals.received.packets,
+        totals.received.bytes as f64 / 1024.0 / elapsed,
+        totals.dropped
+    );
+}
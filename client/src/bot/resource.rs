@@ -0,0 +1,203 @@
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+
+use bevy::prelude::{Resource, Vec2};
+use bevy::utils::HashMap;
+use clap::ValueEnum;
+use rand::Rng;
+
+use mangovillage_common::networking::client_packets::Credentials;
+
+use crate::networking::resource::ClientInfo;
+
+#[derive(Resource, Clone)]
+pub struct BotSettings {
+    pub count: usize,
+    pub server_addr: String,
+    /// Bots bind to this ip, each on its own port from `first_port` up
+    pub bind_ip: IpAddr,
+    pub first_port: u16,
+    pub username_prefix: String,
+    pub password: String,
+    /// Seconds between connecting each bot
+    pub spawn_interval: f64,
+    /// Seconds to run for before reporting
+    pub duration: f64,
+    /// Seconds to wait for the server to accept a bot
+    pub connect_timeout: f64,
+    pub pattern: MovePattern,
+    /// Seconds between changes of direction
+    pub turn_interval: f64,
+    /// Movement packets each bot sends per second
+    pub input_rate: u32,
+}
+
+impl BotSettings {
+    /// Connection details of the bot with this index.  Bots register their account if it doesn't exist yet.
+    pub fn client_info(&self, index: usize) -> ClientInfo {
+        ClientInfo {
+            client_addr: SocketAddr::new(self.bind_ip, self.first_port + index as u16).to_string(),
+            server_addr: self.server_addr.clone(),
+            credentials: Credentials { username: format!("{}{}", self.username_prefix, index), password: self.password.clone(), register: true },
+        }
+    }
+}
+
+/// How bots pick where to walk, like a player clicking around
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum MovePattern {
+    /// A random direction every turn
+    Random,
+    /// Turn 45 degrees every turn, walking in circles
+    Circle,
+    /// Walk back and forth along one line
+    Line,
+}
+
+impl MovePattern {
+    /// Direction to walk in after a turn
+    pub fn turn(&self, direction: Vec2, rng: &mut impl Rng) -> Vec2 {
+        match self {
+            MovePattern::Random => Vec2::from_angle(rng.gen_range(0.0..std::f32::consts::TAU)),
+            MovePattern::Circle => Vec2::from_angle(std::f32::consts::FRAC_PI_4).rotate(direction),
+            MovePattern::Line => -direction,
+        }
+    }
+}
+
+/// Measurements collected over the run
+#[derive(Resource, Default)]
+pub struct BotStats {
+    pub spawned: usize,
+    /// Local seconds to connect the next bot at
+    pub next_spawn: f64,
+    pub connected: usize,
+    /// Transport errors from bots that couldn't open a connection
+    pub failed: Vec<String>,
+    /// Reasons given by the server, with how many bots got each
+    pub rejected: HashMap<String, usize>,
+    /// Bots the server never answered
+    pub timed_out: usize,
+    /// Bots that stopped getting snapshots after joining
+    pub lost: usize,
+    /// Seconds from Connect to ConnectAck
+    pub connect_times: Vec<f64>,
+    /// Seconds from sending a Movement to getting a snapshot in which the server had processed it
+    pub input_latencies: Vec<f64>,
+    /// Snapshots per second each bot got while connected
+    pub snapshot_rates: Vec<f64>,
+    /// Round trip times in milliseconds the server measured, as it reported them in pings
+    pub rtts: Vec<f64>,
+}
+
+/// Summary of a set of measurements
+pub struct Distribution {
+    pub count: usize,
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    /// `None` if there are no values
+    pub fn of(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+        Some(Distribution {
+            count: sorted.len(),
+            min: sorted[0],
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+
+    pub fn scaled(&self, factor: f64) -> Self {
+        Distribution {
+            count: self.count,
+            min: self.min * factor,
+            mean: self.mean * factor,
+            p50: self.p50 * factor,
+            p95: self.p95 * factor,
+            p99: self.p99 * factor,
+            max: self.max * factor,
+        }
+    }
+}
+
+impl Display for Distribution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "min {:.1}, mean {:.1}, p50 {:.1}, p95 {:.1}, p99 {:.1}, max {:.1} ({} samples)",
+            self.min, self.mean, self.p50, self.p95, self.p99, self.max, self.count
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn distribution_of_nothing_is_none() {
+        assert!(Distribution::of(&[]).is_none());
+    }
+
+    #[test]
+    fn distribution_percentiles() {
+        // Shuffled, to check the values are sorted first
+        let values: Vec<f64> = (1..=100).map(|i| ((i * 37) % 100 + 1) as f64).collect();
+        let distribution = Distribution::of(&values).unwrap();
+        assert_eq!(distribution.count, 100);
+        assert_eq!((distribution.min, distribution.max), (1.0, 100.0));
+        assert_eq!(distribution.mean, 50.5);
+        assert_eq!((distribution.p50, distribution.p95, distribution.p99), (51.0, 95.0, 99.0));
+    }
+
+    #[test]
+    fn distribution_of_one_value() {
+        let distribution = Distribution::of(&[3.0]).unwrap();
+        assert_eq!(
+            (distribution.min, distribution.mean, distribution.p50, distribution.p95, distribution.p99, distribution.max),
+            (3.0, 3.0, 3.0, 3.0, 3.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn circle_turns_45_degrees() {
+        let turned = MovePattern::Circle.turn(Vec2::X, &mut StdRng::seed_from_u64(0));
+        assert!(turned.abs_diff_eq(Vec2::new(1.0, 1.0).normalize(), 1e-6));
+        let mut direction = Vec2::X;
+        for _ in 0..8 {
+            direction = MovePattern::Circle.turn(direction, &mut StdRng::seed_from_u64(0));
+        }
+        assert!(direction.abs_diff_eq(Vec2::X, 1e-5));
+    }
+
+    #[test]
+    fn line_turns_around() {
+        assert_eq!(MovePattern::Line.turn(Vec2::Y, &mut StdRng::seed_from_u64(0)), Vec2::NEG_Y);
+    }
+
+    #[test]
+    fn random_turns_to_unit_directions() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let direction = MovePattern::Random.turn(Vec2::X, &mut rng);
+            assert!((direction.length() - 1.0).abs() < 1e-5);
+        }
+    }
+}
@@ -10,6 +10,7 @@ use mangovillage_common::player::component::PlayerData;
 use crate::camera::component::PanOrbitCamera;
 use crate::camera::resource::{CameraSpeed, DebugCameraState, LockedCameraState, MeshVisibility};
 use crate::player::component::Me;
+use crate::settings::resource::ClientSettings;
 use crate::state::CameraState;
 
 mod component;
@@ -52,6 +53,7 @@ fn setup_camera(mut commands: Commands) {
 /// Toggle debug camera state
 fn toggle_camera(
     buttons: Res<Input<KeyCode>>,
+    settings: Res<ClientSettings>,
     camera_state: Res<State<CameraState>>,
     mut debug_camera_state: ResMut<DebugCameraState>,
     mut locked_camera_state: ResMut<LockedCameraState>,
@@ -61,7 +63,7 @@ fn toggle_camera(
     mut camera_query: Query<(&mut PanOrbitCamera, &mut Transform), With<Camera>>,
     //mut debug_render_context: ResMut<DebugRenderContext>,
 ) {
-    if buttons.just_pressed(KeyCode::F1) {
+    if buttons.just_pressed(settings.keybindings.toggle_camera) {
         let (mut pan_orbit, mut transform) = camera_query.single_mut();
         // debug camera state always matches the last camera transform
         debug_camera_state.transform = *transform;
@@ -90,7 +92,10 @@ fn update_locked_camera(
     me: Query<&Transform, (With<Me>, With<PlayerData>, Without<Camera>)>,
 ) {
     let (mut pan_orbit, mut transform) = camera_query.single_mut();
-    let me = me.single();
+    // Not spawned yet, e.g. while reconnecting
+    let Ok(me) = me.get_single() else {
+        return;
+    };
     // Keep pan orbit focus up to date so debug camera has correct orientation
     pan_orbit.focus = me.translation;
     transform.translation = me.translation;
@@ -101,16 +106,17 @@ fn update_locked_camera(
 /// Toggle visibility of meshes and debug render lines
 fn toggle_visibility(
     buttons: Res<Input<KeyCode>>,
+    settings: Res<ClientSettings>,
     mut mesh_query: Query<&mut Visibility, With<Handle<Scene>>>,
     mut mesh_vis: ResMut<MeshVisibility>,
     mut debug_render_context: ResMut<DebugRenderContext>,
 ) {
-    if buttons.just_pressed(KeyCode::F2) {
+    if buttons.just_pressed(settings.keybindings.toggle_meshes) {
         mesh_vis.visible = !mesh_vis.visible;
         set_mesh_visibilities(&mut mesh_query, mesh_vis.visible);
     }
 
-    if buttons.just_pressed(KeyCode::F3) {
+    if buttons.just_pressed(settings.keybindings.toggle_debug_render) {
         debug_render_context.enabled = !debug_render_context.enabled;
     }
 }
@@ -124,14 +130,14 @@ fn set_mesh_visibilities(mesh_query: &mut Query<&mut Visibility, With<Handle<Sce
 }
 
 /// Update camera zoom speed
-fn update_zoom_speed(mut camera_state: ResMut<DebugCameraState>, buttons: Res<Input<KeyCode>>) {
+fn update_zoom_speed(mut camera_state: ResMut<DebugCameraState>, buttons: Res<Input<KeyCode>>, settings: Res<ClientSettings>) {
     let mut changed = false;
     let camera_speed = &mut camera_state.camera_speed;
-    if buttons.pressed(KeyCode::BracketLeft) {
+    if buttons.pressed(settings.keybindings.camera_slower) {
         camera_speed.zoom_speed = f32::max(0.01, camera_speed.zoom_speed - 0.1);
         camera_speed.move_speed = f32::max(0.0001, camera_speed.move_speed - 0.0001);
         changed = true;
-    } else if buttons.pressed(KeyCode::BracketRight) {
+    } else if buttons.pressed(settings.keybindings.camera_faster) {
         camera_speed.zoom_speed += 0.1;
         camera_speed.move_speed += 0.0001;
         changed = true;
@@ -143,6 +149,7 @@ fn update_zoom_speed(mut camera_state: ResMut<DebugCameraState>, buttons: Res<In
 
 fn movement(
     buttons: Res<Input<KeyCode>>,
+    settings: Res<ClientSettings>,
     camera_state: Res<DebugCameraState>,
     mut camera_query: Query<(&mut PanOrbitCamera, &mut Transform), With<Camera>>,
 ) {
@@ -151,22 +158,23 @@ fn movement(
     let mut side = 0.0;
 
     let cam_speed = &camera_state.camera_speed;
-    if buttons.pressed(KeyCode::W) {
+    let bindings = &settings.keybindings;
+    if buttons.pressed(bindings.camera_forward) {
         forward -= cam_speed.move_speed;
     }
-    if buttons.pressed(KeyCode::S) {
+    if buttons.pressed(bindings.camera_back) {
         forward += cam_speed.move_speed;
     }
-    if buttons.pressed(KeyCode::D) {
+    if buttons.pressed(bindings.camera_right) {
         side += cam_speed.move_speed;
     }
-    if buttons.pressed(KeyCode::A) {
+    if buttons.pressed(bindings.camera_left) {
         side -= cam_speed.move_speed;
     }
-    if buttons.pressed(KeyCode::E) {
+    if buttons.pressed(bindings.camera_up) {
         up += cam_speed.move_speed;
     }
-    if buttons.pressed(KeyCode::Q) {
+    if buttons.pressed(bindings.camera_down) {
         up -= cam_speed.move_speed;
     }
 
@@ -207,9 +215,10 @@ fn pan(
     windows: Query<&Window, With<PrimaryWindow>>,
     mut camera_query: Query<(&mut PanOrbitCamera, &mut Transform, &Projection), With<Camera>>,
     buttons: Res<Input<MouseButton>>,
+    settings: Res<ClientSettings>,
     mut motion_evr: EventReader<MouseMotion>,
 ) {
-    if buttons.pressed(MouseButton::Middle) {
+    if buttons.pressed(settings.keybindings.camera_pan) {
         let mut pan = motion_evr.iter().map(|ev| ev.delta).sum::<Vec2>() * PAN_SPEED;
 
         if pan.length_squared() > 0.0 {
@@ -238,9 +247,10 @@ fn orbit(
     windows: Query<&Window, With<PrimaryWindow>>,
     mut camera_query: Query<(&PanOrbitCamera, &mut Transform, &Projection), With<Camera>>,
     buttons: Res<Input<MouseButton>>,
+    settings: Res<ClientSettings>,
     mut motion_evr: EventReader<MouseMotion>,
 ) {
-    if buttons.pressed(MouseButton::Left) {
+    if buttons.pressed(settings.keybindings.camera_orbit) {
         let mut rotation_move = motion_evr.iter().map(|ev| ev.delta).sum::<Vec2>() * ORBIT_SPEED;
 
         if rotation_move.length_squared() > 0.0 {
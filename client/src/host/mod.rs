@@ -0,0 +1,110 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use bevy::app::{AppExit, ScheduleRunnerPlugin};
+use bevy::prelude::*;
+use bevy::window::WindowCloseRequested;
+
+use mangovillage_server::config::ServerSettings;
+use mangovillage_server::headless::HeadlessPlugins;
+use mangovillage_server::state::ServerState;
+use mangovillage_server::GameServerPlugin;
+
+use crate::host::resource::HostedServer;
+use crate::settings;
+use crate::settings::resource::ClientSettings;
+use crate::settings::HostMode;
+
+pub mod resource;
+
+/// How long to wait for the hosted server to load its world
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Stops the hosted server when the game exits, so it can save characters first
+pub struct HostPlugin;
+impl Plugin for HostPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Last, stop_server.run_if(resource_exists::<HostedServer>()));
+    }
+}
+
+/// Run a server on a background thread, returning once it accepts connections
+pub fn start_server(client_settings: &ClientSettings, mode: &HostMode) -> Result<HostedServer, String> {
+    let mut settings = ServerSettings::read(&settings::host_server_config(client_settings), true)?;
+    let mut bind_address: SocketAddr = client_settings.host.bind_address.parse().map_err(|e| format!("invalid host.bind_address: {}", e))?;
+    if let HostMode::Offline = mode {
+        bind_address.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+    settings.network.bind_address = bind_address.to_string();
+    // Relative paths in the server config would otherwise depend on where the game was started from
+    let data_dir = settings::host_data_dir();
+    settings.accounts.path = data_dir.join(&settings.accounts.path);
+    settings.persistence.characters_dir = data_dir.join(&settings.persistence.characters_dir);
+    settings.validate().map_err(|errors| errors.join(", "))?;
+    let connect_address = connect_address(&settings.network.bind_address)?;
+    let frame_time = Duration::from_secs_f64(1.0 / settings.simulation.tick_rate as f64);
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let (ready_sender, ready) = mpsc::channel();
+    let thread = thread::Builder::new()
+        .name("server".to_string())
+        .spawn({
+            let shutdown = shutdown.clone();
+            // Systems have to be Sync
+            let ready_sender = Mutex::new(ready_sender);
+            move || {
+                App::new()
+                    // The client already set up logging
+                    .add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(frame_time)))
+                    .add_plugins(HeadlessPlugins)
+                    .add_plugins(GameServerPlugin { settings })
+                    .add_systems(OnEnter(ServerState::Running), move || {
+                        let _ = ready_sender.lock().unwrap().send(());
+                    })
+                    .add_systems(First, move |mut exit: EventWriter<AppExit>| {
+                        if shutdown.load(Ordering::Relaxed) {
+                            exit.send(AppExit);
+                        }
+                    })
+                    .run();
+                info!("[server] Hosted server stopped");
+            }
+        })
+        .map_err(|e| e.to_string())?;
+
+    match ready.recv_timeout(STARTUP_TIMEOUT) {
+        Ok(()) => Ok(HostedServer { connect_address, shutdown, thread: Some(thread) }),
+        // Sender was dropped, so the server thread panicked
+        Err(RecvTimeoutError::Disconnected) => Err("server stopped while starting, see the log for why".to_string()),
+        Err(RecvTimeoutError::Timeout) => Err(format!("server did not start within {:?}", STARTUP_TIMEOUT)),
+    }
+}
+
+/// Address to reach a server bound to `bind_address` from this machine
+fn connect_address(bind_address: &str) -> Result<String, String> {
+    let mut addr: SocketAddr = bind_address.parse().map_err(|_| format!("{:?} is not an ip:port address", bind_address))?;
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    Ok(addr.to_string())
+}
+
+fn stop_server(mut hosted: ResMut<HostedServer>, exit: EventReader<AppExit>, close_window: EventReader<WindowCloseRequested>) {
+    if exit.is_empty() && close_window.is_empty() {
+        return;
+    }
+    if let Some(thread) = hosted.thread.take() {
+        info!("[client] Stopping hosted server");
+        hosted.shutdown.store(true, Ordering::Relaxed);
+        if thread.join().is_err() {
+            error!("[client] Hosted server panicked while stopping");
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use bevy::prelude::Resource;
+
+/// Server running in this process, for hosting a game
+#[derive(Resource)]
+pub struct HostedServer {
+    /// Address we connect to it on
+    pub connect_address: String,
+    /// Tells the server thread to exit
+    pub shutdown: Arc<AtomicBool>,
+    pub thread: Option<JoinHandle<()>>,
+}
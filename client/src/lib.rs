@@ -0,0 +1,12 @@
+pub mod bot;
+pub mod camera;
+pub mod component;
+pub mod host;
+pub mod lighting;
+pub mod networking;
+pub mod physics;
+pub mod player;
+pub mod replication;
+pub mod settings;
+pub mod state;
+pub mod world;
@@ -1,34 +1,22 @@
-mod camera;
-mod component;
-mod lighting;
-mod networking;
-mod physics;
-mod player;
-mod state;
-mod world;
-
-use crate::state::ClientState;
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
-use bevy::log::{Level, LogPlugin};
+use bevy::log::LogPlugin;
 use bevy::prelude::*;
-use bevy::window::{PresentMode, WindowResolution};
+use bevy::window::WindowResolution;
 use bevy_embedded_assets::EmbeddedAssetPlugin;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
-use std::env;
+use mangovillage_common::networking::diagnostics::NetworkDiagnosticsPlugin;
+use mangovillage_client::state::ClientState;
+use mangovillage_client::{camera, host, lighting, networking, physics, player, replication, settings, world};
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let default_client_addr = "0.0.0.0:5001".to_string();
-    let default_server_addr = "127.0.0.1:28154".to_string();
-    let client_addr = args.get(1).unwrap_or(&default_client_addr);
-    let server_addr = args.get(2).unwrap_or(&default_server_addr);
+    let (settings, launch) = settings::load();
     println!("[client] Initializing client");
 
     // Set log level manually
-    let default_plugins =
-        DefaultPlugins.build().set(LogPlugin { filter: "info,mangovillage_client=debug,durian=info,wgpu=error".to_string(), level: Level::INFO });
+    let default_plugins = DefaultPlugins.build().set(LogPlugin { filter: settings.log.filter.clone(), level: settings.log_level() });
 
-    App::new()
+    let mut app = App::new();
+    app
         // This sets image filtering to nearest
         // This is done to prevent textures with low resolution (e.g. pixel art) from being blurred
         // by linear filtering.
@@ -36,23 +24,49 @@ fn main() {
             WindowPlugin {
                 primary_window: Some(Window {
                     title: "Mango Village".to_string(),
-                    resolution: WindowResolution::new(1280.0, 720.0),
+                    resolution: WindowResolution::new(settings.window.width, settings.window.height),
                     position: WindowPosition::Centered(MonitorSelection::Primary),
-                    //present_mode: PresentMode::Immediate,
+                    present_mode: settings.window.present_mode,
                     ..default()
                 }),
                 ..default()
             }
-        }))
-        .add_state::<ClientState>()
-        .add_plugins((
-            networking::ClientPlugin { client_addr: client_addr.clone(), server_addr: server_addr.clone() },
-            world::WorldPlugin,
-            physics::PhysicsPlugin,
-            lighting::LightingPlugin,
-            camera::CameraPlugin,
-            player::PlayerPlugin,
-        ))
-        .add_plugins((LogDiagnosticsPlugin::default(), FrameTimeDiagnosticsPlugin::default(), WorldInspectorPlugin::default()))
-        .run();
+        }));
+
+    // Started after logging is set up so its logs aren't lost
+    let mut server_addr = settings.network.server_address.clone();
+    if let Some(mode) = &launch.host {
+        let hosted = host::start_server(&settings, mode).unwrap_or_else(|e| {
+            eprintln!("[client] Could not host a game: {}", e);
+            std::process::exit(1);
+        });
+        info!("[client] Hosting a game, joining it on {}", hosted.connect_address);
+        server_addr = hosted.connect_address.clone();
+        app.insert_resource(hosted);
+    }
+
+    app.add_state::<ClientState>().add_plugins((
+        networking::ClientPlugin {
+            client_addr: settings.network.client_address.clone(),
+            server_addr,
+            credentials: launch.credentials,
+            conditions: launch.network_simulation.conditions(),
+        },
+        settings::SettingsPlugin { path: launch.settings_path, network_simulation: launch.network_simulation },
+        host::HostPlugin,
+        world::WorldPlugin,
+        physics::PhysicsPlugin,
+        lighting::LightingPlugin,
+        camera::CameraPlugin,
+        player::PlayerPlugin,
+        replication::ReplicationPlugin,
+        NetworkDiagnosticsPlugin,
+    ));
+    if settings.debug.diagnostics {
+        app.add_plugins((LogDiagnosticsPlugin::default(), FrameTimeDiagnosticsPlugin::default()));
+    }
+    if settings.debug.inspector {
+        app.add_plugins(WorldInspectorPlugin::default());
+    }
+    app.insert_resource(settings).run();
 }
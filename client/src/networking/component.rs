@@ -0,0 +1,5 @@
+use bevy::prelude::Component;
+
+/// Text shown while reconnecting
+#[derive(Component)]
+pub struct ReconnectingText;
@@ -1,75 +1,271 @@
+pub mod component;
 pub mod resource;
 
-use crate::networking::resource::{ClientInfo, ClientPacketManager};
-use crate::player::resource::ClientId;
+use crate::networking::component::ReconnectingText;
+use crate::networking::resource::{ClientInfo, ClientPacketManager, ConnectRejected, Connector, ReconnectAttempts, ReconnectSettings, Session};
+use crate::player::resource::{ClientId, ServerClock};
 use crate::state::ClientState;
 use bevy::app::AppExit;
 use bevy::prelude::*;
 use bevy::window::WindowCloseRequested;
-use durian::{register_receive, register_send, ClientConfig, PacketManager};
-use mangovillage_common::networking::client_packets::{Connect, Disconnect, Movement};
+use bevy_rapier3d::prelude::{RapierConfiguration, TimestepMode};
+use durian::ClientConfig;
+use mangovillage_common::networking::client_packets::{Connect, Credentials, Disconnect, Pong};
+use mangovillage_common::networking::diagnostics::resource::NetworkStats;
+use mangovillage_common::networking::registry::SCHEMA_FINGERPRINT;
 use mangovillage_common::networking::server_packets::{
-    ConnectAck, ConnectAckPacketBuilder, Players, PlayersPacketBuilder, SpawnScene, SpawnScenePacketBuilder,
+    ConnectAck, ConnectAckPacketBuilder, ConnectReject, ConnectRejectPacketBuilder, Ping, PingPacketBuilder,
 };
-use mangovillage_common::util;
+use mangovillage_common::networking::transport::{
+    insert_network_conditions, DurianTransport, LinkConditions, MeteredTransport, NetworkConditions, SimulatedTransport, Transport, TransportError,
+};
+use mangovillage_common::networking::{BUILD_HASH, PROTOCOL_VERSION};
 use std::time::Duration;
 
 pub struct ClientPlugin {
     pub client_addr: String,
     pub server_addr: String,
+    pub credentials: Credentials,
+    /// Simulated on the connection to the server, see [`insert_network_conditions`]
+    pub conditions: LinkConditions,
 }
 
 impl Plugin for ClientPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(ClientInfo { client_addr: self.client_addr.clone(), server_addr: self.server_addr.clone() })
+        if !app.world.contains_resource::<Connector>() {
+            app.insert_resource(Connector(Box::new(connect_durian)));
+        }
+        insert_network_conditions(app, &self.conditions, true);
+        app.init_resource::<NetworkStats>()
+            .insert_resource(ClientInfo {
+            client_addr: self.client_addr.clone(),
+            server_addr: self.server_addr.clone(),
+            credentials: self.credentials.clone(),
+        })
+            .init_resource::<Session>()
+            .init_resource::<ReconnectSettings>()
+            .init_resource::<ReconnectAttempts>()
             .add_systems(Startup, init_client)
             .add_systems(Update, transition_running.run_if(in_state(ClientState::JoiningServer)))
-            .add_systems(Update, on_app_exit);
+            .add_systems(
+                Update,
+                detect_connection_loss.run_if(in_state(ClientState::Running).or_else(in_state(ClientState::JoiningServer))),
+            )
+            .add_systems(Update, answer_pings.run_if(resource_exists::<ClientPacketManager>().and_then(not(in_state(ClientState::Rejected)))))
+            .add_systems(OnEnter(ClientState::Reconnecting), start_reconnecting)
+            .add_systems(Update, reconnect.run_if(in_state(ClientState::Reconnecting)))
+            .add_systems(OnExit(ClientState::Reconnecting), stop_reconnecting)
+            .add_systems(OnEnter(ClientState::Rejected), show_rejection)
+            // Already disconnected if rejected
+            .add_systems(Update, on_app_exit.run_if(not(in_state(ClientState::Rejected))));
     }
 }
 
-fn init_client(mut commands: Commands, client_info: Res<ClientInfo>) {
-    let mut manager = PacketManager::new();
-    // register packets client-side
-    let receives = util::validate_register_results(
-        true,
-        register_receive!(manager, (ConnectAck, ConnectAckPacketBuilder), (SpawnScene, SpawnScenePacketBuilder), (Players, PlayersPacketBuilder)),
-    );
-    let sends = util::validate_register_results(true, register_send!(manager, Connect, Disconnect, Movement));
-    // TODO: better error handling
-    if !receives {
-        panic!("Failed to register all receive packets");
-    }
-    if !sends {
-        panic!("Failed to register all send packets");
+fn init_client(
+    mut commands: Commands,
+    client_info: Res<ClientInfo>,
+    connector: Res<Connector>,
+    conditions: Res<NetworkConditions>,
+    stats: Res<NetworkStats>,
+    mut client_state: ResMut<NextState<ClientState>>,
+) {
+    match connect(&client_info, None, &connector, &conditions, &stats) {
+        Ok(manager) => {
+            info!("[client] Initialized client");
+            commands.insert_resource(ClientPacketManager { manager });
+        }
+        Err(e) => {
+            // e.g. the port is in use or the server isn't up yet, so keep trying with backoff
+            error!("[client] Could not connect to {}: {}", client_info.server_addr, e);
+            info!("Transitioning state to Reconnecting");
+            client_state.set(ClientState::Reconnecting);
+        }
     }
+}
+
+/// Connect to the server and log in, resuming our session if we have a token
+pub fn connect(
+    client_info: &ClientInfo,
+    session_token: Option<u64>,
+    connector: &Connector,
+    conditions: &NetworkConditions,
+    stats: &NetworkStats,
+) -> Result<Box<dyn Transport>, String> {
+    let transport = (connector.0)(client_info).map_err(|e| e.to_string())?;
+    let transport = Box::new(SimulatedTransport::new(transport, conditions.clone(), rand::random()));
+    let mut manager: Box<dyn Transport> = Box::new(MeteredTransport::new(transport, stats.clone()));
+    manager
+        .send(Connect {
+            protocol_version: PROTOCOL_VERSION,
+            build_hash: BUILD_HASH.to_string(),
+            schema_fingerprint: SCHEMA_FINGERPRINT,
+            credentials: client_info.credentials.clone(),
+            session_token,
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(manager)
+}
+
+pub fn connect_durian(client_info: &ClientInfo) -> Result<Box<dyn Transport>, TransportError> {
     let mut client_config = ClientConfig::new(client_info.client_addr.clone(), client_info.server_addr.clone(), 3, 3);
     // Server sends keep alive packets
     client_config.with_keep_alive_interval(Duration::from_secs(30));
-    manager.init_client(client_config).unwrap();
-
-    info!("[client] Initialized client");
-    manager.send(Connect).unwrap();
-    commands.insert_resource(ClientPacketManager { manager });
+    Ok(Box::new(DurianTransport::client(client_config)?))
 }
 
 /// Waits for ConnectAck from server and goes to Running state initially, and switches states when we get commands from server
-fn transition_running(mut manager: ResMut<ClientPacketManager>, mut client_state: ResMut<NextState<ClientState>>, mut commands: Commands) {
+///
+/// If the server rejects us instead, we disconnect and move to [`ClientState::Rejected`].
+#[allow(clippy::too_many_arguments)]
+fn transition_running(
+    mut manager: ResMut<ClientPacketManager>,
+    mut client_state: ResMut<NextState<ClientState>>,
+    mut commands: Commands,
+    mut server_clock: ResMut<ServerClock>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+    mut session: ResMut<Session>,
+    mut reconnect_attempts: ResMut<ReconnectAttempts>,
+    time: Res<Time>,
+) {
+    let rejects = manager.received::<ConnectReject, ConnectRejectPacketBuilder>(false).unwrap();
+    if let Some(mut rejects) = rejects {
+        let reject = rejects.swap_remove(rejects.len() - 1);
+        error!("[client] Server rejected connection: {}", reject.reason);
+        manager.send(Disconnect).unwrap();
+        commands.insert_resource(ConnectRejected { reason: reject.reason });
+        info!("Transitioning state to Rejected");
+        client_state.set(ClientState::Rejected);
+        return;
+    }
+
     let acks = manager.received::<ConnectAck, ConnectAckPacketBuilder>(false).unwrap();
     // Should only be 1 packet
     if let Some(acks) = acks {
         let connect_ack = acks.last().unwrap();
-        info!("Received ConnectAck from server, client_id={}", connect_ack.id);
+        info!("Received ConnectAck from server, client_id={}, tick_rate={}", connect_ack.id, connect_ack.tick_rate);
         commands.insert_resource(ClientId(connect_ack.id));
+        session.token = Some(connect_ack.session_token);
+        session.connected_at = time.elapsed_seconds_f64();
+        reconnect_attempts.attempts = 0;
+        // Run prediction and physics at the server's tick rate
+        let tick_seconds = 1.0 / connect_ack.tick_rate as f32;
+        commands.insert_resource(FixedTime::new_from_secs(tick_seconds));
+        rapier_config.timestep_mode = TimestepMode::Fixed { dt: tick_seconds, substeps: 1 };
+        server_clock.set_tick_rate(connect_ack.tick_rate);
+        commands.insert_resource(connect_ack.movement);
         info!("Transitioning state to LoadingLevel");
         client_state.set(ClientState::LoadingLevel);
     }
 }
 
+/// The server sends snapshots at its send rate, so if they stop coming the connection is gone.  Also catches the server
+/// never answering a reconnect.
+fn detect_connection_loss(
+    server_clock: Res<ServerClock>,
+    session: Res<Session>,
+    settings: Res<ReconnectSettings>,
+    time: Res<Time>,
+    mut client_state: ResMut<NextState<ClientState>>,
+) {
+    // Nothing to resume if we never joined
+    if session.token.is_none() {
+        return;
+    }
+    let last_heard = server_clock.last_heard().map_or(session.connected_at, |last_heard| last_heard.max(session.connected_at));
+    if time.elapsed_seconds_f64() - last_heard > settings.timeout {
+        warn!("[client] Nothing from server in {}s, connection lost", settings.timeout);
+        info!("Transitioning state to Reconnecting");
+        client_state.set(ClientState::Reconnecting);
+    }
+}
+
+fn start_reconnecting(mut commands: Commands, mut reconnect_attempts: ResMut<ReconnectAttempts>, time: Res<Time>) {
+    // Free up our address for the new connection
+    commands.remove_resource::<ClientPacketManager>();
+    reconnect_attempts.attempts = 0;
+    reconnect_attempts.next_attempt = time.elapsed_seconds_f64();
+    commands.spawn((
+        TextBundle::from_section("Connection lost, reconnecting...", TextStyle { font_size: 32.0, color: Color::WHITE, ..default() })
+            .with_style(Style { position_type: PositionType::Absolute, top: Val::Px(20.0), left: Val::Px(20.0), ..default() }),
+        ReconnectingText,
+    ));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn reconnect(
+    mut commands: Commands,
+    client_info: Res<ClientInfo>,
+    connector: Res<Connector>,
+    conditions: Res<NetworkConditions>,
+    stats: Res<NetworkStats>,
+    mut session: ResMut<Session>,
+    settings: Res<ReconnectSettings>,
+    mut reconnect_attempts: ResMut<ReconnectAttempts>,
+    time: Res<Time>,
+    mut client_state: ResMut<NextState<ClientState>>,
+) {
+    let now = time.elapsed_seconds_f64();
+    if now < reconnect_attempts.next_attempt {
+        return;
+    }
+    reconnect_attempts.attempts += 1;
+    info!("[client] Reconnecting to {}, attempt {}", client_info.server_addr, reconnect_attempts.attempts);
+    match connect(&client_info, session.token, &connector, &conditions, &stats) {
+        Ok(manager) => {
+            commands.insert_resource(ClientPacketManager { manager });
+            // Give the server until the timeout to answer
+            session.connected_at = now;
+            info!("Transitioning state to JoiningServer");
+            client_state.set(ClientState::JoiningServer);
+        }
+        Err(e) => {
+            let delay = reconnect_attempts.backoff(now, &settings);
+            warn!("[client] Could not reconnect: {}.  Retrying in {}s", e, delay);
+        }
+    }
+}
+
+/// Answer the server's pings so it can measure our round trip time, and keep the one it measured last
+fn answer_pings(mut manager: ResMut<ClientPacketManager>, stats: Res<NetworkStats>) {
+    let Some(pings) = manager.received::<Ping, PingPacketBuilder>(false).unwrap() else {
+        return;
+    };
+    for ping in pings {
+        if let Err(e) = manager.send(Pong { id: ping.id }) {
+            debug!("[client] Could not answer ping {}.  Error: {}", ping.id, e);
+        }
+        if let Some(rtt_ms) = ping.rtt_ms {
+            stats.record_rtt(0, Duration::from_secs_f32(rtt_ms / 1000.0));
+        }
+    }
+}
+
+fn stop_reconnecting(mut commands: Commands, texts: Query<Entity, With<ReconnectingText>>) {
+    for entity in texts.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Show why the server rejected us
+fn show_rejection(mut commands: Commands, rejected: Res<ConnectRejected>) {
+    commands.spawn(
+        TextBundle::from_section(
+            format!("Could not join server\n{}", rejected.reason),
+            TextStyle { font_size: 32.0, color: Color::WHITE, ..default() },
+        )
+        .with_style(Style { position_type: PositionType::Absolute, top: Val::Px(20.0), left: Val::Px(20.0), ..default() }),
+    );
+}
+
 // Send disconnect packet to server to disconnect gracefully rather than wait for timeout.
-fn on_app_exit(mut manager: ResMut<ClientPacketManager>, exit: EventReader<AppExit>, close_window: EventReader<WindowCloseRequested>) {
+fn on_app_exit(manager: Option<ResMut<ClientPacketManager>>, exit: EventReader<AppExit>, close_window: EventReader<WindowCloseRequested>) {
     if !exit.is_empty() || !close_window.is_empty() {
         info!("[client] Exiting game");
-        manager.send(Disconnect).unwrap();
+        // No connection while reconnecting
+        if let Some(mut manager) = manager {
+            if let Err(e) = manager.send(Disconnect) {
+                warn!("[client] Could not send Disconnect: {:?}", e);
+            }
+        }
     }
 }
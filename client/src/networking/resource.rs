@@ -1,20 +1,72 @@
 use std::ops::{Deref, DerefMut};
 use bevy::prelude::Resource;
-use durian::PacketManager;
+use derivative::Derivative;
+use mangovillage_common::networking::client_packets::Credentials;
+use mangovillage_common::networking::server_packets::RejectReason;
+use mangovillage_common::networking::transport::{Transport, TransportError};
 
 #[derive(Resource)]
 pub struct ClientInfo {
     pub client_addr: String,
-    pub server_addr: String
+    pub server_addr: String,
+    pub credentials: Credentials,
 }
 
+/// Our session with the server, kept across reconnects.  Logging in to the same account again with the token resumes it.
+#[derive(Resource, Default)]
+pub struct Session {
+    /// From the server's last ConnectAck, if it has accepted us before, so there is a session to resume
+    pub token: Option<u64>,
+    /// Local seconds we were last acknowledged by the server
+    pub connected_at: f64,
+}
+
+#[derive(Resource, Derivative)]
+#[derivative(Default)]
+pub struct ReconnectSettings {
+    /// Seconds without a snapshot from the server before we consider the connection lost
+    #[derivative(Default(value = "5.0"))]
+    pub timeout: f64,
+    /// Seconds to wait after the first failed attempt, doubling with each failed attempt after
+    #[derivative(Default(value = "1.0"))]
+    pub initial_backoff: f64,
+    #[derivative(Default(value = "30.0"))]
+    pub max_backoff: f64,
+}
+
+#[derive(Resource, Default)]
+pub struct ReconnectAttempts {
+    pub attempts: u32,
+    /// Local seconds to make the next attempt at
+    pub next_attempt: f64,
+}
+
+impl ReconnectAttempts {
+    /// Schedule the next attempt after a failed one
+    pub fn backoff(&mut self, now: f64, settings: &ReconnectSettings) -> f64 {
+        let delay = (settings.initial_backoff * 2f64.powi(self.attempts.saturating_sub(1) as i32)).min(settings.max_backoff);
+        self.next_attempt = now + delay;
+        delay
+    }
+}
+
+/// Why the server refused to let us join
+#[derive(Resource)]
+pub struct ConnectRejected {
+    pub reason: RejectReason,
+}
+
+/// Opens a transport to the server.  Durian unless one was inserted before the [`ClientPlugin`](crate::networking::ClientPlugin).
+#[derive(Resource)]
+pub struct Connector(pub Box<dyn Fn(&ClientInfo) -> Result<Box<dyn Transport>, TransportError> + Send + Sync>);
+
 #[derive(Resource)]
 pub struct ClientPacketManager {
-    pub manager: PacketManager
+    pub manager: Box<dyn Transport>,
 }
 
 impl Deref for ClientPacketManager {
-    type Target = PacketManager;
+    type Target = dyn Transport;
 
     fn deref(&self) -> &Self::Target {
         &self.manager
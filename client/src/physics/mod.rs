@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use bevy_rapier3d::plugin::PhysicsSet;
 use bevy_rapier3d::prelude::{NoUserData, RapierDebugRenderPlugin, RapierPhysicsPlugin};
 
 use mangovillage_common::physics;
@@ -8,8 +9,22 @@ use crate::state::ClientState;
 pub struct PhysicsPlugin;
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((RapierPhysicsPlugin::<NoUserData>::default(), RapierDebugRenderPlugin { enabled: true, ..default() }))
-            .add_systems(Update, load_colliders.run_if(in_state(ClientState::LoadingPhysics)));
+        // Step physics at the server's tick rate so client-side prediction matches the server's simulation.  The timestep
+        // is set once we know the server's tick rate from ConnectAck.
+        app.add_plugins((
+            RapierPhysicsPlugin::<NoUserData>::default().with_default_system_setup(false),
+            RapierDebugRenderPlugin { enabled: true, ..default() },
+        ))
+        .configure_sets(FixedUpdate, (PhysicsSet::SyncBackend, PhysicsSet::StepSimulation, PhysicsSet::Writeback).chain())
+        .add_systems(
+            FixedUpdate,
+            (
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::SyncBackend).in_set(PhysicsSet::SyncBackend),
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::StepSimulation).in_set(PhysicsSet::StepSimulation),
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::Writeback).in_set(PhysicsSet::Writeback),
+            ),
+        )
+        .add_systems(Update, load_colliders.run_if(in_state(ClientState::LoadingPhysics)));
     }
 }
 
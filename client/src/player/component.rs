@@ -1,5 +1,100 @@
-use bevy::prelude::Component;
+use std::collections::VecDeque;
+
+use bevy::math::{Quat, Vec3};
+use bevy::prelude::{Component, Timer};
+
+use mangovillage_common::networking::server_packets::Player;
+use mangovillage_common::resource::LevelBounds;
 
 /// To mark entities that belong to the current client
 #[derive(Component)]
 pub struct Me;
+
+/// On screen notice about a player leaving, despawned once the timer finishes
+#[derive(Component)]
+pub struct LeaveNotice(pub Timer);
+
+/// A remote player's state at a server tick
+#[derive(Clone, Copy)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: f32,
+}
+
+impl Snapshot {
+    pub fn new(tick: u32, player: &Player, bounds: &LevelBounds) -> Self {
+        Snapshot {
+            tick,
+            translation: player.transform.translation(bounds),
+            rotation: player.transform.rotation(),
+            scale: player.transform.scale(),
+        }
+    }
+}
+
+/// Recent snapshots of a remote player, so it can be rendered slightly in the past by interpolating between them
+#[derive(Component, Default)]
+pub struct SnapshotBuffer {
+    /// Ordered by tick, oldest first
+    pub snapshots: VecDeque<Snapshot>,
+}
+
+impl SnapshotBuffer {
+    pub fn push(&mut self, snapshot: Snapshot, max_snapshots: usize) {
+        // Drop duplicated or out of order snapshots
+        if matches!(self.snapshots.back(), Some(last) if last.tick >= snapshot.tick) {
+            return;
+        }
+        if self.snapshots.len() >= max_snapshots {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Sample the player's state at a fractional tick.  Interpolates between the surrounding snapshots, or extrapolates
+    /// from the two newest snapshots by at most `max_extrapolation_ticks` past the newest one.
+    pub fn sample(&self, tick: f32, max_extrapolation_ticks: f32) -> Option<Snapshot> {
+        let newest = *self.snapshots.back()?;
+        let oldest = *self.snapshots.front()?;
+        if tick <= oldest.tick as f32 {
+            return Some(oldest);
+        }
+
+        if tick >= newest.tick as f32 {
+            // Not enough history to extrapolate from
+            if self.snapshots.len() < 2 {
+                return Some(newest);
+            }
+            let previous = self.snapshots[self.snapshots.len() - 2];
+            let ahead = (tick - newest.tick as f32).min(max_extrapolation_ticks);
+            let t = 1.0 + ahead / (newest.tick - previous.tick) as f32;
+            return Some(lerp_snapshot(&previous, &newest, t));
+        }
+
+        // Find the pair of snapshots surrounding the tick
+        let index = self.snapshots.iter().position(|snapshot| snapshot.tick as f32 > tick)?;
+        let from = self.snapshots[index - 1];
+        let to = self.snapshots[index];
+        let t = (tick - from.tick as f32) / (to.tick - from.tick) as f32;
+        Some(lerp_snapshot(&from, &to, t))
+    }
+
+    /// Drop snapshots that are too old to be interpolated from at `tick`, keeping the newest one before it
+    pub fn prune(&mut self, tick: f32) {
+        while self.snapshots.len() > 2 && self.snapshots[1].tick as f32 <= tick {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+fn lerp_snapshot(from: &Snapshot, to: &Snapshot, t: f32) -> Snapshot {
+    Snapshot {
+        tick: if t < 1.0 { from.tick } else { to.tick },
+        translation: from.translation.lerp(to.translation, t),
+        // Don't keep spinning when extrapolating
+        rotation: from.rotation.slerp(to.rotation, t.min(1.0)),
+        scale: from.scale + (to.scale - from.scale) * t.min(1.0),
+    }
+}
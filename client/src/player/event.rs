@@ -0,0 +1,10 @@
+use bevy::prelude::Event;
+
+use mangovillage_common::networking::server_packets::LeaveReason;
+
+/// A player was despawned because the server told us they left
+#[derive(Event)]
+pub struct PlayerLeftEvent {
+    pub id: u32,
+    pub reason: LeaveReason,
+}
@@ -1,51 +1,268 @@
 use bevy::prelude::*;
-use bevy::utils::HashMap;
+use bevy::utils::{HashMap, HashSet};
 use bevy::window::PrimaryWindow;
+use bevy_rapier3d::control::KinematicCharacterControllerOutput;
+use bevy_rapier3d::plugin::PhysicsSet;
+use bevy_rapier3d::prelude::{Collider, KinematicCharacterController, RapierContext};
 
-use mangovillage_common::networking::client_packets::Movement;
-use mangovillage_common::networking::server_packets::Player;
-use mangovillage_common::networking::server_packets::{Players, PlayersPacketBuilder};
+use mangovillage_common::component::MoveTarget;
+use mangovillage_common::networking::client_packets::{Movement, SnapshotAck};
+use mangovillage_common::networking::server_packets::{LeaveReason, Player, Players, PlayersLeft, PlayersLeftPacketBuilder, PlayersPacketBuilder};
 use mangovillage_common::player;
 use mangovillage_common::player::component::PlayerData;
-use mangovillage_common::player::{set_player_rotation, PLAYER_MODEL_HANDLE_IDS};
-use player::get_player_collider;
+use mangovillage_common::player::movement as player_movement;
+use mangovillage_common::player::movement::MovementSettings;
+use mangovillage_common::player::PLAYER_MODEL_HANDLE_IDS;
+use mangovillage_common::resource::{LevelBounds, LevelInfo};
+use player::{get_player_collider, get_player_collider_bundle};
 
 use crate::component::Animations;
 use crate::networking::resource::ClientPacketManager;
-use crate::player::component::Me;
-use crate::player::resource::ClientId;
+use crate::player::component::{LeaveNotice, Me, Snapshot, SnapshotBuffer};
+use crate::player::event::PlayerLeftEvent;
+use crate::player::resource::{ClientId, InterpolationSettings, PendingInputs, ReceivedSnapshots, ServerClock};
+use crate::settings::resource::ClientSettings;
 use crate::state::ClientState;
 
 pub mod component;
+pub mod event;
 pub mod resource;
 
 pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (update_players, movement, player_animations).run_if(in_state(ClientState::Running)));
+        app.init_resource::<PendingInputs>()
+            // Replaced by the server's settings on ConnectAck
+            .init_resource::<MovementSettings>()
+            .init_resource::<ServerClock>()
+            .init_resource::<InterpolationSettings>()
+            .init_resource::<ReceivedSnapshots>()
+            .add_event::<PlayerLeftEvent>()
+            .add_systems(Update, (update_players, spawn_players, interpolate_players).chain().run_if(in_state(ClientState::Running)))
+            .add_systems(Update, receive_removal.run_if(in_state(ClientState::Running)))
+            .add_systems(Update, player_animations.run_if(in_state(ClientState::Running)))
+            .add_systems(Update, (show_leave_notices, expire_leave_notices).run_if(in_state(ClientState::Running)))
+            .add_systems(OnEnter(ClientState::Reconnecting), reset_players)
+            // Predict our own player at the server's tick rate, one input per tick like the server processes them
+            .add_systems(
+                FixedUpdate,
+                (movement, predict_movement, player_collision).chain().before(PhysicsSet::SyncBackend).run_if(in_state(ClientState::Running)),
+            );
     }
 }
 
 // TODO: optimize networking
-fn movement(mut manager: ResMut<ClientPacketManager>, mouse_button_input: Res<Input<MouseButton>>, windows: Query<&Window, With<PrimaryWindow>>) {
-    if mouse_button_input.pressed(MouseButton::Right) {
+fn movement(
+    mut commands: Commands,
+    mut manager: ResMut<ClientPacketManager>,
+    mut pending_inputs: ResMut<PendingInputs>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    settings: Res<ClientSettings>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    me: Query<Entity, With<Me>>,
+) {
+    if mouse_button_input.pressed(settings.keybindings.move_to) {
         let window = windows.single();
         if let Some(mut position) = window.cursor_position() {
             // Get position with origin at center of window
             // y is flipped
             position.x -= window.width() / 2.0;
             position.y = window.height() / 2.0 - position.y;
-            manager.send(Movement { translation: position.to_array() }).unwrap();
+            // No direction to move in
+            if position == Vec2::ZERO {
+                return;
+            }
+            let translation = position.to_array();
+            let sequence = pending_inputs.push(translation);
+            // Connection loss is handled by the networking plugin
+            if let Err(e) = manager.send(Movement { translation, sequence }) {
+                debug!("[client] Could not send Movement: {:?}", e);
+            }
+            // Predict the move locally instead of waiting for the server to echo it back
+            if let Ok(me) = me.get_single() {
+                commands.entity(me).insert(player_movement::move_target_from_input(translation));
+            }
         }
     }
 }
 
+/// Client-side prediction of our own player's movement, using the same movement logic as the server
+fn predict_movement(
+    mut commands: Commands,
+    mut pending_inputs: ResMut<PendingInputs>,
+    mut me: Query<(Entity, &mut Transform, &mut MoveTarget, &mut KinematicCharacterController), With<Me>>,
+    movement_settings: Res<MovementSettings>,
+    fixed_time: Res<FixedTime>,
+) {
+    let delta_seconds = fixed_time.period.as_secs_f32();
+    for (entity, mut transform, mut move_target, mut controller) in me.iter_mut() {
+        // Record the step so it can be replayed if the server hasn't processed this input by its next update
+        if let Some(input) = pending_inputs.inputs.back_mut() {
+            input.steps.push(delta_seconds);
+        }
+        if player_movement::apply_movement(&mut transform, &mut move_target, &mut controller, &movement_settings, delta_seconds) {
+            commands.entity(entity).remove::<MoveTarget>();
+        }
+    }
+}
+
+/// Same collision handling as the server, for our own predicted player
+fn player_collision(
+    rapier_context: Res<RapierContext>,
+    mut me: Query<(&Transform, &Collider, &mut KinematicCharacterController, Option<&KinematicCharacterControllerOutput>), With<Me>>,
+    movement_settings: Res<MovementSettings>,
+    fixed_time: Res<FixedTime>,
+) {
+    for (transform, collider, mut controller, controller_output) in me.iter_mut() {
+        player_movement::apply_player_collision(
+            &rapier_context,
+            transform,
+            collider,
+            &mut controller,
+            controller_output,
+            &movement_settings,
+            fixed_time.period.as_secs_f32(),
+        );
+    }
+}
+
+/// Server reconciliation: snap our player to the server's state, then replay inputs the server has not processed yet on
+/// top of it
+///
+/// Only the horizontal steps are replayed, straight from the server's position.  Gravity and collisions are left to the
+/// character controller on the following frames, so when the server's player collided or fell during the replayed
+/// inputs, Me is put where the server would be without them and visibly snaps until the controller catches up.
+fn reconcile_me(
+    commands: &mut Commands,
+    entity: Entity,
+    transform: &mut Transform,
+    server_player: &Player,
+    pending_inputs: &mut PendingInputs,
+    movement_settings: &MovementSettings,
+    bounds: &LevelBounds,
+) {
+    pending_inputs.acknowledge(server_player.last_input);
+    transform.translation = server_player.transform.translation(bounds);
+    transform.scale = Vec3::splat(server_player.transform.scale());
+    if pending_inputs.inputs.is_empty() {
+        // Server has caught up, keep following whatever move target we have left
+        return;
+    }
+
+    let mut move_target = None;
+    for input in pending_inputs.inputs.iter() {
+        let mut target = player_movement::move_target_from_input(input.translation);
+        let mut reached = false;
+        for delta_seconds in input.steps.iter() {
+            let (delta, step_reached) = player_movement::step_move_target(&mut target, movement_settings, *delta_seconds);
+            transform.translation += delta.extend(0.0);
+            reached = step_reached;
+            if reached {
+                break;
+            }
+        }
+        move_target = if reached { None } else { Some(target) };
+    }
+    match move_target {
+        Some(move_target) => commands.entity(entity).insert(move_target),
+        None => commands.entity(entity).remove::<MoveTarget>(),
+    };
+}
+
+/// Spawn and despawn players so they match the newest snapshot.  Snapshots are diffed against ones we acknowledged, so
+/// players entering or leaving while packets were lost still show up in the next snapshot that arrives.
+#[allow(clippy::too_many_arguments)]
+fn spawn_players(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    players_query: Query<(Entity, &PlayerData)>,
+    client_id: Res<ClientId>,
+    interpolation_settings: Res<InterpolationSettings>,
+    level: Res<LevelInfo>,
+    mut received_snapshots: ResMut<ReceivedSnapshots>,
+    mut left_events: EventWriter<PlayerLeftEvent>,
+) {
+    let departed = std::mem::take(&mut received_snapshots.departed);
+    let Some((tick, players)) = received_snapshots.latest() else {
+        return;
+    };
+
+    let mut spawned = HashSet::new();
+    for (entity, player_data) in players_query.iter() {
+        spawned.insert(player_data.id);
+        if !players.contains_key(&player_data.id) {
+            // Missing from a full snapshot, which doesn't say why
+            let reason = departed.get(&player_data.id).copied().unwrap_or(LeaveReason::OutOfRange);
+            debug!("Removing player {}: {}", player_data.id, reason);
+            commands.entity(entity).despawn_recursive();
+            left_events.send(PlayerLeftEvent { id: player_data.id, reason });
+        }
+    }
+    for player in players.values().filter(|player| !spawned.contains(&player.id)) {
+        spawn_player(&mut commands, &asset_server, player, tick, &client_id, &interpolation_settings, &level.bounds);
+    }
+}
+
+/// The server tells us when it removes our own player, just before closing our connection
+fn receive_removal(mut manager: ResMut<ClientPacketManager>, client_id: Res<ClientId>, mut left_events: EventWriter<PlayerLeftEvent>) {
+    let packets = manager.received::<PlayersLeft, PlayersLeftPacketBuilder>(false).unwrap().unwrap_or_default();
+    for player_left in packets.into_iter().flat_map(|packet| packet.players).filter(|player_left| player_left.id == client_id.0) {
+        left_events.send(PlayerLeftEvent { id: player_left.id, reason: player_left.reason });
+    }
+}
+
+fn spawn_player(
+    commands: &mut Commands,
+    asset_server: &Res<AssetServer>,
+    player: &Player,
+    tick: u32,
+    client_id: &ClientId,
+    interpolation_settings: &InterpolationSettings,
+    bounds: &LevelBounds,
+) -> Entity {
+    debug!("Adding new player {}", player.id);
+    let transform = player.transform.to_transform(bounds);
+    let mut entity = player::spawn_player(commands, transform, player.handle_id, asset_server);
+    debug!("Added player {} with entity id {:?}", player.id, entity.id());
+
+    // Animations
+    let mut animations = Vec::new();
+    for i in 0..1 {
+        let mut animation_asset = String::new();
+        animation_asset.push_str(PLAYER_MODEL_HANDLE_IDS[player.handle_id as usize]);
+        animation_asset.push_str("#Animation");
+        animation_asset.push_str(i.to_string().as_str());
+        animations.push(asset_server.load(animation_asset));
+    }
+
+    entity.insert(PlayerData { id: player.id, handle_id: player.handle_id }).insert(Animations(animations));
+
+    if client_id.0 == player.id {
+        // Our own player is simulated locally for client-side prediction
+        entity.insert(Me).insert(get_player_collider_bundle()).insert(player_movement::player_character_controller());
+    } else {
+        let mut snapshots = SnapshotBuffer::default();
+        snapshots.push(Snapshot::new(tick, player, bounds), interpolation_settings.max_snapshots);
+        // Add collider for debug rendering
+        entity.insert(get_player_collider()).insert(snapshots);
+    }
+    entity.id()
+}
+
+/// Feed snapshots of players in our area of interest into their interpolation buffers, and reconcile our own player
+#[allow(clippy::too_many_arguments)]
 fn update_players(
     mut manager: ResMut<ClientPacketManager>,
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut players_query: Query<(Entity, &PlayerData, &mut Transform)>,
+    mut players_query: Query<(Entity, &PlayerData, &mut Transform, Option<&mut SnapshotBuffer>)>,
     client_id: Res<ClientId>,
+    mut pending_inputs: ResMut<PendingInputs>,
+    mut server_clock: ResMut<ServerClock>,
+    mut received_snapshots: ResMut<ReceivedSnapshots>,
+    interpolation_settings: Res<InterpolationSettings>,
+    movement_settings: Res<MovementSettings>,
+    level: Res<LevelInfo>,
+    time: Res<Time>,
     //meshes: Query<(Entity, &Handle<Mesh>), Without<NoFrustumCulling>>,
 ) {
     // TODO: properly disable frustum culling for player meshes only due to bug https://github.com/bevyengine/bevy/issues/4294
@@ -53,65 +270,112 @@ fn update_players(
     //     commands.entity(entity).insert(NoFrustumCulling);
     // }
     let server_player_packets = manager.received::<Players, PlayersPacketBuilder>(false).unwrap();
-    if let Some(mut server_players) = server_player_packets {
-        // Only care about last packet
-        let server_players = server_players.swap_remove(server_players.len() - 1);
-        // Find differences and intersections
-        let mut server_players_map: HashMap<u32, Player> = server_players.players.into_iter().map(|player| (player.id, player)).collect();
-
-        for (entity, client_player_data, mut transform) in players_query.iter_mut() {
-            if let Some(server_player_info) = server_players_map.remove(&client_player_data.id) {
-                // TODO: handle model changes
-                // TODO: optimize
-                let old_translation = transform.translation;
-                transform.translation.x = server_player_info.transform[0];
-                transform.translation.y = server_player_info.transform[1];
-                transform.translation.z = server_player_info.transform[2];
-                //println!("trans {:}", transform.translation);
-                // transform.translation = old_translation;
-                // if time.elapsed_seconds() as u32 / 2 % 2 == 0 {
-                //     transform.translation.x += 0.5;
-                // }
-                let look_direction = Vec2::new(transform.translation.x - old_translation.x, transform.translation.y - old_translation.y);
-                set_player_rotation(look_direction, &mut transform);
-                transform.scale = Vec3::splat(server_player_info.scale);
-                //println!("### transform {:?}", transform);
-            } else {
-                debug!("Removing player {}", client_player_data.id);
-                commands.entity(entity).despawn_recursive();
-            }
+    if let Some(server_players) = server_player_packets {
+        let reconstructed: Vec<(u32, HashMap<u32, Player>)> =
+            server_players.into_iter().filter_map(|packet| Some((packet.tick, received_snapshots.reconstruct(packet)?))).collect();
+        let Some((latest_tick, _)) = reconstructed.last() else {
+            return;
+        };
+        // Let the server diff against the latest snapshot from now on
+        if let Err(e) = manager.send(SnapshotAck { tick: *latest_tick }) {
+            debug!("[client] Could not send SnapshotAck: {:?}", e);
         }
 
-        // TODO: handle spawning players in a separate system to optimize
-        // New players
-        server_players_map.into_iter().for_each(|(id, player)| {
-            debug!("Adding new player {}", id);
-            let mut transform =
-                Transform::from_xyz(player.transform[0], player.transform[1], player.transform[2]).with_scale(Vec3::splat(player.scale));
-            transform.look_to(Vec3::NEG_Y, Vec3::Z);
-            let mut entity = player::spawn_player(&mut commands, transform, player.handle_id, &asset_server);
-            debug!("Added player {} with entity id {:?}", id, entity.id());
-
-            // Animations
-            let mut animations = Vec::new();
-            for i in 0..1 {
-                let mut animation_asset = String::new();
-                animation_asset.push_str(PLAYER_MODEL_HANDLE_IDS[player.handle_id as usize]);
-                animation_asset.push_str("#Animation");
-                animation_asset.push_str(i.to_string().as_str());
-                animations.push(asset_server.load(animation_asset));
+        let latest_index = reconstructed.len() - 1;
+        // Older snapshots that arrived this frame are still useful for interpolating remote players
+        for (index, (tick, server_players_map)) in reconstructed.iter().enumerate() {
+            server_clock.observe(*tick, time.elapsed_seconds_f64());
+            // Players not spawned yet are spawned by spawn_players from the latest snapshot
+            for (entity, client_player_data, mut transform, snapshots) in players_query.iter_mut() {
+                if let Some(server_player_info) = server_players_map.get(&client_player_data.id) {
+                    if client_player_data.id == client_id.0 {
+                        // Only reconcile against the latest state
+                        if index == latest_index {
+                            reconcile_me(
+                                &mut commands,
+                                entity,
+                                &mut transform,
+                                server_player_info,
+                                &mut pending_inputs,
+                                &movement_settings,
+                                &level.bounds,
+                            );
+                        }
+                    } else if let Some(mut snapshots) = snapshots {
+                        // TODO: handle model changes
+                        // Rendered by interpolate_players
+                        snapshots.push(Snapshot::new(*tick, server_player_info, &level.bounds), interpolation_settings.max_snapshots);
+                    }
+                }
             }
+        }
+    }
+}
 
-            entity
-                .insert(PlayerData { id, handle_id: player.handle_id })
-                // Add collider for debug rendering
-                .insert(get_player_collider())
-                .insert(Animations(animations));
+/// Render remote players a short delay in the past, interpolating between the snapshots around that time so late or
+/// jittered packets don't make them stutter
+fn interpolate_players(
+    mut players: Query<(&mut Transform, &mut SnapshotBuffer), Without<Me>>,
+    server_clock: Res<ServerClock>,
+    interpolation_settings: Res<InterpolationSettings>,
+    time: Res<Time>,
+) {
+    let Some(server_tick) = server_clock.estimate(time.elapsed_seconds_f64()) else {
+        return;
+    };
+    let render_tick = (server_tick - interpolation_settings.delay as f64 / server_clock.tick_duration) as f32;
+    let max_extrapolation_ticks = (interpolation_settings.max_extrapolation as f64 / server_clock.tick_duration) as f32;
+    for (mut transform, mut snapshots) in players.iter_mut() {
+        if let Some(snapshot) = snapshots.sample(render_tick, max_extrapolation_ticks) {
+            transform.translation = snapshot.translation;
+            transform.rotation = snapshot.rotation;
+            transform.scale = Vec3::splat(snapshot.scale);
+        }
+        snapshots.prune(render_tick);
+    }
+}
 
-            if client_id.0 == id {
-                entity.insert(Me);
-            }
-        });
+/// Start over once we reconnect, since the server resends every player in our area of interest
+fn reset_players(
+    mut commands: Commands,
+    players: Query<Entity, With<PlayerData>>,
+    mut pending_inputs: ResMut<PendingInputs>,
+    mut received_snapshots: ResMut<ReceivedSnapshots>,
+    mut server_clock: ResMut<ServerClock>,
+) {
+    for entity in players.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    // Keep the sequence going, the server remembers the last input it processed for our player
+    pending_inputs.inputs.clear();
+    *received_snapshots = ReceivedSnapshots::default();
+    *server_clock = ServerClock::default();
+}
+
+/// How long a notice about a player leaving stays on screen
+const LEAVE_NOTICE_SECONDS: f32 = 5.0;
+
+/// Show a notice when a player leaves the server.  Players moving out of range are not worth mentioning.
+fn show_leave_notices(mut commands: Commands, mut left_events: EventReader<PlayerLeftEvent>, client_id: Res<ClientId>) {
+    for event in left_events.iter().filter(|event| event.reason != LeaveReason::OutOfRange) {
+        let text = if event.id == client_id.0 {
+            format!("Removed from server: {}", event.reason)
+        } else {
+            format!("Player {} left: {}", event.id, event.reason)
+        };
+        commands.spawn((
+            TextBundle::from_section(text, TextStyle { font_size: 24.0, color: Color::WHITE, ..default() })
+                .with_style(Style { position_type: PositionType::Absolute, bottom: Val::Px(20.0), left: Val::Px(20.0), ..default() }),
+            LeaveNotice(Timer::from_seconds(LEAVE_NOTICE_SECONDS, TimerMode::Once)),
+        ));
+    }
+}
+
+fn expire_leave_notices(mut commands: Commands, mut notices: Query<(Entity, &mut LeaveNotice)>, time: Res<Time>) {
+    for (entity, mut notice) in notices.iter_mut() {
+        if notice.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
     }
 }
 
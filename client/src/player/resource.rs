@@ -1,4 +1,170 @@
+use std::collections::VecDeque;
+
 use bevy::prelude::Resource;
+use bevy::utils::HashMap;
+use derivative::Derivative;
+use mangovillage_common::networking::server_packets::{LeaveReason, Player, Players};
 
+/// Persistent id of our player, from ConnectAck
 #[derive(Resource)]
 pub struct ClientId(pub u32);
+
+/// Max inputs kept around waiting for the server to process them before we give up on the oldest ones
+const MAX_PENDING_INPUTS: usize = 256;
+
+/// Movement inputs sent to the server that it has not processed yet, replayed on top of the server's state of our player
+/// for client-side prediction
+#[derive(Resource, Default)]
+pub struct PendingInputs {
+    pub last_sequence: u32,
+    pub inputs: VecDeque<PendingInput>,
+}
+
+pub struct PendingInput {
+    pub sequence: u32,
+    /// x, y as sent in the Movement packet
+    pub translation: [f32; 2],
+    /// Delta seconds of each movement step predicted while this was the latest input
+    pub steps: Vec<f32>,
+}
+
+impl PendingInputs {
+    /// Track a new input, returning its sequence number
+    pub fn push(&mut self, translation: [f32; 2]) -> u32 {
+        self.last_sequence += 1;
+        if self.inputs.len() >= MAX_PENDING_INPUTS {
+            self.inputs.pop_front();
+        }
+        self.inputs.push_back(PendingInput { sequence: self.last_sequence, translation, steps: Vec::new() });
+        self.last_sequence
+    }
+
+    /// Drop inputs the server has already processed
+    pub fn acknowledge(&mut self, last_processed: u32) {
+        while matches!(self.inputs.front(), Some(input) if input.sequence <= last_processed) {
+            self.inputs.pop_front();
+        }
+    }
+}
+
+/// How remote players are rendered from the server's snapshots
+#[derive(Derivative, Resource)]
+#[derivative(Default)]
+pub struct InterpolationSettings {
+    /// How far in the past, in seconds, remote players are rendered.  Should cover a couple of snapshot intervals so
+    /// there is usually a newer snapshot to interpolate towards.
+    #[derivative(Default(value = "0.1"))]
+    pub delay: f32,
+    /// How long, in seconds, remote players keep moving along their last known velocity when snapshots stop arriving
+    #[derivative(Default(value = "0.25"))]
+    pub max_extrapolation: f32,
+    /// Snapshots kept per remote player
+    #[derivative(Default(value = "32"))]
+    pub max_snapshots: usize,
+}
+
+/// Estimates the server's current tick from when snapshots arrive
+#[derive(Derivative, Resource)]
+#[derivative(Default)]
+pub struct ServerClock {
+    /// Seconds per server tick.  Estimated from snapshots until the server tells us its tick rate.
+    #[derivative(Default(value = "1.0 / 60.0"))]
+    pub tick_duration: f64,
+    tick_rate_known: bool,
+    /// Smoothed `tick - local_seconds / tick_duration`
+    offset: Option<f64>,
+    last_observed: Option<(u32, f64)>,
+}
+
+impl ServerClock {
+    /// Smoothing factor for tick duration and offset estimates
+    const SMOOTHING: f64 = 0.1;
+    /// Snap instead of smoothing if the estimate is off by more than this many ticks
+    const MAX_DRIFT_TICKS: f64 = 30.0;
+
+    pub fn set_tick_rate(&mut self, tick_rate: u32) {
+        self.tick_duration = 1.0 / tick_rate as f64;
+        self.tick_rate_known = true;
+        // Offset is relative to the old tick duration
+        self.offset = None;
+    }
+
+    /// Record that a snapshot for `tick` arrived at `now` local seconds
+    pub fn observe(&mut self, tick: u32, now: f64) {
+        if let Some((last_tick, last_now)) = self.last_observed {
+            if tick <= last_tick {
+                return;
+            }
+            let sample = (now - last_now) / (tick - last_tick) as f64;
+            if !self.tick_rate_known && sample > 0.0 {
+                self.tick_duration += (sample - self.tick_duration) * Self::SMOOTHING;
+            }
+        }
+        self.last_observed = Some((tick, now));
+
+        let offset_sample = tick as f64 - now / self.tick_duration;
+        self.offset = match self.offset {
+            Some(offset) if (offset_sample - offset).abs() < Self::MAX_DRIFT_TICKS => Some(offset + (offset_sample - offset) * Self::SMOOTHING),
+            _ => Some(offset_sample),
+        };
+    }
+
+    /// Local seconds we last got a snapshot at
+    pub fn last_heard(&self) -> Option<f64> {
+        self.last_observed.map(|(_, now)| now)
+    }
+
+    /// Estimated server tick at `now` local seconds, if we've heard from the server yet
+    pub fn estimate(&self, now: f64) -> Option<f64> {
+        self.offset.map(|offset| now / self.tick_duration + offset)
+    }
+}
+
+/// Snapshots kept around as baselines for the server's deltas.  The server only diffs against snapshots we've
+/// acknowledged, so this only needs to cover acks that are still in flight.
+const MAX_RECEIVED_SNAPSHOTS: usize = 64;
+
+/// Snapshots reconstructed from the server's deltas
+#[derive(Resource, Default)]
+pub struct ReceivedSnapshots {
+    /// Ordered by tick, oldest first
+    snapshots: VecDeque<(u32, HashMap<u32, Player>)>,
+    /// Why players were removed in snapshots since this was last taken.  The same removal is repeated until we
+    /// acknowledge a snapshot without the player.
+    pub departed: HashMap<u32, LeaveReason>,
+}
+
+impl ReceivedSnapshots {
+    /// Apply a delta to the baseline it was diffed against.  Returns the full snapshot, or `None` if we no longer have
+    /// the baseline or already have a newer snapshot.
+    pub fn reconstruct(&mut self, packet: Players) -> Option<HashMap<u32, Player>> {
+        if matches!(self.snapshots.back(), Some((tick, _)) if *tick >= packet.tick) {
+            return None;
+        }
+        let mut players = match packet.baseline {
+            Some(baseline_tick) => self.snapshots.iter().find(|(tick, _)| *tick == baseline_tick)?.1.clone(),
+            None => HashMap::default(),
+        };
+        for player_left in packet.removed.iter() {
+            players.remove(&player_left.id);
+            self.departed.insert(player_left.id, player_left.reason);
+        }
+        for delta in packet.changed.iter() {
+            if let Some(player) = players.get_mut(&delta.id) {
+                delta.apply(player);
+            }
+        }
+        players.extend(packet.added.into_iter().map(|player| (player.id, player)));
+
+        if self.snapshots.len() >= MAX_RECEIVED_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((packet.tick, players.clone()));
+        Some(players)
+    }
+
+    /// Tick and players of the newest snapshot
+    pub fn latest(&self) -> Option<(u32, &HashMap<u32, Player>)> {
+        self.snapshots.back().map(|(tick, players)| (*tick, players))
+    }
+}
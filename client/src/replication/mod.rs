@@ -0,0 +1,128 @@
+use bevy::hierarchy::despawn_with_children_recursive;
+use bevy::prelude::*;
+
+use mangovillage_common::networking::client_packets::ReplicationAck;
+use mangovillage_common::networking::server_packets::{EntityState, Replication, ReplicationPacketBuilder};
+use mangovillage_common::replication;
+use mangovillage_common::replication::component::{NetworkId, Replicated, ReplicatedModel, ReplicatedTransform};
+use mangovillage_common::replication::ReplicationRegistry;
+use mangovillage_common::resource::LevelInfo;
+
+use crate::networking::resource::ClientPacketManager;
+use crate::replication::resource::{NetworkEntityMap, ReceivedReplications};
+use crate::state::ClientState;
+
+pub mod resource;
+
+/// Spawns, updates and despawns entities replicated from the server
+pub struct ReplicationPlugin;
+impl Plugin for ReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        replication::register_replicated_components(app);
+        app.init_resource::<NetworkEntityMap>()
+            .init_resource::<ReceivedReplications>()
+            .add_systems(
+                Update,
+                (apply_replication, spawn_replicated_models, sync_replicated_transforms).chain().run_if(in_state(ClientState::Running)),
+            )
+            .add_systems(OnEnter(ClientState::Reconnecting), despawn_replicated);
+    }
+}
+
+fn apply_replication(world: &mut World) {
+    let Some(packets) = world.resource_mut::<ClientPacketManager>().received::<Replication, ReplicationPacketBuilder>(false).unwrap() else {
+        return;
+    };
+    let mut latest_tick = None;
+    world.resource_scope(|world, registry: Mut<ReplicationRegistry>| {
+        world.resource_scope(|world, mut entity_map: Mut<NetworkEntityMap>| {
+            world.resource_scope(|world, mut received: Mut<ReceivedReplications>| {
+                for packet in packets {
+                    let previous = received.latest().cloned().unwrap_or_default();
+                    let Some(entities) = received.reconstruct(&packet) else {
+                        continue;
+                    };
+                    latest_tick = Some(packet.tick);
+                    for state in packet.spawned.into_iter().chain(packet.updated) {
+                        let entity = *entity_map.entities.entry(state.id).or_insert_with(|| world.spawn((Replicated, NetworkId(state.id))).id());
+                        apply_state(world, &registry, entity, state);
+                    }
+                    // Removals may have been in packets we lost, so go by what the entities should have now
+                    for (id, kinds) in previous.iter() {
+                        let Some(&entity) = entity_map.entities.get(id) else {
+                            continue;
+                        };
+                        match entities.get(id) {
+                            Some(current_kinds) => {
+                                if let Some(mut entity_mut) = world.get_entity_mut(entity) {
+                                    for kind in kinds.difference(current_kinds) {
+                                        if let Some(component) = registry.get(*kind) {
+                                            (component.remove)(&mut entity_mut);
+                                        }
+                                    }
+                                }
+                            }
+                            None => {
+                                despawn_with_children_recursive(world, entity);
+                                entity_map.entities.remove(id);
+                            }
+                        }
+                    }
+                }
+            });
+        });
+    });
+    // Let the server diff against the latest state from now on
+    if let Some(tick) = latest_tick {
+        if let Err(e) = world.resource_mut::<ClientPacketManager>().send(ReplicationAck { tick }) {
+            debug!("[client] Could not send ReplicationAck: {:?}", e);
+        }
+    }
+}
+
+fn apply_state(world: &mut World, registry: &ReplicationRegistry, entity: Entity, state: EntityState) {
+    let Some(mut entity_mut) = world.get_entity_mut(entity) else {
+        return;
+    };
+    for (kind, bytes) in state.components.iter() {
+        match registry.get(*kind) {
+            Some(component) => {
+                if let Err(e) = (component.write)(&mut entity_mut, bytes) {
+                    warn!("[client] Could not deserialize replicated component {} of entity {}: {}", component.name, state.id, e);
+                }
+            }
+            None => warn!("[client] Received unknown replicated component kind {}", kind),
+        }
+    }
+    for kind in state.removed.iter() {
+        if let Some(component) = registry.get(*kind) {
+            (component.remove)(&mut entity_mut);
+        }
+    }
+}
+
+/// The server resends everything in our area of interest once we reconnect
+fn despawn_replicated(mut commands: Commands, mut entity_map: ResMut<NetworkEntityMap>, mut received: ResMut<ReceivedReplications>) {
+    received.clear();
+    for (_, entity) in entity_map.entities.drain() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn spawn_replicated_models(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    level: Res<LevelInfo>,
+    models: Query<(Entity, &ReplicatedModel, Option<&ReplicatedTransform>), Changed<ReplicatedModel>>,
+) {
+    for (entity, model, transform) in models.iter() {
+        let transform = transform.map(|transform| transform.0.to_transform(&level.bounds)).unwrap_or_default();
+        commands.entity(entity).insert(SceneBundle { scene: asset_server.load(&model.handle_id), transform, ..default() });
+    }
+}
+
+fn sync_replicated_transforms(level: Res<LevelInfo>, mut entities: Query<(&ReplicatedTransform, &mut Transform), Changed<ReplicatedTransform>>) {
+    for (replicated_transform, mut transform) in entities.iter_mut() {
+        *transform = replicated_transform.0.to_transform(&level.bounds);
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::{Entity, Resource};
+use bevy::utils::{HashMap, HashSet};
+use mangovillage_common::networking::server_packets::Replication;
+
+/// Maps network ids of replicated entities to our local entities
+#[derive(Resource, Default)]
+pub struct NetworkEntityMap {
+    pub entities: HashMap<u32, Entity>,
+}
+
+/// Component kinds of each replicated entity, keyed by network id
+pub type EntityKinds = HashMap<u32, HashSet<u32>>;
+
+/// Replication states kept around as baselines for the server's deltas.  The server only diffs against states we've
+/// acknowledged, so this only needs to cover acks that are still in flight.
+const MAX_RECEIVED_REPLICATIONS: usize = 64;
+
+/// Which entities and components we had after each [`Replication`] we applied
+#[derive(Resource, Default)]
+pub struct ReceivedReplications {
+    /// Ordered by tick, oldest first
+    states: VecDeque<(u32, EntityKinds)>,
+}
+
+impl ReceivedReplications {
+    /// Apply a delta to the baseline it was diffed against.  Returns the entities and components we should have, or
+    /// `None` if we no longer have the baseline or already applied a newer packet.
+    pub fn reconstruct(&mut self, packet: &Replication) -> Option<EntityKinds> {
+        if matches!(self.states.back(), Some((tick, _)) if *tick >= packet.tick) {
+            return None;
+        }
+        let mut entities = match packet.baseline {
+            Some(baseline_tick) => self.states.iter().find(|(tick, _)| *tick == baseline_tick)?.1.clone(),
+            None => EntityKinds::default(),
+        };
+        for id in packet.despawned.iter() {
+            entities.remove(id);
+        }
+        for state in packet.updated.iter() {
+            let kinds = entities.entry(state.id).or_default();
+            kinds.extend(state.components.iter().map(|(kind, _)| *kind));
+            for kind in state.removed.iter() {
+                kinds.remove(kind);
+            }
+        }
+        for state in packet.spawned.iter() {
+            entities.insert(state.id, state.components.iter().map(|(kind, _)| *kind).collect());
+        }
+
+        if self.states.len() >= MAX_RECEIVED_REPLICATIONS {
+            self.states.pop_front();
+        }
+        self.states.push_back((packet.tick, entities.clone()));
+        Some(entities)
+    }
+
+    /// Entities and components after the newest packet we applied
+    pub fn latest(&self) -> Option<&EntityKinds> {
+        self.states.back().map(|(_, entities)| entities)
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+}
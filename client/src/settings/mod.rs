@@ -0,0 +1,282 @@
+use std::path::{Path, PathBuf};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::window::{PresentMode, PrimaryWindow, WindowCloseRequested, WindowResized};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+
+use mangovillage_common::networking::client_packets::Credentials;
+use mangovillage_common::networking::transport::{NetworkConditions, NetworkSimulationArgs, NetworkSimulationSettings};
+
+use crate::settings::resource::{ClientSettings, NetworkSimulation, SettingsFile, NETWORK_PRESETS};
+
+pub mod resource;
+
+/// Seconds to wait after the last change before writing settings
+const SAVE_DELAY: f32 = 1.0;
+
+#[derive(Parser)]
+#[command(about = "Mango Village client")]
+pub struct Cli {
+    /// Account to log in to, defaults to the last one used
+    #[arg(short, long)]
+    pub username: Option<String>,
+    /// Prompted for if not given.  Prefer the environment variable, as arguments are visible to other users.
+    #[arg(short, long, env = "MANGOVILLAGE_PASSWORD", hide_env_values = true)]
+    pub password: Option<String>,
+    /// Create the account if it doesn't exist yet
+    #[arg(long)]
+    pub register: bool,
+    /// Run a server in this process for others to join, and play on it
+    #[arg(long)]
+    pub host: bool,
+    /// Like --host, but only reachable from this machine
+    #[arg(long)]
+    pub offline: bool,
+    /// Server to connect to, e.g. 127.0.0.1:28154
+    #[arg(short, long)]
+    pub server: Option<String>,
+    /// Local address to bind to, e.g. 0.0.0.0:5001
+    #[arg(long)]
+    pub bind: Option<String>,
+    #[arg(long)]
+    pub width: Option<f32>,
+    #[arg(long)]
+    pub height: Option<f32>,
+    #[arg(long)]
+    pub vsync: Option<bool>,
+    /// One of error, warn, info, debug or trace
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// Settings file to use instead of the one in the user config directory
+    #[arg(long)]
+    pub settings: Option<PathBuf>,
+    #[command(flatten)]
+    pub network_simulation: NetworkSimulationArgs,
+}
+
+/// How this run of the game was started, as opposed to the settings that are kept between runs
+pub struct Launch {
+    pub credentials: Credentials,
+    pub settings_path: PathBuf,
+    pub host: Option<HostMode>,
+    /// Saved conditions with those given on the command line applied
+    pub network_simulation: NetworkSimulationSettings,
+}
+
+pub enum HostMode {
+    /// Others can join
+    Public,
+    /// Bound to loopback
+    Offline,
+}
+
+/// Read settings from the settings file and command line.  Options given on the command line are saved as the new
+/// defaults, except for network simulation which is only for this run.  Exits with an error message if they are invalid.
+pub fn load() -> (ClientSettings, Launch) {
+    let cli = Cli::parse();
+    let path = cli.settings.clone().unwrap_or_else(default_path);
+    let (mut settings, existed) = match ClientSettings::load(&path) {
+        Ok(loaded) => loaded,
+        Err(e) => exit_with_error(&path, e),
+    };
+    let original = toml::to_string(&settings).ok();
+
+    if let Some(username) = cli.username {
+        settings.network.username = Some(username);
+    }
+    if let Some(server) = cli.server {
+        settings.network.server_address = server;
+    }
+    if let Some(bind) = cli.bind {
+        settings.network.client_address = bind;
+    }
+    if let Some(width) = cli.width {
+        settings.window.width = width;
+    }
+    if let Some(height) = cli.height {
+        settings.window.height = height;
+    }
+    if let Some(vsync) = cli.vsync {
+        settings.window.present_mode = if vsync { PresentMode::AutoVsync } else { PresentMode::AutoNoVsync };
+    }
+    if let Some(log_level) = cli.log_level {
+        settings.log.level = log_level;
+    }
+    if let Err(e) = settings.validate() {
+        exit_with_error(&path, e);
+    }
+    let mut network_simulation = settings.network_simulation.clone();
+    network_simulation.apply_overrides(&cli.network_simulation);
+    if let Err(e) = network_simulation.validate() {
+        Cli::command().error(ErrorKind::ValueValidation, format!("Invalid network simulation: {}", e)).exit();
+    }
+
+    let Some(username) = settings.network.username.clone() else {
+        Cli::command().error(ErrorKind::MissingRequiredArgument, "--username is required until you have logged in once").exit();
+    };
+    // Written on first launch too, so there is a file to edit
+    if !existed || toml::to_string(&settings).ok() != original {
+        if let Err(e) = settings.save(&path) {
+            eprintln!("[client] Could not save settings to {:?}: {}", path, e);
+        }
+    }
+    let host = match (cli.offline, cli.host) {
+        (true, _) => Some(HostMode::Offline),
+        (false, true) => Some(HostMode::Public),
+        (false, false) => None,
+    };
+    // Hosts create their account on their own server
+    let register = cli.register || host.is_some();
+    let password = cli.password.unwrap_or_else(|| prompt_password(&username));
+    (settings, Launch { credentials: Credentials { username, password, register }, settings_path: path, host, network_simulation })
+}
+
+fn prompt_password(username: &str) -> String {
+    match rpassword::prompt_password(format!("Password for {}: ", username)) {
+        Ok(password) => password,
+        Err(e) => Cli::command()
+            .error(ErrorKind::MissingRequiredArgument, format!("--password or MANGOVILLAGE_PASSWORD is required without a terminal ({})", e))
+            .exit(),
+    }
+}
+
+fn default_path() -> PathBuf {
+    config_dir().join("client.toml")
+}
+
+fn config_dir() -> PathBuf {
+    dirs::config_dir().map(|dir| dir.join("mangovillage")).unwrap_or_default()
+}
+
+/// Where a hosted server keeps accounts and characters
+pub fn host_data_dir() -> PathBuf {
+    dirs::data_dir().map(|dir| dir.join("mangovillage")).unwrap_or_default().join("host")
+}
+
+/// Config file for a hosted server
+pub fn host_server_config(settings: &ClientSettings) -> PathBuf {
+    settings.host.server_config.clone().unwrap_or_else(|| config_dir().join("server.toml"))
+}
+
+fn exit_with_error(path: &Path, error: impl std::fmt::Display) -> ! {
+    eprintln!("[client] Invalid settings file {:?}: {}", path, error);
+    std::process::exit(1);
+}
+
+/// Keeps [`ClientSettings`] up to date with changes made in game and writes them back to the settings file
+pub struct SettingsPlugin {
+    pub path: PathBuf,
+    pub network_simulation: NetworkSimulationSettings,
+}
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        let mut save_timer = Timer::from_seconds(SAVE_DELAY, TimerMode::Once);
+        // Paused while there is nothing to save
+        save_timer.pause();
+        app.insert_resource(SettingsFile { path: self.path.clone(), save_timer })
+            .insert_resource(NetworkSimulation(self.network_simulation.clone()))
+            .add_systems(
+                Update,
+                (track_window_size, toggle_vsync, toggle_network_simulation, cycle_network_preset, apply_network_conditions, save_settings).chain(),
+            )
+            .add_systems(Last, save_on_exit);
+    }
+}
+
+fn track_window_size(
+    mut resized: EventReader<WindowResized>,
+    primary_window: Query<Entity, With<PrimaryWindow>>,
+    mut settings: ResMut<ClientSettings>,
+) {
+    let Ok(primary_window) = primary_window.get_single() else {
+        return;
+    };
+    if let Some(resized) = resized.iter().filter(|resized| resized.window == primary_window).last() {
+        // Only touch settings on an actual change, so they aren't saved for nothing
+        if settings.window.width != resized.width || settings.window.height != resized.height {
+            settings.window.width = resized.width;
+            settings.window.height = resized.height;
+        }
+    }
+}
+
+fn toggle_vsync(keys: Res<Input<KeyCode>>, mut settings: ResMut<ClientSettings>, mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    if keys.just_pressed(settings.keybindings.toggle_vsync) {
+        settings.window.present_mode = match settings.window.present_mode {
+            PresentMode::AutoNoVsync | PresentMode::Immediate | PresentMode::Mailbox => PresentMode::AutoVsync,
+            PresentMode::AutoVsync | PresentMode::Fifo => PresentMode::AutoNoVsync,
+        };
+        info!("[client] Present mode set to {:?}", settings.window.present_mode);
+        for mut window in windows.iter_mut() {
+            window.present_mode = settings.window.present_mode;
+        }
+    }
+}
+
+fn toggle_network_simulation(keys: Res<Input<KeyCode>>, settings: Res<ClientSettings>, mut simulation: ResMut<NetworkSimulation>) {
+    if keys.just_pressed(settings.keybindings.toggle_network_simulation) {
+        simulation.0.enabled = !simulation.0.enabled;
+        info!("[client] Network simulation {}", if simulation.0.enabled { "enabled" } else { "disabled" });
+    }
+}
+
+fn cycle_network_preset(keys: Res<Input<KeyCode>>, settings: Res<ClientSettings>, mut simulation: ResMut<NetworkSimulation>) {
+    if !keys.just_pressed(settings.keybindings.cycle_network_preset) {
+        return;
+    }
+    let simulation = &mut simulation.0;
+    let conditions = (simulation.latency_ms, simulation.jitter_ms, simulation.loss, simulation.duplicate, simulation.reorder);
+    let current = NETWORK_PRESETS
+        .iter()
+        .position(|&(_, latency, jitter, loss, duplicate, reorder)| (latency, jitter, loss, duplicate, reorder) == conditions);
+    // Starts from the first preset if the conditions were set by hand
+    let (name, latency, jitter, loss, duplicate, reorder) = NETWORK_PRESETS[current.map_or(0, |current| (current + 1) % NETWORK_PRESETS.len())];
+    simulation.enabled = true;
+    simulation.latency_ms = latency;
+    simulation.jitter_ms = jitter;
+    simulation.loss = loss;
+    simulation.duplicate = duplicate;
+    simulation.reorder = reorder;
+    info!("[client] Simulating a {} network: {:?}", name, simulation.conditions());
+}
+
+/// Changes take effect on the connection immediately
+fn apply_network_conditions(simulation: Res<NetworkSimulation>, conditions: Res<NetworkConditions>) {
+    if simulation.is_changed() {
+        conditions.set(simulation.0.conditions());
+    }
+}
+
+fn save_settings(mut settings_file: ResMut<SettingsFile>, settings: Res<ClientSettings>, time: Res<Time>) {
+    if settings.is_changed() && !settings.is_added() {
+        settings_file.save_timer.reset();
+        settings_file.save_timer.unpause();
+    }
+    if settings_file.save_timer.tick(time.delta()).just_finished() {
+        settings_file.save_timer.pause();
+        write_settings(&settings_file, &settings);
+    }
+}
+
+/// Don't lose changes made within [`SAVE_DELAY`] of exiting
+fn save_on_exit(
+    mut settings_file: ResMut<SettingsFile>,
+    settings: Res<ClientSettings>,
+    exit: EventReader<AppExit>,
+    close_window: EventReader<WindowCloseRequested>,
+) {
+    if (!exit.is_empty() || !close_window.is_empty()) && !settings_file.save_timer.paused() {
+        settings_file.save_timer.pause();
+        write_settings(&settings_file, &settings);
+    }
+}
+
+fn write_settings(settings_file: &SettingsFile, settings: &ClientSettings) {
+    match settings.save(&settings_file.path) {
+        Ok(()) => debug!("[client] Saved settings to {:?}", settings_file.path),
+        Err(e) => error!("[client] Could not save settings to {:?}: {}", settings_file.path, e),
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use bevy::log::Level;
+use bevy::prelude::{KeyCode, MouseButton, Resource, Timer};
+use bevy::window::PresentMode;
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+
+use mangovillage_common::networking::transport::NetworkSimulationSettings;
+
+/// Settings persisted in the user's config directory
+#[derive(Resource, Serialize, Deserialize, Derivative, Clone)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientSettings {
+    pub network: NetworkSettings,
+    pub window: WindowSettings,
+    pub log: LogSettings,
+    pub debug: DebugSettings,
+    pub host: HostSettings,
+    /// Conditions to start with.  Changes made in game aren't saved.
+    pub network_simulation: NetworkSimulationSettings,
+    pub keybindings: KeyBindings,
+}
+
+#[derive(Serialize, Deserialize, Derivative, Clone)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSettings {
+    #[derivative(Default(value = "\"0.0.0.0:5001\".to_string()"))]
+    pub client_address: String,
+    #[derivative(Default(value = "\"127.0.0.1:28154\".to_string()"))]
+    pub server_address: String,
+    /// Last account logged in to.  Passwords are never saved.
+    pub username: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Derivative, Clone)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowSettings {
+    #[derivative(Default(value = "1280.0"))]
+    pub width: f32,
+    #[derivative(Default(value = "720.0"))]
+    pub height: f32,
+    #[derivative(Default(value = "PresentMode::AutoVsync"))]
+    pub present_mode: PresentMode,
+}
+
+#[derive(Serialize, Deserialize, Derivative, Clone)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    /// One of error, warn, info, debug or trace
+    #[derivative(Default(value = "\"info\".to_string()"))]
+    pub level: String,
+    /// Per crate levels, in the same format as RUST_LOG
+    #[derivative(Default(value = "\"info,mangovillage_client=debug,durian=info,wgpu=error\".to_string()"))]
+    pub filter: String,
+}
+
+#[derive(Serialize, Deserialize, Derivative, Clone)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebugSettings {
+    /// Show the world inspector
+    #[derivative(Default(value = "true"))]
+    pub inspector: bool,
+    /// Log frame time and network diagnostics
+    #[derivative(Default(value = "true"))]
+    pub diagnostics: bool,
+}
+
+/// Used when hosting a game with --host or --offline
+#[derive(Serialize, Deserialize, Derivative, Clone)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostSettings {
+    /// Address other players join on.  Only the port is used when playing offline.
+    #[derivative(Default(value = "\"0.0.0.0:28154\".to_string()"))]
+    pub bind_address: String,
+    /// Server config file, defaults to server.toml next to this file.  Relative paths in it are relative to the user
+    /// data directory.
+    pub server_config: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Derivative, Clone)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    #[derivative(Default(value = "MouseButton::Right"))]
+    pub move_to: MouseButton,
+    /// Switch between the locked and debug camera
+    #[derivative(Default(value = "KeyCode::F1"))]
+    pub toggle_camera: KeyCode,
+    #[derivative(Default(value = "KeyCode::F2"))]
+    pub toggle_meshes: KeyCode,
+    #[derivative(Default(value = "KeyCode::F3"))]
+    pub toggle_debug_render: KeyCode,
+    #[derivative(Default(value = "KeyCode::F4"))]
+    pub toggle_vsync: KeyCode,
+    #[derivative(Default(value = "KeyCode::F5"))]
+    pub toggle_network_simulation: KeyCode,
+    /// Switch to the next of the [`NETWORK_PRESETS`]
+    #[derivative(Default(value = "KeyCode::F6"))]
+    pub cycle_network_preset: KeyCode,
+    #[derivative(Default(value = "KeyCode::BracketLeft"))]
+    pub camera_slower: KeyCode,
+    #[derivative(Default(value = "KeyCode::BracketRight"))]
+    pub camera_faster: KeyCode,
+    #[derivative(Default(value = "KeyCode::W"))]
+    pub camera_forward: KeyCode,
+    #[derivative(Default(value = "KeyCode::S"))]
+    pub camera_back: KeyCode,
+    #[derivative(Default(value = "KeyCode::A"))]
+    pub camera_left: KeyCode,
+    #[derivative(Default(value = "KeyCode::D"))]
+    pub camera_right: KeyCode,
+    #[derivative(Default(value = "KeyCode::E"))]
+    pub camera_up: KeyCode,
+    #[derivative(Default(value = "KeyCode::Q"))]
+    pub camera_down: KeyCode,
+    #[derivative(Default(value = "MouseButton::Middle"))]
+    pub camera_pan: MouseButton,
+    #[derivative(Default(value = "MouseButton::Left"))]
+    pub camera_orbit: MouseButton,
+}
+
+/// Name, latency, jitter, loss, duplicate and reorder of the network conditions that can be cycled through in game
+pub const NETWORK_PRESETS: [(&str, u64, u64, f64, f64, f64); 4] = [
+    ("lan", 1, 0, 0.0, 0.0, 0.0),
+    ("average", 40, 10, 0.01, 0.0, 0.01),
+    ("poor", 120, 40, 0.05, 0.01, 0.05),
+    ("terrible", 300, 100, 0.15, 0.05, 0.1),
+];
+
+/// Network conditions simulated right now, changed in game without touching the settings file
+#[derive(Resource)]
+pub struct NetworkSimulation(pub NetworkSimulationSettings);
+
+/// Where settings are saved, and a debounce so dragging the window edge doesn't write the file every frame
+#[derive(Resource)]
+pub struct SettingsFile {
+    pub path: PathBuf,
+    pub save_timer: Timer,
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    Invalid(String),
+}
+
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::Io(e) => write!(f, "{}", e),
+            SettingsError::Parse(e) => write!(f, "invalid settings file: {}", e),
+            SettingsError::Serialize(e) => write!(f, "could not serialize settings: {}", e),
+            SettingsError::Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl ClientSettings {
+    /// Load settings from `path`, using defaults if the file does not exist yet.  Also returns whether it existed.
+    pub fn load(path: &Path) -> Result<(Self, bool), SettingsError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok((toml::from_str(&contents).map_err(SettingsError::Parse)?, true)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok((ClientSettings::default(), false)),
+            Err(e) => Err(SettingsError::Io(e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SettingsError> {
+        let contents = toml::to_string(self).map_err(SettingsError::Serialize)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(SettingsError::Io)?;
+        }
+        let temp_path = path.with_extension("toml.tmp");
+        fs::write(&temp_path, contents).map_err(SettingsError::Io)?;
+        fs::rename(&temp_path, path).map_err(SettingsError::Io)
+    }
+
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if self.network.client_address.parse::<SocketAddr>().is_err() {
+            return Err(SettingsError::Invalid(format!("network.client_address {:?} is not an ip:port address", self.network.client_address)));
+        }
+        if self.network.server_address.parse::<SocketAddr>().is_err() {
+            return Err(SettingsError::Invalid(format!("network.server_address {:?} is not an ip:port address", self.network.server_address)));
+        }
+        if self.host.bind_address.parse::<SocketAddr>().is_err() {
+            return Err(SettingsError::Invalid(format!("host.bind_address {:?} is not an ip:port address", self.host.bind_address)));
+        }
+        // Also catches nan
+        let size_valid = self.window.width >= 1.0 && self.window.height >= 1.0;
+        if !size_valid {
+            return Err(SettingsError::Invalid(format!("window size {}x{} is too small", self.window.width, self.window.height)));
+        }
+        if let Err(e) = self.network_simulation.validate() {
+            return Err(SettingsError::Invalid(format!("network_simulation.{}", e)));
+        }
+        if self.log.level.parse::<Level>().is_err() {
+            return Err(SettingsError::Invalid(format!("log.level {:?} is not one of error, warn, info, debug or trace", self.log.level)));
+        }
+        Ok(())
+    }
+
+    pub fn log_level(&self) -> Level {
+        self.log.level.parse().unwrap_or(Level::INFO)
+    }
+}
@@ -7,6 +7,10 @@ pub enum ClientState {
     LoadingLevel,
     LoadingPhysics,
     Running,
+    /// Lost connection to the server, retrying with backoff.  Goes back to JoiningServer once connected.
+    Reconnecting,
+    /// Server refused our Connect, see [`ConnectRejected`](crate::networking::resource::ConnectRejected)
+    Rejected,
 }
 
 #[derive(States, Default, Debug, Clone, Eq, PartialEq, Hash)]
@@ -1,6 +1,7 @@
 use bevy::prelude::*;
 
 use mangovillage_common::networking::server_packets::{SpawnScene, SpawnScenePacketBuilder};
+use mangovillage_common::resource::LevelInfo;
 use mangovillage_common::world;
 
 use crate::networking::resource::ClientPacketManager;
@@ -18,12 +19,22 @@ fn spawn_scene(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut client_state: ResMut<NextState<ClientState>>,
+    current_level: Option<Res<LevelInfo>>,
 ) {
     let spawn_scene_packets = manager.received::<SpawnScene, SpawnScenePacketBuilder>(false).unwrap();
     if let Some(spawn_scenes) = spawn_scene_packets {
         let scene = spawn_scenes.last().unwrap();
+        // Reconnected to a server running the level we already have loaded
+        if matches!(&current_level, Some(current_level) if current_level.handle_id == scene.level.handle_id) {
+            info!("[client] Level {} already loaded, transitioning state to Running", scene.level.handle_id);
+            client_state.set(ClientState::Running);
+            return;
+        }
+        // TODO: unload the previous level when reconnecting to a server running a different one
         info!("[client] Spawning level {:?}", scene.level);
         world::load_level(&mut commands, &asset_server, &scene.level);
+        // Needed to decode replicated transforms
+        commands.insert_resource(scene.level.clone());
         info!("[client] Transitioning state to LoadingPhysics");
         client_state.set(ClientState::LoadingPhysics);
     }
@@ -0,0 +1,261 @@
+//! Whole sessions between a server and clients, see [`harness`]
+
+mod harness;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use mangovillage_client::networking::resource::{ConnectRejected, ReconnectSettings};
+use mangovillage_client::state::ClientState;
+use mangovillage_common::networking::client_packets::Credentials;
+use mangovillage_common::networking::server_packets::RejectReason;
+use mangovillage_common::networking::transport::LinkConditions;
+use mangovillage_common::player::component::PlayerData;
+use mangovillage_common::replication::component::{NetworkId, Replicated, ReplicatedModel};
+use mangovillage_common::replication::{AppReplicationExt, ReplicatedComponent};
+use mangovillage_common::resource::LevelInfo;
+use mangovillage_server::config::PropSettings;
+use mangovillage_server::networking::event::KickClient;
+use mangovillage_server::player::component::{Disconnected, ServerPlayer};
+use mangovillage_server::state::ServerState;
+
+use crate::harness::{Harness, PASSWORD};
+
+fn rejection(harness: &Harness, client: usize) -> RejectReason {
+    harness.clients[client].world.resource::<ConnectRejected>().reason.clone()
+}
+
+#[test]
+fn client_joins_and_loads_the_level() {
+    let mut harness = Harness::new();
+    let client = harness.add_client("alice");
+    harness.run_until_running();
+
+    assert_eq!(harness.server_state(), ServerState::Running);
+    // Only set on ConnectAck
+    assert!(harness.client_id(client).is_some());
+    // Only set on SpawnScene
+    let client_level = harness.clients[client].world.resource::<LevelInfo>().handle_id.clone();
+    assert_eq!(client_level, harness.server.world.resource::<LevelInfo>().handle_id);
+    let players = harness.server.world.query::<&ServerPlayer>().iter(&harness.server.world).count();
+    assert_eq!(players, 1);
+}
+
+#[test]
+fn client_sees_itself_in_players_after_moving() {
+    let mut harness = Harness::new();
+    let client = harness.add_client("alice");
+    harness.run_until_running();
+    harness.run_until("the client is in its own snapshot", |harness| harness.me_in_snapshot(client).is_some());
+    let start = harness.me_in_snapshot(client).unwrap().transform.position;
+
+    harness.hold_move(client, Vec2::new(100.0, 0.0));
+    harness.steps(30);
+    harness.release_move(client);
+    let last_input = harness.last_input(client);
+    assert!(last_input > 0, "no Movement was sent");
+
+    harness.run_until("the server processed every input", |harness| {
+        harness.me_in_snapshot(client).is_some_and(|me| me.last_input >= last_input)
+    });
+    assert_ne!(harness.me_in_snapshot(client).unwrap().transform.position, start);
+}
+
+#[test]
+fn clients_see_each_other() {
+    let mut harness = Harness::new();
+    let alice = harness.add_client("alice");
+    let bob = harness.add_client("bob");
+    harness.run_until_running();
+
+    let ids = [harness.client_id(alice).unwrap(), harness.client_id(bob).unwrap()];
+    assert_ne!(ids[0], ids[1]);
+    harness.run_until("both clients see both players", |harness| {
+        [alice, bob].iter().all(|client| {
+            harness.latest_snapshot(*client).is_some_and(|(_, players)| ids.iter().all(|id| players.contains_key(id)))
+        })
+    });
+}
+
+#[test]
+fn players_leaving_while_packets_are_lost_are_still_removed() {
+    let mut harness = Harness::new();
+    let alice = harness.add_client("alice");
+    let bob = harness.add_client("bob");
+    harness.run_until_running();
+    let bob_id = harness.client_id(bob).unwrap();
+    harness.run_until("alice sees bob", |harness| harness.spawned_players(alice).contains(&bob_id));
+
+    harness.network.set_conditions(LinkConditions { loss: 1.0, ..default() });
+    let remote_id = harness
+        .server
+        .world
+        .query::<(&ServerPlayer, &PlayerData)>()
+        .iter(&harness.server.world)
+        .find(|(_, player_data)| player_data.id == bob_id)
+        .map(|(server_player, _)| server_player.remote_id)
+        .unwrap();
+    harness.server.world.send_event(KickClient { remote_id, reason: "testing".to_string() });
+    harness.steps(30);
+    assert!(harness.spawned_players(alice).contains(&bob_id), "the removal should have been lost");
+
+    harness.network.set_conditions(LinkConditions::default());
+    harness.run_until("alice removes bob", |harness| !harness.spawned_players(alice).contains(&bob_id));
+}
+
+/// Only replicated in tests
+#[derive(Component, Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+struct Counter(u32);
+
+impl ReplicatedComponent for Counter {
+    const NAME: &'static str = "test_counter";
+}
+
+#[test]
+fn props_are_replicated() {
+    let model = "models/small/big.glb#Scene0".to_string();
+    let mut harness = Harness::with_settings(|settings| settings.world.props.push(PropSettings { model: model.clone(), position: [0.0, 0.0, 0.0] }));
+    let client = harness.add_client("alice");
+    harness.run_until_running();
+
+    harness.run_until("the client has the prop", |harness| {
+        harness.replicated::<ReplicatedModel>(client).values().any(|prop| prop.as_ref().is_some_and(|prop| prop.handle_id == model))
+    });
+}
+
+#[test]
+fn replicated_components_catch_up_after_packets_are_lost() {
+    let mut harness = Harness::new();
+    let client = harness.add_client("alice");
+    harness.run_until_running();
+    harness.server.replicate::<Counter>();
+    harness.clients[client].replicate::<Counter>();
+
+    // Without a transform, so visible everywhere
+    let kept = harness.server.world.spawn((Replicated, Counter(1))).id();
+    let despawned = harness.server.world.spawn((Replicated, Counter(1))).id();
+    harness.run_until("the client has both entities", |harness| {
+        harness.replicated::<Counter>(client).values().filter(|counter| **counter == Some(Counter(1))).count() == 2
+    });
+    let kept_id = harness.server.world.get::<NetworkId>(kept).unwrap().0;
+    let despawned_id = harness.server.world.get::<NetworkId>(despawned).unwrap().0;
+
+    harness.network.set_conditions(LinkConditions { loss: 1.0, ..default() });
+    harness.server.world.entity_mut(kept).insert(Counter(2));
+    harness.steps(10);
+    harness.server.world.entity_mut(kept).remove::<Counter>();
+    harness.server.world.despawn(despawned);
+    harness.steps(30);
+    let replicated = harness.replicated::<Counter>(client);
+    assert_eq!(replicated.get(&kept_id), Some(&Some(Counter(1))), "the changes should have been lost");
+    assert!(replicated.contains_key(&despawned_id), "the despawn should have been lost");
+
+    harness.network.set_conditions(LinkConditions::default());
+    harness.run_until("the client catches up", |harness| {
+        let replicated = harness.replicated::<Counter>(client);
+        replicated.get(&kept_id) == Some(&None) && !replicated.contains_key(&despawned_id)
+    });
+}
+
+#[test]
+fn traffic_and_round_trip_times_are_measured() {
+    let mut harness = Harness::new();
+    let client = harness.add_client("alice");
+    harness.run_until_running();
+    // The server reports the round trip time it measured with one ping in the next
+    harness.run_until("the client knows its round trip time", |harness| {
+        harness.client_stats(client).connection(0).is_some_and(|connection| connection.rtt.is_some())
+    });
+
+    let remote_id = harness.server.world.query::<&ServerPlayer>().single(&harness.server.world).remote_id;
+    let on_server = harness.server_stats().connection(remote_id).unwrap();
+    let on_client = harness.client_stats(client).connection(0).unwrap();
+    assert!(on_server.rtt.is_some());
+    assert_eq!(on_server.packet_types["Connect"].received.packets, 1);
+    assert_eq!(on_client.packet_types["Connect"].sent, on_server.packet_types["Connect"].received);
+    assert_eq!(on_client.packet_types["ConnectAck"].received.packets, 1);
+    assert!(on_client.received.bytes > 0 && on_server.sent.bytes >= on_client.received.bytes);
+}
+
+#[test]
+fn wrong_password_is_rejected() {
+    let mut harness = Harness::new();
+    harness.add_client("alice");
+    harness.run_until_running();
+
+    let impostor = harness.add_client_with(Credentials { username: "alice".to_string(), password: format!("not {}", PASSWORD), register: false });
+    harness.run_until("the impostor is rejected", |harness| harness.client_state(impostor) == ClientState::Rejected);
+    assert!(matches!(rejection(&harness, impostor), RejectReason::InvalidCredentials));
+}
+
+#[test]
+fn logging_in_again_does_not_take_over_the_player() {
+    let mut harness = Harness::new();
+    let alice = harness.add_client("alice");
+    harness.run_until_running();
+
+    let second = harness.add_client_with(Credentials { username: "alice".to_string(), password: PASSWORD.to_string(), register: false });
+    harness.run_until("the second login is rejected", |harness| harness.client_state(second) == ClientState::Rejected);
+    assert!(matches!(rejection(&harness, second), RejectReason::AlreadyLoggedIn));
+    assert_eq!(harness.client_state(alice), ClientState::Running);
+    let players = harness.server.world.query::<&ServerPlayer>().iter(&harness.server.world).count();
+    assert_eq!(players, 1);
+}
+
+#[test]
+fn lost_connections_resume_the_same_player() {
+    let mut harness = Harness::new();
+    let alice = harness.add_client("alice");
+    harness.run_until_running();
+    let alice_id = harness.client_id(alice).unwrap();
+    let player = harness.server_player(alice_id).unwrap();
+    let token = harness.session_token(alice);
+    assert!(token.is_some());
+
+    // The level is loaded, so the server going quiet now means the connection is gone
+    let reconnect_settings = harness.clients[alice].world.remove_resource::<ReconnectSettings>().unwrap();
+    harness.clients[alice].insert_resource(ReconnectSettings { timeout: 1.0, ..default() });
+    harness.set_online(alice, false);
+    harness.network.set_conditions(LinkConditions { loss: 1.0, ..default() });
+    harness.run_until("alice notices the connection is lost", |harness| harness.client_state(alice) == ClientState::Reconnecting);
+    harness.network.set_conditions(LinkConditions::default());
+    harness.run_until("the server keeps alice's player", |harness| harness.server.world.get::<Disconnected>(player).is_some());
+
+    harness.clients[alice].insert_resource(reconnect_settings);
+    harness.set_online(alice, true);
+    harness.run_until_running();
+    assert_eq!(harness.client_id(alice), Some(alice_id));
+    assert_eq!(harness.session_token(alice), token);
+    assert_eq!(harness.server_player(alice_id), Some(player), "the player should have been resumed rather than spawned again");
+    assert!(harness.server.world.get::<Disconnected>(player).is_none());
+}
+
+#[test]
+fn players_are_removed_when_the_grace_period_runs_out() {
+    let mut harness = Harness::with_settings(|settings| settings.network.session_grace_period_secs = 1);
+    let alice = harness.add_client("alice");
+    harness.run_until_running();
+    let alice_id = harness.client_id(alice).unwrap();
+    let player = harness.server_player(alice_id).unwrap();
+    let token = harness.session_token(alice);
+
+    harness.lose_connection(alice);
+    harness.run_until("the server keeps alice's player", |harness| harness.server.world.get::<Disconnected>(player).is_some());
+    harness.run_until("the server removes alice's player", |harness| harness.server_player(alice_id).is_none());
+
+    // The expired token is ignored and the credentials log in to a new session
+    harness.set_online(alice, true);
+    harness.run_until_running();
+    assert_eq!(harness.client_id(alice), Some(alice_id));
+    assert_ne!(harness.session_token(alice), token);
+    assert_ne!(harness.server_player(alice_id), Some(player));
+}
+
+#[test]
+fn registration_can_be_disabled() {
+    let mut harness = Harness::with_settings(|settings| settings.accounts.allow_registration = false);
+    let client = harness.add_client("alice");
+    harness.run_until("the client is rejected", |harness| harness.client_state(client) == ClientState::Rejected);
+    assert!(matches!(rejection(&harness, client), RejectReason::RegistrationDisabled));
+    assert!(harness.client_id(client).is_none());
+}
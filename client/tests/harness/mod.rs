@@ -0,0 +1,286 @@
+//! Runs a server and headless clients in one process over a [`ChannelNetwork`], stepping them frame by frame
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy::app::PluginGroupBuilder;
+use bevy::audio::AudioPlugin;
+use bevy::gilrs::GilrsPlugin;
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use bevy::render::settings::WgpuSettings;
+use bevy::render::RenderPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bevy::utils::HashMap;
+use bevy::window::{ExitCondition, PrimaryWindow, WindowResolution};
+use bevy::winit::WinitPlugin;
+use bevy_embedded_assets::EmbeddedAssetPlugin;
+
+use mangovillage_client::networking::resource::{ClientInfo, Connector, ReconnectSettings, Session};
+use mangovillage_client::player::resource::{ClientId, PendingInputs, ReceivedSnapshots};
+use mangovillage_client::settings::resource::ClientSettings;
+use mangovillage_client::state::ClientState;
+use mangovillage_client::{networking, physics, player, replication, world};
+use mangovillage_common::networking::client_packets::Credentials;
+use mangovillage_common::networking::diagnostics::resource::NetworkStats;
+use mangovillage_common::networking::server_packets::Player;
+use mangovillage_common::networking::transport::{ChannelNetwork, LinkConditions, MeteredTransport, Transport, TransportError};
+use mangovillage_common::player::component::PlayerData;
+use mangovillage_common::replication::component::NetworkId;
+use mangovillage_server::config::ServerSettings;
+use mangovillage_server::headless::HeadlessPlugins;
+use mangovillage_server::networking::resource::ServerPacketManager;
+use mangovillage_server::player::component::ServerPlayer;
+use mangovillage_server::state::ServerState;
+use mangovillage_server::GameServerPlugin;
+
+/// Simulated time each step advances every app and the network by
+pub const FRAME: Duration = Duration::from_micros(16_667);
+/// Wall time to wait for a condition, mostly spent loading assets
+const TIMEOUT: Duration = Duration::from_secs(120);
+const SERVER_ADDR: &str = "127.0.0.1:28154";
+const WINDOW_WIDTH: f32 = 1280.0;
+const WINDOW_HEIGHT: f32 = 720.0;
+pub const PASSWORD: &str = "password123";
+
+/// Gives each harness its own accounts and characters
+static NEXT_DATA_DIR: AtomicU32 = AtomicU32::new(0);
+
+pub struct Harness {
+    pub network: ChannelNetwork,
+    pub server: App,
+    pub clients: Vec<App>,
+    /// Whether each client can open connections
+    online: Vec<Arc<AtomicBool>>,
+    data_dir: PathBuf,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        Harness::with_settings(|_| {})
+    }
+
+    /// Server with default settings, changed by `configure`
+    pub fn with_settings(configure: impl FnOnce(&mut ServerSettings)) -> Self {
+        let data_dir_name = format!("mangovillage-test-{}-{}", std::process::id(), NEXT_DATA_DIR.fetch_add(1, Ordering::Relaxed));
+        let data_dir = std::env::temp_dir().join(data_dir_name);
+        let mut settings = ServerSettings::default();
+        settings.network.bind_address = SERVER_ADDR.to_string();
+        settings.accounts.path = data_dir.join("accounts.toml");
+        settings.persistence.characters_dir = data_dir.join("characters");
+        configure(&mut settings);
+        settings.validate().expect("invalid server settings");
+
+        let network = ChannelNetwork::new(LinkConditions::default(), 0);
+        let transport = network.listen(settings.network.bind_address.parse().unwrap()).unwrap();
+        let stats = NetworkStats::default();
+        let transport = MeteredTransport::new(Box::new(transport), stats.clone());
+        let mut server = App::new();
+        server
+            .add_plugins((MinimalPlugins, HeadlessPlugins))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(FRAME))
+            // Used instead of a durian socket
+            .insert_resource(ServerPacketManager { manager: Box::new(transport) })
+            .insert_resource(stats)
+            .add_plugins(GameServerPlugin { settings });
+        Harness { network, server, clients: Vec::new(), online: Vec::new(), data_dir }
+    }
+
+    /// Add a client that logs in as `username`, registering if the account doesn't exist.  Returns its index.
+    pub fn add_client(&mut self, username: &str) -> usize {
+        self.add_client_with(Credentials { username: username.to_string(), password: PASSWORD.to_string(), register: true })
+    }
+
+    pub fn add_client_with(&mut self, credentials: Credentials) -> usize {
+        let index = self.clients.len();
+        let network = self.network.clone();
+        let online = Arc::new(AtomicBool::new(true));
+        self.online.push(online.clone());
+        let connector = Connector(Box::new(move |client_info: &ClientInfo| {
+            let server_addr: SocketAddr = client_info.server_addr.parse().unwrap();
+            if !online.load(Ordering::Relaxed) {
+                return Err(TransportError::Address(server_addr));
+            }
+            let transport = network.connect(client_info.client_addr.parse().unwrap(), server_addr)?;
+            Ok(Box::new(transport) as Box<dyn Transport>)
+        }));
+        let mut client = App::new();
+        client
+            .add_plugins(client_plugins().set(WindowPlugin {
+                // Never opened without winit, but player input reads the cursor position from it
+                primary_window: Some(Window { resolution: WindowResolution::new(WINDOW_WIDTH, WINDOW_HEIGHT), ..default() }),
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            }))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(FRAME))
+            .insert_resource(connector)
+            // Simulated time runs ahead of asset loading, which shouldn't look like a lost connection
+            .insert_resource(ReconnectSettings { timeout: 600.0, ..default() })
+            .insert_resource(ClientSettings::default())
+            .add_state::<ClientState>()
+            .add_plugins((
+                networking::ClientPlugin {
+                    client_addr: format!("127.0.0.1:{}", 5001 + index),
+                    server_addr: SERVER_ADDR.to_string(),
+                    credentials,
+                    conditions: LinkConditions::default(),
+                },
+                world::WorldPlugin,
+                physics::PhysicsPlugin,
+                player::PlayerPlugin,
+                replication::ReplicationPlugin,
+            ));
+        self.clients.push(client);
+        index
+    }
+
+    /// Advance the network, then update the server and every client by one frame
+    pub fn step(&mut self) {
+        self.network.advance(FRAME);
+        self.server.update();
+        for client in self.clients.iter_mut() {
+            client.update();
+        }
+    }
+
+    pub fn steps(&mut self, count: usize) {
+        for _ in 0..count {
+            self.step();
+        }
+    }
+
+    /// Step until `condition` holds, panicking with `description` if it doesn't within [`TIMEOUT`]
+    pub fn run_until(&mut self, description: &str, mut condition: impl FnMut(&mut Harness) -> bool) {
+        let started = Instant::now();
+        while !condition(self) {
+            assert!(started.elapsed() < TIMEOUT, "timed out waiting until {}", description);
+            self.step();
+            // Let asset loading tasks make progress
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    pub fn server_state(&self) -> ServerState {
+        self.server.world.resource::<State<ServerState>>().get().clone()
+    }
+
+    pub fn client_state(&self, client: usize) -> ClientState {
+        self.clients[client].world.resource::<State<ClientState>>().get().clone()
+    }
+
+    /// Step until every client has loaded the level and is playing
+    pub fn run_until_running(&mut self) {
+        self.run_until("every client is running", |harness| {
+            (0..harness.clients.len()).all(|client| harness.client_state(client) == ClientState::Running)
+        });
+    }
+
+    /// Persistent id the server gave the client's player, once it has joined
+    pub fn client_id(&self, client: usize) -> Option<u32> {
+        self.clients[client].world.get_resource::<ClientId>().map(|client_id| client_id.0)
+    }
+
+    /// Token the client resumes its session with
+    pub fn session_token(&self, client: usize) -> Option<u64> {
+        self.clients[client].world.resource::<Session>().token
+    }
+
+    /// Server entity of the player with this id
+    pub fn server_player(&mut self, id: u32) -> Option<Entity> {
+        let world = &mut self.server.world;
+        world
+            .query_filtered::<(Entity, &PlayerData), With<ServerPlayer>>()
+            .iter(world)
+            .find(|(_, player_data)| player_data.id == id)
+            .map(|(entity, _)| entity)
+    }
+
+    /// Whether the client can open connections.  Its current connection is left alone.
+    pub fn set_online(&mut self, client: usize, online: bool) {
+        self.online[client].store(online, Ordering::Relaxed);
+    }
+
+    /// Drop the client's connection as if it timed out, and keep it from reconnecting until it is set back online
+    pub fn lose_connection(&mut self, client: usize) {
+        self.set_online(client, false);
+        self.clients[client].world.resource_mut::<NextState<ClientState>>().set(ClientState::Reconnecting);
+    }
+
+    /// Players in the newest snapshot the client has reconstructed
+    pub fn latest_snapshot(&self, client: usize) -> Option<(u32, HashMap<u32, Player>)> {
+        let snapshots = self.clients[client].world.resource::<ReceivedSnapshots>();
+        snapshots.latest().map(|(tick, players)| (tick, players.clone()))
+    }
+
+    /// The client's own player in its newest snapshot
+    pub fn me_in_snapshot(&self, client: usize) -> Option<Player> {
+        let id = self.client_id(client)?;
+        self.latest_snapshot(client)?.1.get(&id).copied()
+    }
+
+    pub fn server_stats(&self) -> NetworkStats {
+        self.server.world.resource::<NetworkStats>().clone()
+    }
+
+    pub fn client_stats(&self, client: usize) -> NetworkStats {
+        self.clients[client].world.resource::<NetworkStats>().clone()
+    }
+
+    /// Ids of the players the client has spawned
+    pub fn spawned_players(&mut self, client: usize) -> Vec<u32> {
+        let world = &mut self.clients[client].world;
+        world.query::<&PlayerData>().iter(world).map(|player_data| player_data.id).collect()
+    }
+
+    /// Network ids of the replicated entities the client has, with their `C` if they have one
+    pub fn replicated<C: Component + Clone>(&mut self, client: usize) -> HashMap<u32, Option<C>> {
+        let world = &mut self.clients[client].world;
+        world.query::<(&NetworkId, Option<&C>)>().iter(world).map(|(network_id, component)| (network_id.0, component.cloned())).collect()
+    }
+
+    /// Sequence of the last Movement the client sent
+    pub fn last_input(&self, client: usize) -> u32 {
+        self.clients[client].world.resource::<PendingInputs>().last_sequence
+    }
+
+    /// Hold the move button down with the cursor `offset` from the window center, with y up
+    pub fn hold_move(&mut self, client: usize, offset: Vec2) {
+        let world = &mut self.clients[client].world;
+        let move_to = world.resource::<ClientSettings>().keybindings.move_to;
+        let mut windows = world.query_filtered::<&mut Window, With<PrimaryWindow>>();
+        let mut window = windows.single_mut(world);
+        let center = Vec2::new(window.width(), window.height()) / 2.0;
+        window.set_cursor_position(Some(Vec2::new(center.x + offset.x, center.y - offset.y)));
+        world.resource_mut::<Input<MouseButton>>().press(move_to);
+    }
+
+    pub fn release_move(&mut self, client: usize) {
+        let world = &mut self.clients[client].world;
+        let move_to = world.resource::<ClientSettings>().keybindings.move_to;
+        world.resource_mut::<Input<MouseButton>>().release(move_to);
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+/// Everything but windowing, rendering backends, input devices, audio and logging, which is global and set up once
+/// per process at most
+fn client_plugins() -> PluginGroupBuilder {
+    DefaultPlugins
+        .build()
+        .disable::<LogPlugin>()
+        .disable::<WinitPlugin>()
+        .disable::<GilrsPlugin>()
+        .disable::<AudioPlugin>()
+        .set(RenderPlugin { wgpu_settings: WgpuSettings { backends: None, ..default() } })
+        .add_before::<AssetPlugin, _>(EmbeddedAssetPlugin)
+}
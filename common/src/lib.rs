@@ -2,6 +2,7 @@ pub mod component;
 pub mod networking;
 pub mod physics;
 pub mod player;
+pub mod replication;
 pub mod resource;
 pub mod util;
 pub mod world;
@@ -1,8 +1,29 @@
 use durian::bincode_packet;
+use serde::{Deserialize, Serialize};
 
 /// Connect to server
 #[bincode_packet]
-pub struct Connect;
+pub struct Connect {
+    /// Client's [`PROTOCOL_VERSION`](crate::networking::PROTOCOL_VERSION)
+    pub protocol_version: u32,
+    /// Client's [`BUILD_HASH`](crate::networking::BUILD_HASH)
+    pub build_hash: String,
+    /// Client's [`SCHEMA_FINGERPRINT`](crate::networking::registry::SCHEMA_FINGERPRINT)
+    pub schema_fingerprint: u64,
+    pub credentials: Credentials,
+    /// Token from an earlier [`ConnectAck`](crate::networking::server_packets::ConnectAck), to reclaim our player after
+    /// losing connection
+    pub session_token: Option<u64>,
+}
+
+/// Account to log in as.  Reclaiming the account's player after losing connection also takes the session token.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+    /// Create the account if it does not exist, if the server allows registration
+    pub register: bool,
+}
 
 /// For graceful disconnects
 #[bincode_packet]
@@ -13,4 +34,26 @@ pub struct Disconnect;
 pub struct Movement {
     /// x, y
     pub translation: [f32; 2],
+    /// Increments with each Movement sent, so the server can report back which inputs it has processed
+    pub sequence: u32,
+}
+
+/// Acknowledges the latest [`Players`](crate::networking::server_packets::Players) snapshot the client reconstructed, so
+/// the server can diff future snapshots against it
+#[bincode_packet]
+pub struct SnapshotAck {
+    pub tick: u32,
+}
+
+/// Acknowledges the latest [`Replication`](crate::networking::server_packets::Replication) the client applied, so the
+/// server can diff future ones against it
+#[bincode_packet]
+pub struct ReplicationAck {
+    pub tick: u32,
+}
+
+/// Answers a [`Ping`](crate::networking::server_packets::Ping) right away, so the server can measure round trip time
+#[bincode_packet]
+pub struct Pong {
+    pub id: u32,
 }
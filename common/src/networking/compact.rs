@@ -0,0 +1,193 @@
+//! Compact encodings for replicating transforms.
+//!
+//! Positions are fixed-point relative to the level's [`LevelBounds`], rotations use the "smallest three" quaternion
+//! encoding, and scales are an index into [`SCALES`].  An encoded transform is 11 bytes instead of 40.
+
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use bevy::math::{Quat, Vec3};
+use bevy::prelude::Transform;
+use serde::{Deserialize, Serialize};
+
+use crate::resource::LevelBounds;
+
+/// Bits per component of the three smallest quaternion components
+const ROTATION_COMPONENT_BITS: u32 = 10;
+const ROTATION_COMPONENT_MAX: u32 = (1 << ROTATION_COMPONENT_BITS) - 1;
+
+/// Scales that can be replicated
+pub const SCALES: [f32; 8] = [0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 3.0, 4.0];
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CompactTransform {
+    /// x, y, z fixed-point within the level bounds
+    pub position: [u16; 3],
+    /// See [`encode_rotation`]
+    pub rotation: u32,
+    /// Index into [`SCALES`]
+    pub scale: u8,
+}
+
+impl CompactTransform {
+    pub fn from_transform(transform: &Transform, bounds: &LevelBounds) -> Self {
+        CompactTransform {
+            position: encode_position(transform.translation, bounds),
+            rotation: encode_rotation(transform.rotation),
+            // Only uniform scales are replicated
+            scale: encode_scale(transform.scale.x),
+        }
+    }
+
+    pub fn to_transform(&self, bounds: &LevelBounds) -> Transform {
+        Transform { translation: self.translation(bounds), rotation: self.rotation(), scale: Vec3::splat(self.scale()) }
+    }
+
+    pub fn translation(&self, bounds: &LevelBounds) -> Vec3 {
+        decode_position(self.position, bounds)
+    }
+
+    pub fn rotation(&self) -> Quat {
+        decode_rotation(self.rotation)
+    }
+
+    pub fn scale(&self) -> f32 {
+        decode_scale(self.scale)
+    }
+}
+
+/// Encode a position as 16 bit fixed-point per axis relative to the bounds.  Positions outside the bounds are clamped.
+pub fn encode_position(position: Vec3, bounds: &LevelBounds) -> [u16; 3] {
+    let normalized = (position - Vec3::from(bounds.min)) / bounds.size();
+    let quantized = (normalized.clamp(Vec3::ZERO, Vec3::ONE) * u16::MAX as f32).round();
+    [quantized.x as u16, quantized.y as u16, quantized.z as u16]
+}
+
+pub fn decode_position(position: [u16; 3], bounds: &LevelBounds) -> Vec3 {
+    let normalized = Vec3::new(position[0] as f32, position[1] as f32, position[2] as f32) / u16::MAX as f32;
+    Vec3::from(bounds.min) + normalized * bounds.size()
+}
+
+/// Encode a rotation with the "smallest three" method.  The largest quaternion component is dropped and rebuilt from
+/// the other three, which are each stored in 10 bits.  The top 2 bits hold the index of the dropped component.
+pub fn encode_rotation(rotation: Quat) -> u32 {
+    let components = rotation.normalize().to_array();
+    let largest = (0..4).max_by(|a, b| components[*a].abs().total_cmp(&components[*b].abs())).unwrap();
+    // q and -q are the same rotation, so flip the sign to keep the dropped component positive
+    let sign = if components[largest] < 0.0 { -1.0 } else { 1.0 };
+    let mut packed = largest as u32;
+    for (i, component) in components.iter().enumerate() {
+        if i != largest {
+            // The smallest three are always within +-1/sqrt(2)
+            let normalized = (component * sign / FRAC_1_SQRT_2) * 0.5 + 0.5;
+            let quantized = (normalized * ROTATION_COMPONENT_MAX as f32).round().clamp(0.0, ROTATION_COMPONENT_MAX as f32) as u32;
+            packed = (packed << ROTATION_COMPONENT_BITS) | quantized;
+        }
+    }
+    packed
+}
+
+pub fn decode_rotation(packed: u32) -> Quat {
+    let largest = (packed >> (ROTATION_COMPONENT_BITS * 3)) as usize;
+    let mut components = [0.0; 4];
+    let mut sum_squares = 0.0;
+    // Components were packed in order, so the last one is in the lowest bits
+    let mut shift = ROTATION_COMPONENT_BITS * 3;
+    for (i, component) in components.iter_mut().enumerate() {
+        if i != largest {
+            shift -= ROTATION_COMPONENT_BITS;
+            let quantized = (packed >> shift) & ROTATION_COMPONENT_MAX;
+            *component = ((quantized as f32 / ROTATION_COMPONENT_MAX as f32) - 0.5) * 2.0 * FRAC_1_SQRT_2;
+            sum_squares += *component * *component;
+        }
+    }
+    components[largest] = (1.0 - sum_squares).max(0.0).sqrt();
+    Quat::from_array(components).normalize()
+}
+
+/// Index of the closest scale in [`SCALES`]
+pub fn encode_scale(scale: f32) -> u8 {
+    (0..SCALES.len()).min_by(|a, b| (SCALES[*a] - scale).abs().total_cmp(&(SCALES[*b] - scale).abs())).unwrap() as u8
+}
+
+pub fn decode_scale(index: u8) -> f32 {
+    SCALES.get(index as usize).copied().unwrap_or(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds() -> LevelBounds {
+        LevelBounds { min: [-2048.0, -2048.0, -512.0], max: [2048.0, 2048.0, 1536.0] }
+    }
+
+    #[test]
+    fn position_round_trip_within_half_step() {
+        let bounds = bounds();
+        // Half of one fixed-point step per axis
+        let max_error = bounds.size() / u16::MAX as f32 / 2.0;
+        for position in [Vec3::ZERO, Vec3::new(-10.0, 0.0, 150.0), Vec3::new(2047.9, -2047.9, -511.9), Vec3::new(123.456, -789.012, 345.678)] {
+            let error = (decode_position(encode_position(position, &bounds), &bounds) - position).abs();
+            assert!(error.cmple(max_error + Vec3::splat(1e-4)).all(), "position {} had error {}", position, error);
+        }
+    }
+
+    #[test]
+    fn position_outside_bounds_is_clamped() {
+        let bounds = bounds();
+        assert_eq!(decode_position(encode_position(Vec3::splat(-10000.0), &bounds), &bounds), Vec3::from(bounds.min));
+        assert_eq!(decode_position(encode_position(Vec3::splat(10000.0), &bounds), &bounds), Vec3::from(bounds.max));
+    }
+
+    #[test]
+    fn rotation_round_trip_error() {
+        let mut max_angle: f32 = 0.0;
+        for yaw in 0..24 {
+            for pitch in 0..12 {
+                for roll in 0..12 {
+                    let rotation = Quat::from_euler(bevy::math::EulerRot::ZYX, yaw as f32 * 0.27, pitch as f32 * 0.53, roll as f32 * 0.53);
+                    let decoded = decode_rotation(encode_rotation(rotation));
+                    max_angle = max_angle.max(rotation.angle_between(decoded));
+                }
+            }
+        }
+        // 10 bits per component keeps the error well under a degree
+        assert!(max_angle < 0.01, "max rotation error was {} radians", max_angle);
+    }
+
+    #[test]
+    fn rotation_sign_does_not_matter() {
+        let rotation = Quat::from_rotation_z(1.0);
+        assert_eq!(encode_rotation(rotation), encode_rotation(-rotation));
+    }
+
+    #[test]
+    fn player_facing_round_trip() {
+        let mut transform = Transform::default();
+        transform.look_to(Vec3::new(1.0, -2.0, 0.0), Vec3::Z);
+        let decoded = decode_rotation(encode_rotation(transform.rotation));
+        assert!((decoded * Vec3::NEG_Z).angle_between(transform.forward()) < 0.01);
+    }
+
+    #[test]
+    fn scale_round_trip() {
+        for (index, scale) in SCALES.iter().enumerate() {
+            assert_eq!(encode_scale(*scale), index as u8);
+            assert_eq!(decode_scale(encode_scale(*scale)), *scale);
+        }
+        // Closest scale
+        assert_eq!(decode_scale(encode_scale(1.1)), 1.0);
+        assert_eq!(decode_scale(encode_scale(100.0)), 4.0);
+    }
+
+    #[test]
+    fn compact_transform_round_trip() {
+        let bounds = bounds();
+        let mut transform = Transform::from_xyz(-10.0, 0.0, 150.0).with_scale(Vec3::splat(2.0));
+        transform.look_to(Vec3::NEG_Y, Vec3::Z);
+        let decoded = CompactTransform::from_transform(&transform, &bounds).to_transform(&bounds);
+        assert!(decoded.translation.distance(transform.translation) < 0.1);
+        assert!(decoded.rotation.angle_between(transform.rotation) < 0.01);
+        assert_eq!(decoded.scale, transform.scale);
+    }
+}
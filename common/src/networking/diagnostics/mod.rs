@@ -0,0 +1,76 @@
+//! Bandwidth, packet rates, drops and round trip times, for both client and server
+
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics, RegisterDiagnostic};
+use bevy::prelude::*;
+
+use crate::networking::diagnostics::resource::{ConnectionStats, NetworkStats};
+
+pub mod resource;
+
+/// Seconds between measurements, so rates aren't computed from single frames
+const MEASURE_INTERVAL: f64 = 1.0;
+/// Measurements averaged over
+const HISTORY_LENGTH: usize = 10;
+
+/// Reports the totals in [`NetworkStats`] through Bevy's [`Diagnostics`], so `LogDiagnosticsPlugin` prints them.  Per
+/// connection and per packet type numbers are read from [`NetworkStats`] directly.
+pub struct NetworkDiagnosticsPlugin;
+
+impl NetworkDiagnosticsPlugin {
+    pub const BYTES_SENT: DiagnosticId = DiagnosticId::from_u128(0x6d0b_4c1e_2a8f_4f6b_9e35_0c7a_1d52_e801);
+    pub const BYTES_RECEIVED: DiagnosticId = DiagnosticId::from_u128(0x6d0b_4c1e_2a8f_4f6b_9e35_0c7a_1d52_e802);
+    pub const PACKETS_SENT: DiagnosticId = DiagnosticId::from_u128(0x6d0b_4c1e_2a8f_4f6b_9e35_0c7a_1d52_e803);
+    pub const PACKETS_RECEIVED: DiagnosticId = DiagnosticId::from_u128(0x6d0b_4c1e_2a8f_4f6b_9e35_0c7a_1d52_e804);
+    pub const PACKETS_DROPPED: DiagnosticId = DiagnosticId::from_u128(0x6d0b_4c1e_2a8f_4f6b_9e35_0c7a_1d52_e805);
+    pub const RTT: DiagnosticId = DiagnosticId::from_u128(0x6d0b_4c1e_2a8f_4f6b_9e35_0c7a_1d52_e806);
+    pub const CONNECTIONS: DiagnosticId = DiagnosticId::from_u128(0x6d0b_4c1e_2a8f_4f6b_9e35_0c7a_1d52_e807);
+
+    /// Every diagnostic this plugin reports, e.g. to filter `LogDiagnosticsPlugin` with
+    pub const DIAGNOSTICS: [DiagnosticId; 7] = [
+        Self::BYTES_SENT,
+        Self::BYTES_RECEIVED,
+        Self::PACKETS_SENT,
+        Self::PACKETS_RECEIVED,
+        Self::PACKETS_DROPPED,
+        Self::RTT,
+        Self::CONNECTIONS,
+    ];
+}
+
+impl Plugin for NetworkDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetworkStats>()
+            .register_diagnostic(Diagnostic::new(Self::BYTES_SENT, "network_bytes_sent", HISTORY_LENGTH).with_suffix(" B/s"))
+            .register_diagnostic(Diagnostic::new(Self::BYTES_RECEIVED, "network_bytes_received", HISTORY_LENGTH).with_suffix(" B/s"))
+            .register_diagnostic(Diagnostic::new(Self::PACKETS_SENT, "network_packets_sent", HISTORY_LENGTH).with_suffix("/s"))
+            .register_diagnostic(Diagnostic::new(Self::PACKETS_RECEIVED, "network_packets_received", HISTORY_LENGTH).with_suffix("/s"))
+            .register_diagnostic(Diagnostic::new(Self::PACKETS_DROPPED, "network_packets_dropped", HISTORY_LENGTH).with_suffix("/s"))
+            .register_diagnostic(Diagnostic::new(Self::RTT, "network_rtt", HISTORY_LENGTH).with_suffix(" ms"))
+            .register_diagnostic(Diagnostic::new(Self::CONNECTIONS, "network_connections", HISTORY_LENGTH))
+            .add_systems(Last, measure);
+    }
+}
+
+fn measure(mut diagnostics: Diagnostics, stats: Res<NetworkStats>, time: Res<Time>, mut last: Local<Option<(f64, ConnectionStats)>>) {
+    let now = time.elapsed_seconds_f64();
+    let Some((last_measured, last_totals)) = &*last else {
+        *last = Some((now, stats.totals()));
+        return;
+    };
+    let elapsed = now - last_measured;
+    if elapsed < MEASURE_INTERVAL {
+        return;
+    }
+    let totals = stats.totals();
+    let rate = |current: u64, previous: u64| current.saturating_sub(previous) as f64 / elapsed;
+    diagnostics.add_measurement(NetworkDiagnosticsPlugin::BYTES_SENT, || rate(totals.sent.bytes, last_totals.sent.bytes));
+    diagnostics.add_measurement(NetworkDiagnosticsPlugin::BYTES_RECEIVED, || rate(totals.received.bytes, last_totals.received.bytes));
+    diagnostics.add_measurement(NetworkDiagnosticsPlugin::PACKETS_SENT, || rate(totals.sent.packets, last_totals.sent.packets));
+    diagnostics.add_measurement(NetworkDiagnosticsPlugin::PACKETS_RECEIVED, || rate(totals.received.packets, last_totals.received.packets));
+    diagnostics.add_measurement(NetworkDiagnosticsPlugin::PACKETS_DROPPED, || rate(totals.dropped, last_totals.dropped));
+    if let Some(rtt) = totals.rtt {
+        diagnostics.add_measurement(NetworkDiagnosticsPlugin::RTT, || rtt.as_secs_f64() * 1000.0);
+    }
+    diagnostics.add_measurement(NetworkDiagnosticsPlugin::CONNECTIONS, || stats.connections().len() as f64);
+    *last = Some((now, totals));
+}
@@ -0,0 +1,122 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use bevy::prelude::Resource;
+use bevy::utils::HashMap;
+
+/// Packets and bytes in one direction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub packets: u64,
+    /// Encoded packet sizes, not counting transport overhead
+    pub bytes: u64,
+}
+
+impl Traffic {
+    fn record(&mut self, bytes: u64) {
+        self.packets += 1;
+        self.bytes += bytes;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PacketTypeStats {
+    pub sent: Traffic,
+    pub received: Traffic,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionStats {
+    pub sent: Traffic,
+    pub received: Traffic,
+    /// Packets that failed to send, or were thrown away on arrival like the server does with invalid ones
+    pub dropped: u64,
+    /// Latest round trip time measured.  For the totals, the mean over open connections.
+    pub rtt: Option<Duration>,
+    /// By packet name
+    pub packet_types: HashMap<&'static str, PacketTypeStats>,
+}
+
+impl ConnectionStats {
+    fn record_sent(&mut self, name: &'static str, bytes: u64) {
+        self.sent.record(bytes);
+        self.packet_types.entry(name).or_default().sent.record(bytes);
+    }
+
+    fn record_received(&mut self, name: &'static str, bytes: u64) {
+        self.received.record(bytes);
+        self.packet_types.entry(name).or_default().received.record(bytes);
+    }
+}
+
+/// Traffic counters for every connection, shared between the [`MeteredTransport`](crate::networking::transport::MeteredTransport)
+/// recording them and the game, UI or admin tools reading them.  Counters only grow, but a connection's are forgotten
+/// once it closes.  The totals keep counting across connections.
+#[derive(Resource, Clone, Default)]
+pub struct NetworkStats(Arc<Mutex<StatsState>>);
+
+#[derive(Default)]
+struct StatsState {
+    connections: HashMap<u32, ConnectionStats>,
+    totals: ConnectionStats,
+}
+
+impl NetworkStats {
+    pub fn record_sent(&self, remote_id: u32, name: &'static str, bytes: u64) {
+        let mut state = self.lock();
+        state.connections.entry(remote_id).or_default().record_sent(name, bytes);
+        state.totals.record_sent(name, bytes);
+    }
+
+    pub fn record_received(&self, remote_id: u32, name: &'static str, bytes: u64) {
+        let mut state = self.lock();
+        state.connections.entry(remote_id).or_default().record_received(name, bytes);
+        state.totals.record_received(name, bytes);
+    }
+
+    pub fn record_dropped(&self, remote_id: u32) {
+        let mut state = self.lock();
+        // Failed sends to closed connections shouldn't bring them back
+        if let Some(connection) = state.connections.get_mut(&remote_id) {
+            connection.dropped += 1;
+        }
+        state.totals.dropped += 1;
+    }
+
+    pub fn record_rtt(&self, remote_id: u32, rtt: Duration) {
+        self.lock().connections.entry(remote_id).or_default().rtt = Some(rtt);
+    }
+
+    /// Forget connections that are no longer open
+    pub fn retain_connections(&self, open: &[u32]) {
+        self.lock().connections.retain(|remote_id, _| open.contains(remote_id));
+    }
+
+    pub fn forget_connection(&self, remote_id: u32) {
+        self.lock().connections.remove(&remote_id);
+    }
+
+    pub fn totals(&self) -> ConnectionStats {
+        let state = self.lock();
+        let rtts: Vec<Duration> = state.connections.values().filter_map(|connection| connection.rtt).collect();
+        let mean_rtt = (!rtts.is_empty()).then(|| rtts.iter().sum::<Duration>() / rtts.len() as u32);
+        ConnectionStats { rtt: mean_rtt, ..state.totals.clone() }
+    }
+
+    pub fn connection(&self, remote_id: u32) -> Option<ConnectionStats> {
+        self.lock().connections.get(&remote_id).cloned()
+    }
+
+    /// Every open connection that has seen traffic, by remote id
+    pub fn connections(&self) -> Vec<(u32, ConnectionStats)> {
+        let mut connections: Vec<(u32, ConnectionStats)> =
+            self.lock().connections.iter().map(|(remote_id, connection)| (*remote_id, connection.clone())).collect();
+        connections.sort_by_key(|(remote_id, _)| *remote_id);
+        connections
+    }
+
+    fn lock(&self) -> MutexGuard<StatsState> {
+        // Counters are still usable if a holder panicked
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
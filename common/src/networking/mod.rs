@@ -1,2 +1,17 @@
 pub mod client_packets;
-pub mod server_packets;
\ No newline at end of file
+pub mod compact;
+pub mod diagnostics;
+pub mod registry;
+pub mod server_packets;
+pub mod transport;
+
+/// Wire protocol version.  Bump this whenever a packet's layout changes so stale clients are rejected on Connect instead
+/// of silently desyncing.  Changes to the packet registration order are caught by [`registry::SCHEMA_FINGERPRINT`].
+pub const PROTOCOL_VERSION: u32 = 16;
+
+/// Identifies the build a client or server was compiled from.  Set `MANGOVILLAGE_BUILD_HASH` at build time (e.g. to the
+/// git commit) for more precise reporting, otherwise this falls back to the crate version.
+pub const BUILD_HASH: &str = match option_env!("MANGOVILLAGE_BUILD_HASH") {
+    Some(hash) => hash,
+    None => env!("CARGO_PKG_VERSION"),
+};
@@ -0,0 +1,81 @@
+//! Single source of truth for every packet and the direction it travels in.
+//!
+//! durian assigns packet IDs in registration order, so the client and server must register packets in exactly the same
+//! order.  Both sides register through the functions generated here so the order can never drift between them.
+
+use durian::{register_receive, register_send, PacketManager};
+
+use crate::networking::client_packets::*;
+use crate::networking::server_packets::*;
+use crate::networking::transport::PacketTypes;
+use crate::util;
+
+/// Declares every packet once and generates the registration for both sides, along with the schema fingerprint.
+macro_rules! packet_registry {
+    (
+        client_to_server: [$(($c2s:ident, $c2s_builder:ident)),* $(,)?],
+        server_to_client: [$(($s2c:ident, $s2c_builder:ident)),* $(,)?] $(,)?
+    ) => {
+        /// Human readable layout of the registry, in registration order
+        pub const SCHEMA: &str = concat!("client_to_server:", $(stringify!($c2s), ",",)* ";server_to_client:", $(stringify!($s2c), ",",)*);
+
+        /// Fingerprint of [`SCHEMA`], exchanged on Connect so both sides can verify they registered the same packets
+        pub const SCHEMA_FINGERPRINT: u64 = fingerprint(SCHEMA.as_bytes());
+
+        /// Register server side packets: receive everything clients send, send everything the server sends
+        pub fn register_server_packets(manager: &mut PacketManager) -> bool {
+            let receives = util::validate_register_results(false, register_receive!(manager, $(($c2s, $c2s_builder)),*));
+            let sends = util::validate_register_results(false, register_send!(manager, $($s2c),*));
+            receives && sends
+        }
+
+        /// Register client side packets: receive everything the server sends, send everything clients send
+        pub fn register_client_packets(manager: &mut PacketManager) -> bool {
+            let receives = util::validate_register_results(true, register_receive!(manager, $(($s2c, $s2c_builder)),*));
+            let sends = util::validate_register_results(true, register_send!(manager, $($c2s),*));
+            receives && sends
+        }
+
+        /// Every packet in both directions, for [`Transport`](crate::networking::transport::Transport)s
+        pub fn packet_types() -> PacketTypes {
+            let mut types = PacketTypes::default();
+            $(types.insert::<$c2s, $c2s_builder>(stringify!($c2s));)*
+            $(types.insert::<$s2c, $s2c_builder>(stringify!($s2c));)*
+            types
+        }
+    };
+}
+
+// Connect and ConnectAck/ConnectReject must stay first in their lists so the handshake can still be decoded, and a
+// mismatch reported, by builds whose registries differ.
+packet_registry! {
+    client_to_server: [
+        (Connect, ConnectPacketBuilder),
+        (Disconnect, DisconnectPacketBuilder),
+        (Movement, MovementPacketBuilder),
+        (SnapshotAck, SnapshotAckPacketBuilder),
+        (Pong, PongPacketBuilder),
+        (ReplicationAck, ReplicationAckPacketBuilder),
+    ],
+    server_to_client: [
+        (ConnectAck, ConnectAckPacketBuilder),
+        (ConnectReject, ConnectRejectPacketBuilder),
+        (SpawnScene, SpawnScenePacketBuilder),
+        (Players, PlayersPacketBuilder),
+        (PlayersLeft, PlayersLeftPacketBuilder),
+        (Replication, ReplicationPacketBuilder),
+        (Ping, PingPacketBuilder),
+    ],
+}
+
+/// FNV-1a, so the fingerprint can be computed at compile time
+pub(crate) const fn fingerprint(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
@@ -1,33 +1,232 @@
+use std::fmt::{Display, Formatter};
+
 use bevy::prelude::Component;
 use durian::bincode_packet;
 use serde::{Deserialize, Serialize};
 
+use crate::networking::compact::CompactTransform;
+use crate::player::movement::MovementSettings;
 use crate::resource::LevelInfo;
 
+// Every server packet carries the server tick it was sent at
+
 #[bincode_packet]
 pub struct ConnectAck {
-    /// Client's server ID
+    pub tick: u32,
+    /// Persistent id of the client's player
     pub id: u32,
+    /// Server simulation ticks per second
+    pub tick_rate: u32,
+    /// So client-side prediction moves players like the server does
+    pub movement: MovementSettings,
+    /// Send this in Connect to resume the session if the connection is lost
+    pub session_token: u64,
+}
+
+/// Sent instead of [`ConnectAck`] when the server refuses a client's Connect
+#[bincode_packet]
+#[derive(Debug)]
+pub struct ConnectReject {
+    pub tick: u32,
+    pub reason: RejectReason,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum RejectReason {
+    /// Client and server were built against different wire protocols
+    ProtocolMismatch { server_version: u32, client_version: u32 },
+    /// Client and server registered different packets, or registered them in a different order
+    SchemaMismatch { server_fingerprint: u64, client_fingerprint: u64 },
+    /// Unknown username or wrong password
+    InvalidCredentials,
+    /// Account does not exist and the server does not allow registration
+    RegistrationDisabled,
+    UsernameTaken,
+    /// Username must be 3 to 32 letters, digits or underscores
+    InvalidUsername,
+    PasswordTooShort { min_length: usize },
+    /// Server could not read or write its account store
+    ServerError,
+    ServerFull { max_players: usize },
+    /// Account already has a player, and the client did not have its session token
+    AlreadyLoggedIn,
+}
+
+impl Display for RejectReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RejectReason::ProtocolMismatch { server_version, client_version } => {
+                write!(f, "Client protocol version {} does not match server protocol version {}", client_version, server_version)
+            }
+            RejectReason::SchemaMismatch { server_fingerprint, client_fingerprint } => {
+                write!(f, "Client packet schema {:016x} does not match server packet schema {:016x}", client_fingerprint, server_fingerprint)
+            }
+            RejectReason::InvalidCredentials => write!(f, "Invalid username or password"),
+            RejectReason::RegistrationDisabled => write!(f, "Account does not exist and registration is disabled"),
+            RejectReason::UsernameTaken => write!(f, "Username is already taken"),
+            RejectReason::InvalidUsername => write!(f, "Username must be 3 to 32 letters, digits or underscores"),
+            RejectReason::PasswordTooShort { min_length } => write!(f, "Password must be at least {} characters", min_length),
+            RejectReason::ServerError => write!(f, "Server error, try again later"),
+            RejectReason::ServerFull { max_players } => write!(f, "Server is full ({} players)", max_players),
+            RejectReason::AlreadyLoggedIn => write!(f, "Account is already logged in"),
+        }
+    }
+}
+
+/// Sent to each client every so often to measure round trip time, see [`Pong`](crate::networking::client_packets::Pong)
+#[bincode_packet]
+pub struct Ping {
+    pub tick: u32,
+    pub id: u32,
+    /// Round trip time to the client the server last measured, so clients know theirs without pinging
+    pub rtt_ms: Option<f32>,
 }
 
 #[bincode_packet]
 #[derive(Debug)]
 pub struct SpawnScene {
+    pub tick: u32,
     pub level: LevelInfo,
 }
 
+/// Snapshot of the players within the client's area of interest, as a delta against a baseline snapshot the client has
+/// acknowledged with [`SnapshotAck`](crate::networking::client_packets::SnapshotAck)
 #[bincode_packet]
 pub struct Players {
-    pub players: Vec<Player>,
+    /// Server tick this snapshot was taken at
+    pub tick: u32,
+    /// Tick of the snapshot this one is diffed against, or `None` if this is a full snapshot
+    pub baseline: Option<u32>,
+    /// Players that aren't in the baseline
+    pub added: Vec<Player>,
+    /// Players in the baseline that changed
+    pub changed: Vec<PlayerDelta>,
+    /// Players in the baseline that are no longer in the snapshot, and why
+    pub removed: Vec<PlayerLeft>,
+}
+
+/// Tells a client its own player is being removed from the server, right before its connection is closed.  Other clients
+/// learn about players leaving from the [`Players`] snapshots they acknowledge.
+#[bincode_packet]
+pub struct PlayersLeft {
+    pub tick: u32,
+    pub players: Vec<PlayerLeft>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct PlayerLeft {
+    pub id: u32,
+    pub reason: LeaveReason,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LeaveReason {
+    /// Player sent a Disconnect
+    Disconnected,
+    /// Player's connection was lost without a Disconnect
+    TimedOut,
+    /// Server removed the player
+    Kicked,
+    /// Player is still connected, but moved out of the client's area of interest
+    OutOfRange,
+}
+
+impl Display for LeaveReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LeaveReason::Disconnected => write!(f, "disconnected"),
+            LeaveReason::TimedOut => write!(f, "timed out"),
+            LeaveReason::Kicked => write!(f, "kicked"),
+            LeaveReason::OutOfRange => write!(f, "out of range"),
+        }
+    }
+}
+
+/// Spawns, updates and despawns of [`Replicated`](crate::replication::component::Replicated) entities within the client's
+/// area of interest, as a delta against a baseline the client has acknowledged with
+/// [`ReplicationAck`](crate::networking::client_packets::ReplicationAck)
+#[bincode_packet]
+pub struct Replication {
+    pub tick: u32,
+    /// Tick of the acknowledged packet this one is diffed against, or `None` if it carries everything
+    pub baseline: Option<u32>,
+    /// Entities that aren't in the baseline, with all of their components
+    pub spawned: Vec<EntityState>,
+    /// Entities in the baseline, with only the components that changed
+    pub updated: Vec<EntityState>,
+    /// Network ids of entities in the baseline that the client should despawn
+    pub despawned: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EntityState {
+    /// [`NetworkId`](crate::replication::component::NetworkId) of the entity
+    pub id: u32,
+    /// Kind and serialized value of each component, see [`ReplicationRegistry`](crate::replication::ReplicationRegistry)
+    pub components: Vec<(u32, Vec<u8>)>,
+    /// Kinds of components that were removed
+    pub removed: Vec<u32>,
 }
 
 // TODO: optimize so we can use Copy
 #[derive(Component, Serialize, Deserialize, Copy, Clone)]
 pub struct Player {
-    /// Client's server ID
+    /// Persistent id of the player, see [`PlayerData::id`](crate::player::component::PlayerData::id)
     pub id: u32,
     pub handle_id: u8,
-    // x, y, z
-    pub transform: [f32; 3],
-    pub scale: f32,
+    pub transform: CompactTransform,
+    /// Sequence of the last [`Movement`](crate::networking::client_packets::Movement) the server processed for this player
+    pub last_input: u32,
+}
+
+impl Player {
+    /// Fields that changed from `baseline`, or `None` if nothing changed
+    pub fn diff(&self, baseline: &Player) -> Option<PlayerDelta> {
+        let delta = PlayerDelta {
+            id: self.id,
+            handle_id: (self.handle_id != baseline.handle_id).then_some(self.handle_id),
+            position: (self.transform.position != baseline.transform.position).then_some(self.transform.position),
+            rotation: (self.transform.rotation != baseline.transform.rotation).then_some(self.transform.rotation),
+            scale: (self.transform.scale != baseline.transform.scale).then_some(self.transform.scale),
+            last_input: (self.last_input != baseline.last_input).then_some(self.last_input),
+        };
+        let unchanged = delta.handle_id.is_none()
+            && delta.position.is_none()
+            && delta.rotation.is_none()
+            && delta.scale.is_none()
+            && delta.last_input.is_none();
+        (!unchanged).then_some(delta)
+    }
+}
+
+/// Changed fields of a [`Player`], unchanged fields are `None`.  Transform fields are compared after quantization, so
+/// movement too small to be replicated doesn't get sent.
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct PlayerDelta {
+    pub id: u32,
+    pub handle_id: Option<u8>,
+    pub position: Option<[u16; 3]>,
+    pub rotation: Option<u32>,
+    pub scale: Option<u8>,
+    pub last_input: Option<u32>,
+}
+
+impl PlayerDelta {
+    pub fn apply(&self, player: &mut Player) {
+        if let Some(handle_id) = self.handle_id {
+            player.handle_id = handle_id;
+        }
+        if let Some(position) = self.position {
+            player.transform.position = position;
+        }
+        if let Some(rotation) = self.rotation {
+            player.transform.rotation = rotation;
+        }
+        if let Some(scale) = self.scale {
+            player.transform.scale = scale;
+        }
+        if let Some(last_input) = self.last_input {
+            player.last_input = last_input;
+        }
+    }
 }
@@ -0,0 +1,336 @@
+use std::any::TypeId;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use bevy::log::warn;
+use bevy::utils::HashMap;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::networking::registry;
+use crate::networking::transport::{AnyPacket, LinkConditions, PacketTypes, Received, Transport, TransportError};
+
+/// In-process network that [`ChannelTransport`]s connect over, so whole sessions can run without sockets.  Time only
+/// passes on [`advance`](Self::advance) and randomness is seeded, so runs are reproducible.
+#[derive(Clone)]
+pub struct ChannelNetwork {
+    state: Arc<Mutex<NetworkState>>,
+}
+
+struct NetworkState {
+    now: Duration,
+    conditions: LinkConditions,
+    rng: StdRng,
+    /// Orders packets that arrive at the same time by when they were sent
+    next_sequence: u64,
+    endpoints: HashMap<SocketAddr, Endpoint>,
+    types: PacketTypes,
+}
+
+#[derive(Default)]
+struct Endpoint {
+    listening: bool,
+    next_remote_id: u32,
+    /// Open connections by remote id
+    remotes: HashMap<u32, SocketAddr>,
+    in_flight: Vec<InFlight>,
+}
+
+struct InFlight {
+    deliver_at: Duration,
+    sequence: u64,
+    from: SocketAddr,
+    type_id: TypeId,
+    bytes: Vec<u8>,
+}
+
+impl ChannelNetwork {
+    pub fn new(conditions: LinkConditions, seed: u64) -> Self {
+        let state = NetworkState {
+            now: Duration::ZERO,
+            conditions,
+            rng: StdRng::seed_from_u64(seed),
+            next_sequence: 0,
+            endpoints: HashMap::default(),
+            types: registry::packet_types(),
+        };
+        ChannelNetwork { state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// Applies to packets sent from now on
+    pub fn set_conditions(&self, conditions: LinkConditions) {
+        self.lock().conditions = conditions;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.lock().now += duration;
+    }
+
+    /// Accept connections on `addr`, like a server
+    pub fn listen(&self, addr: SocketAddr) -> Result<ChannelTransport, TransportError> {
+        let mut state = self.lock();
+        if state.endpoints.contains_key(&addr) {
+            return Err(TransportError::Address(addr));
+        }
+        state.endpoints.insert(addr, Endpoint { listening: true, ..Endpoint::default() });
+        Ok(ChannelTransport { network: self.clone(), addr })
+    }
+
+    /// Connect from `client_addr` to a transport listening on `server_addr`.  The server is remote id 0 to the client.
+    pub fn connect(&self, client_addr: SocketAddr, server_addr: SocketAddr) -> Result<ChannelTransport, TransportError> {
+        let mut state = self.lock();
+        if state.endpoints.contains_key(&client_addr) {
+            return Err(TransportError::Address(client_addr));
+        }
+        let server = match state.endpoints.get_mut(&server_addr) {
+            Some(server) if server.listening => server,
+            _ => return Err(TransportError::Address(server_addr)),
+        };
+        let remote_id = server.next_remote_id;
+        server.next_remote_id += 1;
+        server.remotes.insert(remote_id, client_addr);
+        let mut client = Endpoint::default();
+        client.remotes.insert(0, server_addr);
+        state.endpoints.insert(client_addr, client);
+        Ok(ChannelTransport { network: self.clone(), addr: client_addr })
+    }
+
+    fn lock(&self) -> MutexGuard<NetworkState> {
+        // State stays consistent even if a holder panicked, since every change is made under one lock
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl NetworkState {
+    fn transmit(&mut self, from: SocketAddr, to: SocketAddr, type_id: TypeId, bytes: Vec<u8>) {
+        for delay in self.conditions.delays(&mut self.rng) {
+            let sequence = self.next_sequence;
+            self.next_sequence += 1;
+            if let Some(endpoint) = self.endpoints.get_mut(&to) {
+                endpoint.in_flight.push(InFlight { deliver_at: self.now + delay, sequence, from, type_id, bytes: bytes.clone() });
+            }
+        }
+    }
+}
+
+/// One end of a connection over a [`ChannelNetwork`].  Dropping it closes its connections.
+pub struct ChannelTransport {
+    network: ChannelNetwork,
+    addr: SocketAddr,
+}
+
+impl ChannelTransport {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn send_any(&mut self, remote_id: Option<u32>, type_id: TypeId, packet: AnyPacket) -> Result<(), TransportError> {
+        let mut state = self.network.lock();
+        let bytes = (state.types.get(type_id)?.encode)(packet.as_ref())?;
+        let endpoint = &state.endpoints[&self.addr];
+        let targets: Vec<SocketAddr> = match remote_id {
+            Some(remote_id) => vec![*endpoint.remotes.get(&remote_id).ok_or(TransportError::NotConnected(remote_id))?],
+            // Clients need their connection to the server, servers can have no clients
+            None if endpoint.remotes.is_empty() && !endpoint.listening => return Err(TransportError::NotConnected(0)),
+            None => endpoint.remotes.values().copied().collect(),
+        };
+        for to in targets {
+            state.transmit(self.addr, to, type_id, bytes.clone());
+        }
+        Ok(())
+    }
+
+    fn received_any(&mut self, type_id: TypeId, _blocking: bool) -> Result<Received<AnyPacket>, TransportError> {
+        let mut state = self.network.lock();
+        let now = state.now;
+        let decode = state.types.get(type_id)?.decode;
+        let endpoint = state.endpoints.get_mut(&self.addr).expect("channel endpoint removed while its transport is alive");
+        let (mut arrived, in_flight): (Vec<InFlight>, Vec<InFlight>) =
+            std::mem::take(&mut endpoint.in_flight).into_iter().partition(|packet| packet.type_id == type_id && packet.deliver_at <= now);
+        endpoint.in_flight = in_flight;
+        arrived.sort_by_key(|packet| (packet.deliver_at, packet.sequence));
+
+        let mut received: Received<AnyPacket> = endpoint.remotes.keys().map(|remote_id| (*remote_id, None)).collect();
+        received.sort_by_key(|(remote_id, _)| *remote_id);
+        for packet in arrived {
+            // Packets from closed connections were already dropped
+            let remote_id = endpoint.remotes.iter().find(|(_, addr)| **addr == packet.from).map(|(remote_id, _)| *remote_id);
+            if let Some((_, packets)) = received.iter_mut().find(|(id, _)| Some(*id) == remote_id) {
+                // Deliver the rest, which were already taken out of flight
+                match decode(&packet.bytes) {
+                    Ok(decoded) => packets.get_or_insert_with(Vec::new).push(decoded),
+                    Err(e) => warn!("Dropping packet from {} that could not be decoded: {}", packet.from, e),
+                }
+            }
+        }
+        Ok(received)
+    }
+
+    fn get_remote_address(&self, remote_id: u32) -> Option<SocketAddr> {
+        self.network.lock().endpoints.get(&self.addr).and_then(|endpoint| endpoint.remotes.get(&remote_id).copied())
+    }
+
+    fn close_connection(&mut self, remote_id: u32) -> Result<(), TransportError> {
+        let mut state = self.network.lock();
+        let endpoint = state.endpoints.get_mut(&self.addr).expect("channel endpoint removed while its transport is alive");
+        let remote_addr = endpoint.remotes.remove(&remote_id).ok_or(TransportError::NotConnected(remote_id))?;
+        endpoint.in_flight.retain(|packet| packet.from != remote_addr);
+        if let Some(remote) = state.endpoints.get_mut(&remote_addr) {
+            remote.remotes.retain(|_, addr| *addr != self.addr);
+            remote.in_flight.retain(|packet| packet.from != self.addr);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ChannelTransport {
+    fn drop(&mut self) {
+        let mut state = self.network.lock();
+        let Some(endpoint) = state.endpoints.remove(&self.addr) else {
+            return;
+        };
+        for remote_addr in endpoint.remotes.values() {
+            if let Some(remote) = state.endpoints.get_mut(remote_addr) {
+                remote.remotes.retain(|_, addr| *addr != self.addr);
+                remote.in_flight.retain(|packet| packet.from != self.addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::client_packets::{Disconnect, DisconnectPacketBuilder, Movement, MovementPacketBuilder};
+
+    const SERVER: &str = "127.0.0.1:28154";
+    const CLIENT: &str = "127.0.0.1:5001";
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    fn connect(conditions: LinkConditions) -> (ChannelNetwork, Box<dyn Transport>, Box<dyn Transport>) {
+        let network = ChannelNetwork::new(conditions, 0);
+        let server = network.listen(addr(SERVER)).unwrap();
+        let client = network.connect(addr(CLIENT), addr(SERVER)).unwrap();
+        (network, Box::new(server), Box::new(client))
+    }
+
+    fn sequences(packets: Option<Vec<Movement>>) -> Vec<u32> {
+        packets.unwrap_or_default().iter().map(|movement| movement.sequence).collect()
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let (_network, mut server, mut client) = connect(LinkConditions::default());
+        client.send(Movement { translation: [1.5, -2.0], sequence: 7 }).unwrap();
+        let received = server.received_all::<Movement, MovementPacketBuilder>(false).unwrap();
+        assert_eq!(received.len(), 1);
+        let (remote_id, packets) = &received[0];
+        assert_eq!(*remote_id, 0);
+        let movement = &packets.as_ref().unwrap()[0];
+        assert_eq!(movement.translation, [1.5, -2.0]);
+        assert_eq!(movement.sequence, 7);
+
+        server.send_to(0, Disconnect).unwrap();
+        assert_eq!(client.received::<Disconnect, DisconnectPacketBuilder>(false).unwrap().map(|packets| packets.len()), Some(1));
+    }
+
+    #[test]
+    fn open_connections_are_listed_without_packets() {
+        let (network, mut server, _client) = connect(LinkConditions::default());
+        let _other = network.connect(addr("127.0.0.1:5002"), addr(SERVER)).unwrap();
+        let received = server.received_all::<Movement, MovementPacketBuilder>(false).unwrap();
+        assert_eq!(received.iter().map(|(remote_id, packets)| (*remote_id, packets.is_none())).collect::<Vec<_>>(), vec![(0, true), (1, true)]);
+    }
+
+    #[test]
+    fn packets_arrive_after_latency() {
+        let (network, mut server, mut client) = connect(LinkConditions { latency: Duration::from_millis(100), ..LinkConditions::default() });
+        client.send(Movement { translation: [1.0, 0.0], sequence: 1 }).unwrap();
+        assert!(server.received::<Movement, MovementPacketBuilder>(false).unwrap().is_none());
+        network.advance(Duration::from_millis(99));
+        assert!(server.received::<Movement, MovementPacketBuilder>(false).unwrap().is_none());
+        network.advance(Duration::from_millis(1));
+        assert_eq!(sequences(server.received::<Movement, MovementPacketBuilder>(false).unwrap()), vec![1]);
+    }
+
+    #[test]
+    fn lost_packets_never_arrive() {
+        let (network, mut server, mut client) = connect(LinkConditions { loss: 1.0, ..LinkConditions::default() });
+        for sequence in 0..10 {
+            client.send(Movement { translation: [1.0, 0.0], sequence }).unwrap();
+        }
+        network.advance(Duration::from_secs(1));
+        assert!(server.received::<Movement, MovementPacketBuilder>(false).unwrap().is_none());
+    }
+
+    #[test]
+    fn reordered_packets_are_overtaken() {
+        let latency = Duration::from_millis(10);
+        let (network, mut server, mut client) = connect(LinkConditions { latency, reorder: 1.0, ..LinkConditions::default() });
+        client.send(Movement { translation: [1.0, 0.0], sequence: 1 }).unwrap();
+        network.set_conditions(LinkConditions { latency, ..LinkConditions::default() });
+        client.send(Movement { translation: [1.0, 0.0], sequence: 2 }).unwrap();
+        network.advance(latency);
+        assert_eq!(sequences(server.received::<Movement, MovementPacketBuilder>(false).unwrap()), vec![2]);
+        network.advance(latency);
+        assert_eq!(sequences(server.received::<Movement, MovementPacketBuilder>(false).unwrap()), vec![1]);
+    }
+
+    #[test]
+    fn duplicated_packets_arrive_twice() {
+        let (_network, mut server, mut client) = connect(LinkConditions { duplicate: 1.0, ..LinkConditions::default() });
+        client.send(Movement { translation: [1.0, 0.0], sequence: 3 }).unwrap();
+        assert_eq!(sequences(server.received::<Movement, MovementPacketBuilder>(false).unwrap()), vec![3, 3]);
+    }
+
+    #[test]
+    fn packets_arrive_in_order_without_reordering() {
+        let (network, mut server, mut client) = connect(LinkConditions { latency: Duration::from_millis(30), ..LinkConditions::default() });
+        for sequence in 0..5 {
+            client.send(Movement { translation: [1.0, 0.0], sequence }).unwrap();
+        }
+        network.advance(Duration::from_millis(30));
+        assert_eq!(sequences(server.received::<Movement, MovementPacketBuilder>(false).unwrap()), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn undecodable_packets_are_skipped() {
+        let (network, mut server, mut client) = connect(LinkConditions::default());
+        client.send(Movement { translation: [1.0, 0.0], sequence: 1 }).unwrap();
+        network.lock().transmit(addr(CLIENT), addr(SERVER), TypeId::of::<Movement>(), vec![0xff]);
+        client.send(Movement { translation: [1.0, 0.0], sequence: 2 }).unwrap();
+        assert_eq!(sequences(server.received::<Movement, MovementPacketBuilder>(false).unwrap()), vec![1, 2]);
+    }
+
+    #[test]
+    fn closing_a_connection_closes_both_ends() {
+        let (_network, mut server, mut client) = connect(LinkConditions::default());
+        server.close_connection(0).unwrap();
+        assert!(server.get_remote_address(0).is_none());
+        assert!(client.get_remote_address(0).is_none());
+        assert!(matches!(client.send(Disconnect), Err(TransportError::NotConnected(0))));
+        assert!(server.received_all::<Disconnect, DisconnectPacketBuilder>(false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn dropping_a_client_closes_its_connection() {
+        let (_network, server, client) = connect(LinkConditions::default());
+        assert_eq!(server.get_remote_address(0), Some(addr(CLIENT)));
+        drop(client);
+        assert!(server.get_remote_address(0).is_none());
+    }
+
+    #[test]
+    fn addresses_must_be_free_and_listening() {
+        let (network, _server, _client) = connect(LinkConditions::default());
+        assert!(matches!(network.listen(addr(SERVER)), Err(TransportError::Address(_))));
+        assert!(matches!(network.connect(addr(CLIENT), addr(SERVER)), Err(TransportError::Address(_))));
+        assert!(matches!(network.connect(addr("127.0.0.1:5002"), addr("127.0.0.1:1")), Err(TransportError::Address(_))));
+    }
+}
@@ -0,0 +1,121 @@
+use std::any::TypeId;
+use std::net::SocketAddr;
+
+use durian::{ClientConfig, Packet, PacketBuilder, PacketManager, ServerConfig};
+
+use crate::networking::registry;
+use crate::networking::transport::{AnyPacket, PacketTypes, Received, Transport, TransportError};
+
+/// QUIC transport over real sockets
+pub struct DurianTransport {
+    manager: PacketManager,
+    types: PacketTypes,
+    is_server: bool,
+}
+
+impl DurianTransport {
+    pub fn server(config: ServerConfig) -> Result<Self, TransportError> {
+        let mut manager = PacketManager::new();
+        if !registry::register_server_packets(&mut manager) {
+            return Err(TransportError::Durian("failed to register all packets".to_string()));
+        }
+        manager.init_server(config).map_err(|e| TransportError::Durian(format!("{:?}", e)))?;
+        Ok(DurianTransport { manager, types: registry::packet_types(), is_server: true })
+    }
+
+    pub fn client(config: ClientConfig) -> Result<Self, TransportError> {
+        let mut manager = PacketManager::new();
+        if !registry::register_client_packets(&mut manager) {
+            return Err(TransportError::Durian("failed to register all packets".to_string()));
+        }
+        manager.init_client(config).map_err(|e| TransportError::Durian(format!("{:?}", e)))?;
+        Ok(DurianTransport { manager, types: registry::packet_types(), is_server: false })
+    }
+}
+
+impl Transport for DurianTransport {
+    fn send_any(&mut self, remote_id: Option<u32>, type_id: TypeId, packet: AnyPacket) -> Result<(), TransportError> {
+        let durian = self.types.get(type_id)?.durian;
+        match remote_id {
+            Some(remote_id) => (durian.send_to)(&mut self.manager, remote_id, packet),
+            None if self.is_server => (durian.broadcast)(&mut self.manager, packet),
+            None => (durian.send)(&mut self.manager, packet),
+        }
+    }
+
+    fn received_any(&mut self, type_id: TypeId, blocking: bool) -> Result<Received<AnyPacket>, TransportError> {
+        let durian = self.types.get(type_id)?.durian;
+        if self.is_server {
+            (durian.received_all)(&mut self.manager, blocking)
+        } else {
+            // durian doesn't give the server a remote id
+            Ok(vec![(0, (durian.received)(&mut self.manager, blocking)?)])
+        }
+    }
+
+    fn get_remote_address(&self, remote_id: u32) -> Option<SocketAddr> {
+        self.manager.get_remote_address(remote_id)
+    }
+
+    fn close_connection(&mut self, remote_id: u32) -> Result<(), TransportError> {
+        self.manager.close_connection(remote_id).map_err(|e| TransportError::Durian(e.to_string()))
+    }
+}
+
+/// durian's typed calls for one packet type
+#[derive(Copy, Clone)]
+pub(super) struct DurianFns {
+    send: fn(&mut PacketManager, AnyPacket) -> Result<(), TransportError>,
+    send_to: fn(&mut PacketManager, u32, AnyPacket) -> Result<(), TransportError>,
+    broadcast: fn(&mut PacketManager, AnyPacket) -> Result<(), TransportError>,
+    received: fn(&mut PacketManager, bool) -> Result<Option<Vec<AnyPacket>>, TransportError>,
+    received_all: fn(&mut PacketManager, bool) -> Result<Received<AnyPacket>, TransportError>,
+}
+
+impl DurianFns {
+    pub(super) fn of<T: Packet + Send + 'static, B: PacketBuilder<T> + 'static>() -> Self {
+        DurianFns {
+            send: send::<T>,
+            send_to: send_to::<T>,
+            broadcast: broadcast::<T>,
+            received: received::<T, B>,
+            received_all: received_all::<T, B>,
+        }
+    }
+}
+
+fn downcast<T: 'static>(packet: AnyPacket) -> Result<T, TransportError> {
+    packet.downcast::<T>().map(|packet| *packet).map_err(|_| TransportError::WrongType)
+}
+
+fn erase<T: Send + 'static>(packets: Vec<T>) -> Vec<AnyPacket> {
+    packets.into_iter().map(|packet| Box::new(packet) as AnyPacket).collect()
+}
+
+fn send<T: Packet + 'static>(manager: &mut PacketManager, packet: AnyPacket) -> Result<(), TransportError> {
+    manager.send(downcast::<T>(packet)?).map_err(|e| TransportError::Durian(format!("{:?}", e)))
+}
+
+fn send_to<T: Packet + 'static>(manager: &mut PacketManager, remote_id: u32, packet: AnyPacket) -> Result<(), TransportError> {
+    manager.send_to(remote_id, downcast::<T>(packet)?).map_err(|e| TransportError::Durian(format!("{:?}", e)))
+}
+
+fn broadcast<T: Packet + 'static>(manager: &mut PacketManager, packet: AnyPacket) -> Result<(), TransportError> {
+    manager.broadcast(downcast::<T>(packet)?).map_err(|e| TransportError::Durian(format!("{:?}", e)))
+}
+
+fn received<T: Packet + Send + 'static, B: PacketBuilder<T> + 'static>(
+    manager: &mut PacketManager,
+    blocking: bool,
+) -> Result<Option<Vec<AnyPacket>>, TransportError> {
+    let packets = manager.received::<T, B>(blocking).map_err(|e| TransportError::Durian(format!("{:?}", e)))?;
+    Ok(packets.map(erase))
+}
+
+fn received_all<T: Packet + Send + 'static, B: PacketBuilder<T> + 'static>(
+    manager: &mut PacketManager,
+    blocking: bool,
+) -> Result<Received<AnyPacket>, TransportError> {
+    let received = manager.received_all::<T, B>(blocking).map_err(|e| TransportError::Durian(format!("{:?}", e)))?;
+    Ok(received.into_iter().map(|(remote_id, packets)| (remote_id, packets.map(erase))).collect())
+}
@@ -0,0 +1,136 @@
+use std::any::TypeId;
+use std::net::SocketAddr;
+
+use crate::networking::diagnostics::resource::NetworkStats;
+use crate::networking::registry;
+use crate::networking::transport::{AnyPacket, PacketTypes, Received, Transport, TransportError};
+
+/// Wraps another transport and counts the packets and bytes going through it into [`NetworkStats`]
+pub struct MeteredTransport {
+    inner: Box<dyn Transport>,
+    stats: NetworkStats,
+    types: PacketTypes,
+    /// Open connections as of the last receive, which packets sent to every remote are counted against
+    remotes: Vec<u32>,
+}
+
+impl MeteredTransport {
+    pub fn new(inner: Box<dyn Transport>, stats: NetworkStats) -> Self {
+        MeteredTransport { inner, stats, types: registry::packet_types(), remotes: Vec::new() }
+    }
+
+    fn targets(&self, remote_id: Option<u32>) -> Vec<u32> {
+        match remote_id {
+            Some(remote_id) => vec![remote_id],
+            // Clients send before they first receive, and their only remote is the server
+            None if self.remotes.is_empty() && self.inner.get_remote_address(0).is_some() => vec![0],
+            None => self.remotes.clone(),
+        }
+    }
+}
+
+impl Transport for MeteredTransport {
+    fn send_any(&mut self, remote_id: Option<u32>, type_id: TypeId, packet: AnyPacket) -> Result<(), TransportError> {
+        let packet_type = *self.types.get(type_id)?;
+        let bytes = (packet_type.size)(packet.as_ref())?;
+        let targets = self.targets(remote_id);
+        match self.inner.send_any(remote_id, type_id, packet) {
+            Ok(()) => {
+                for target in targets {
+                    self.stats.record_sent(target, packet_type.name, bytes);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                for target in targets {
+                    self.stats.record_dropped(target);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn received_any(&mut self, type_id: TypeId, blocking: bool) -> Result<Received<AnyPacket>, TransportError> {
+        let packet_type = *self.types.get(type_id)?;
+        let received = self.inner.received_any(type_id, blocking)?;
+        self.remotes = received.iter().map(|(remote_id, _)| *remote_id).collect();
+        self.stats.retain_connections(&self.remotes);
+        for (remote_id, packets) in &received {
+            for packet in packets.iter().flatten() {
+                self.stats.record_received(*remote_id, packet_type.name, (packet_type.size)(packet.as_ref())?);
+            }
+        }
+        Ok(received)
+    }
+
+    fn get_remote_address(&self, remote_id: u32) -> Option<SocketAddr> {
+        self.inner.get_remote_address(remote_id)
+    }
+
+    fn close_connection(&mut self, remote_id: u32) -> Result<(), TransportError> {
+        self.remotes.retain(|remote| *remote != remote_id);
+        self.stats.forget_connection(remote_id);
+        self.inner.close_connection(remote_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::client_packets::{Movement, MovementPacketBuilder};
+    use crate::networking::transport::{ChannelNetwork, LinkConditions};
+
+    fn connect() -> (NetworkStats, NetworkStats, Box<dyn Transport>, Box<dyn Transport>, Box<dyn Transport>) {
+        let network = ChannelNetwork::new(LinkConditions::default(), 0);
+        let server_addr = "127.0.0.1:28154".parse().unwrap();
+        let server = network.listen(server_addr).unwrap();
+        let first = network.connect("127.0.0.1:5001".parse().unwrap(), server_addr).unwrap();
+        let second = network.connect("127.0.0.1:5002".parse().unwrap(), server_addr).unwrap();
+        let (server_stats, client_stats) = (NetworkStats::default(), NetworkStats::default());
+        let server = MeteredTransport::new(Box::new(server), server_stats.clone());
+        let first = MeteredTransport::new(Box::new(first), client_stats.clone());
+        (server_stats, client_stats, Box::new(server), Box::new(first), Box::new(second))
+    }
+
+    fn movement(sequence: u32) -> Movement {
+        Movement { translation: [1.0, 0.0], sequence }
+    }
+
+    #[test]
+    fn counts_packets_and_bytes_by_connection_and_type() {
+        let (server_stats, client_stats, mut server, mut first, mut second) = connect();
+        first.send(movement(1)).unwrap();
+        first.send(movement(2)).unwrap();
+        second.send(movement(3)).unwrap();
+        server.received_all::<Movement, MovementPacketBuilder>(false).unwrap();
+
+        let size = bincode::serialized_size(&movement(1)).unwrap();
+        let sent = client_stats.connection(0).unwrap();
+        assert_eq!((sent.sent.packets, sent.sent.bytes), (2, 2 * size));
+        assert_eq!(sent.packet_types["Movement"].sent.packets, 2);
+        let received = server_stats.connections();
+        assert_eq!(received.iter().map(|(_, connection)| connection.received.packets).collect::<Vec<u64>>(), vec![2, 1]);
+        assert_eq!(server_stats.totals().received.bytes, 3 * size);
+    }
+
+    #[test]
+    fn sending_to_every_remote_counts_for_each() {
+        let (server_stats, _, mut server, _, _) = connect();
+        server.received_all::<Movement, MovementPacketBuilder>(false).unwrap();
+        server.send(movement(1)).unwrap();
+        assert_eq!(server_stats.totals().sent.packets, 2);
+        assert_eq!(server_stats.connections().len(), 2);
+    }
+
+    #[test]
+    fn closed_connections_are_forgotten_but_totals_kept() {
+        let (server_stats, _, mut server, _, mut second) = connect();
+        second.send(movement(1)).unwrap();
+        server.received_all::<Movement, MovementPacketBuilder>(false).unwrap();
+        server.close_connection(1).unwrap();
+        assert!(server_stats.connection(1).is_none());
+        assert_eq!(server_stats.totals().received.packets, 1);
+        assert!(server.send_to(1, movement(2)).is_err());
+        assert_eq!(server_stats.totals().dropped, 1);
+    }
+}
@@ -0,0 +1,196 @@
+//! Transports move packets between the server and clients.
+//!
+//! Game code sends and receives typed packets through `dyn Transport`, the same way it would through a durian
+//! [`PacketManager`](durian::PacketManager).  Transports only see type erased packets, along with the [`PacketType`]
+//! of every registered packet so they can serialize them if they need to.
+
+use std::any::{Any, TypeId};
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bevy::utils::HashMap;
+use durian::{Packet, PacketBuilder};
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub use crate::networking::transport::channel::{ChannelNetwork, ChannelTransport};
+pub use crate::networking::transport::durian_transport::DurianTransport;
+pub use crate::networking::transport::metered::MeteredTransport;
+pub use crate::networking::transport::simulated::{
+    insert_network_conditions, NetworkConditions, NetworkSimulationArgs, NetworkSimulationSettings, SimulatedTransport,
+};
+
+mod channel;
+mod durian_transport;
+mod metered;
+mod simulated;
+
+/// Packet with its type erased
+pub type AnyPacket = Box<dyn Any + Send>;
+
+/// Packets of one type by the remote id they came from.  Every open connection is listed, whether it sent anything or not.
+pub type Received<T> = Vec<(u32, Option<Vec<T>>)>;
+
+pub trait Transport: Send + Sync + 'static {
+    /// Send to one remote, or to every remote if `remote_id` is None.  Clients only have the server as a remote.
+    fn send_any(&mut self, remote_id: Option<u32>, type_id: TypeId, packet: AnyPacket) -> Result<(), TransportError>;
+
+    /// Packets of one type received since the last call
+    fn received_any(&mut self, type_id: TypeId, blocking: bool) -> Result<Received<AnyPacket>, TransportError>;
+
+    fn get_remote_address(&self, remote_id: u32) -> Option<SocketAddr>;
+
+    fn close_connection(&mut self, remote_id: u32) -> Result<(), TransportError>;
+}
+
+/// Typed API, mirroring durian's
+impl dyn Transport {
+    /// Send to the server, or to every client when called on the server
+    pub fn send<T: Packet + Send + 'static>(&mut self, packet: T) -> Result<(), TransportError> {
+        self.send_any(None, TypeId::of::<T>(), Box::new(packet))
+    }
+
+    pub fn send_to<T: Packet + Send + 'static>(&mut self, remote_id: u32, packet: T) -> Result<(), TransportError> {
+        self.send_any(Some(remote_id), TypeId::of::<T>(), Box::new(packet))
+    }
+
+    /// Packets received from every remote.  `B` is the packet's builder, as registered.
+    pub fn received_all<T: Packet + Send + 'static, B: PacketBuilder<T> + 'static>(&mut self, blocking: bool) -> Result<Received<T>, TransportError> {
+        self.received_any(TypeId::of::<T>(), blocking)?
+            .into_iter()
+            .map(|(remote_id, packets)| Ok((remote_id, packets.map(downcast_all::<T>).transpose()?)))
+            .collect()
+    }
+
+    /// Packets received from the server, for clients
+    pub fn received<T: Packet + Send + 'static, B: PacketBuilder<T> + 'static>(&mut self, blocking: bool) -> Result<Option<Vec<T>>, TransportError> {
+        let mut packets: Option<Vec<T>> = None;
+        for (_, received) in self.received_all::<T, B>(blocking)? {
+            if let Some(received) = received {
+                packets.get_or_insert_with(Vec::new).extend(received);
+            }
+        }
+        Ok(packets)
+    }
+}
+
+fn downcast_all<T: 'static>(packets: Vec<AnyPacket>) -> Result<Vec<T>, TransportError> {
+    packets.into_iter().map(|packet| packet.downcast::<T>().map(|packet| *packet).map_err(|_| TransportError::WrongType)).collect()
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    /// No open connection with this remote id
+    NotConnected(u32),
+    /// Address is already bound, or nothing is listening on it
+    Address(SocketAddr),
+    /// Packet was not registered, or did not match its type id
+    WrongType,
+    Encoding(bincode::Error),
+    Durian(String),
+}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportError::NotConnected(remote_id) => write!(f, "not connected to remote {}", remote_id),
+            TransportError::Address(addr) => write!(f, "address {} is unavailable", addr),
+            TransportError::WrongType => write!(f, "unregistered or mismatched packet type"),
+            TransportError::Encoding(e) => write!(f, "could not encode packet: {}", e),
+            TransportError::Durian(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// What happens to packets on their way between endpoints
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LinkConditions {
+    pub latency: Duration,
+    /// Up to this much extra latency, picked at random for each packet
+    pub jitter: Duration,
+    /// Chance of dropping each packet, from 0 to 1
+    pub loss: f64,
+    /// Chance of delivering each packet twice, from 0 to 1
+    pub duplicate: f64,
+    /// Chance of holding each packet back by another `latency` (at least 1ms), so later packets overtake it
+    pub reorder: f64,
+}
+
+impl LinkConditions {
+    /// Nothing is delayed, lost, duplicated or reordered
+    pub fn is_ideal(&self) -> bool {
+        *self == LinkConditions::default()
+    }
+
+    /// How long each copy of a packet takes to arrive.  Empty if it's lost.
+    pub fn delays(&self, rng: &mut impl Rng) -> Vec<Duration> {
+        if rng.gen_bool(self.loss.clamp(0.0, 1.0)) {
+            return Vec::new();
+        }
+        let copies = if rng.gen_bool(self.duplicate.clamp(0.0, 1.0)) { 2 } else { 1 };
+        (0..copies)
+            .map(|_| {
+                let mut delay = self.latency + self.jitter.mul_f64(rng.gen::<f64>());
+                if rng.gen_bool(self.reorder.clamp(0.0, 1.0)) {
+                    delay += self.latency.max(Duration::from_millis(1));
+                }
+                delay
+            })
+            .collect()
+    }
+}
+
+/// Type erased operations on one packet type
+#[derive(Copy, Clone)]
+pub struct PacketType {
+    pub name: &'static str,
+    pub encode: fn(&(dyn Any + Send)) -> Result<Vec<u8>, TransportError>,
+    pub decode: fn(&[u8]) -> Result<AnyPacket, TransportError>,
+    /// Encoded size in bytes, without encoding
+    pub size: fn(&(dyn Any + Send)) -> Result<u64, TransportError>,
+    durian: durian_transport::DurianFns,
+}
+
+impl PacketType {
+    pub fn of<T: Packet + Serialize + DeserializeOwned + Send + 'static, B: PacketBuilder<T> + 'static>(name: &'static str) -> Self {
+        PacketType {
+            name,
+            encode: encode::<T>,
+            decode: decode::<T>,
+            size: size::<T>,
+            durian: durian_transport::DurianFns::of::<T, B>(),
+        }
+    }
+}
+
+fn encode<T: Serialize + 'static>(packet: &(dyn Any + Send)) -> Result<Vec<u8>, TransportError> {
+    let packet = packet.downcast_ref::<T>().ok_or(TransportError::WrongType)?;
+    bincode::serialize(packet).map_err(TransportError::Encoding)
+}
+
+fn size<T: Serialize + 'static>(packet: &(dyn Any + Send)) -> Result<u64, TransportError> {
+    let packet = packet.downcast_ref::<T>().ok_or(TransportError::WrongType)?;
+    bincode::serialized_size(packet).map_err(TransportError::Encoding)
+}
+
+fn decode<T: DeserializeOwned + Send + 'static>(bytes: &[u8]) -> Result<AnyPacket, TransportError> {
+    Ok(Box::new(bincode::deserialize::<T>(bytes).map_err(TransportError::Encoding)?))
+}
+
+/// Every registered packet, by type id
+#[derive(Clone, Default)]
+pub struct PacketTypes {
+    types: HashMap<TypeId, PacketType>,
+}
+
+impl PacketTypes {
+    pub fn insert<T: Packet + Serialize + DeserializeOwned + Send + 'static, B: PacketBuilder<T> + 'static>(&mut self, name: &'static str) {
+        self.types.insert(TypeId::of::<T>(), PacketType::of::<T, B>(name));
+    }
+
+    pub fn get(&self, type_id: TypeId) -> Result<&PacketType, TransportError> {
+        self.types.get(&type_id).ok_or(TransportError::WrongType)
+    }
+}
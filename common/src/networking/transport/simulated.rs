@@ -0,0 +1,307 @@
+use std::any::TypeId;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use bevy::log::warn;
+use bevy::prelude::{App, Resource};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::networking::registry;
+use crate::networking::transport::{AnyPacket, LinkConditions, PacketTypes, Received, Transport, TransportError};
+
+/// Conditions shared by every [`SimulatedTransport`] made with them, so they can be changed while connected
+#[derive(Resource, Clone, Default)]
+pub struct NetworkConditions(Arc<RwLock<LinkConditions>>);
+
+impl NetworkConditions {
+    pub fn get(&self) -> LinkConditions {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn set(&self, conditions: LinkConditions) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = conditions;
+    }
+}
+
+/// Shares `conditions` with every [`SimulatedTransport`] the app makes, through the [`NetworkConditions`] resource
+pub fn insert_network_conditions(app: &mut App, conditions: &LinkConditions, is_client: bool) {
+    let shared = NetworkConditions::default();
+    shared.set(conditions.clone());
+    if !conditions.is_ideal() {
+        warn!("[{}] Simulating network conditions {:?}", if is_client { "client" } else { "server" }, conditions);
+    }
+    app.insert_resource(shared);
+}
+
+/// [`LinkConditions`] as they are configured.  Bad network conditions to test with, off by default.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSimulationSettings {
+    pub enabled: bool,
+    /// One way, so round trips take twice this
+    pub latency_ms: u64,
+    pub jitter_ms: u64,
+    pub loss: f64,
+    pub duplicate: f64,
+    pub reorder: f64,
+}
+
+impl NetworkSimulationSettings {
+    pub fn conditions(&self) -> LinkConditions {
+        if !self.enabled {
+            return LinkConditions::default();
+        }
+        LinkConditions {
+            latency: Duration::from_millis(self.latency_ms),
+            jitter: Duration::from_millis(self.jitter_ms),
+            loss: self.loss,
+            duplicate: self.duplicate,
+            reorder: self.reorder,
+        }
+    }
+
+    /// Apply conditions given on the command line.  Giving any of them turns simulation on.
+    pub fn apply_overrides(&mut self, args: &NetworkSimulationArgs) {
+        if args.sim_latency.is_some()
+            || args.sim_jitter.is_some()
+            || args.sim_loss.is_some()
+            || args.sim_duplicate.is_some()
+            || args.sim_reorder.is_some()
+        {
+            self.enabled = true;
+        }
+        self.latency_ms = args.sim_latency.unwrap_or(self.latency_ms);
+        self.jitter_ms = args.sim_jitter.unwrap_or(self.jitter_ms);
+        self.loss = args.sim_loss.unwrap_or(self.loss);
+        self.duplicate = args.sim_duplicate.unwrap_or(self.duplicate);
+        self.reorder = args.sim_reorder.unwrap_or(self.reorder);
+    }
+
+    /// Problems with the settings, if any
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, chance) in [("loss", self.loss), ("duplicate", self.duplicate), ("reorder", self.reorder)] {
+            if !(0.0..=1.0).contains(&chance) {
+                return Err(format!("{} must be between 0 and 1, got {}", name, chance));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Command line flags overriding [`NetworkSimulationSettings`], shared by the client and server
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct NetworkSimulationArgs {
+    /// Simulate this much one way latency in milliseconds
+    #[arg(long)]
+    pub sim_latency: Option<u64>,
+    /// Simulated latency varies by up to this many milliseconds
+    #[arg(long)]
+    pub sim_jitter: Option<u64>,
+    /// Chance from 0 to 1 that a packet is dropped
+    #[arg(long)]
+    pub sim_loss: Option<f64>,
+    /// Chance from 0 to 1 that a packet arrives twice
+    #[arg(long)]
+    pub sim_duplicate: Option<f64>,
+    /// Chance from 0 to 1 that a packet is held back behind later ones
+    #[arg(long)]
+    pub sim_reorder: Option<f64>,
+}
+
+/// Wraps another transport and delays, drops, duplicates and reorders packets going either way, to see how the game
+/// plays over a bad connection.  Passes packets straight through while conditions are ideal.
+pub struct SimulatedTransport {
+    inner: Box<dyn Transport>,
+    conditions: NetworkConditions,
+    types: PacketTypes,
+    rng: StdRng,
+    /// Orders packets released at the same time by when they were sent
+    next_sequence: u64,
+    /// Sent by the game, waiting to be handed to the inner transport
+    outgoing: Vec<Delayed>,
+    /// Received by the inner transport, waiting to be handed to the game
+    incoming: Vec<Delayed>,
+}
+
+struct Delayed {
+    release_at: Instant,
+    sequence: u64,
+    /// Remote it's for or from.  None to send to every remote.
+    remote_id: Option<u32>,
+    type_id: TypeId,
+    bytes: Vec<u8>,
+}
+
+impl SimulatedTransport {
+    pub fn new(inner: Box<dyn Transport>, conditions: NetworkConditions, seed: u64) -> Self {
+        SimulatedTransport {
+            inner,
+            conditions,
+            types: registry::packet_types(),
+            rng: StdRng::seed_from_u64(seed),
+            next_sequence: 0,
+            outgoing: Vec::new(),
+            incoming: Vec::new(),
+        }
+    }
+
+    /// Queue copies of a packet according to the current conditions
+    fn delay(&mut self, conditions: &LinkConditions, incoming: bool, remote_id: Option<u32>, type_id: TypeId, bytes: Vec<u8>) {
+        let now = Instant::now();
+        for delay in conditions.delays(&mut self.rng) {
+            let sequence = self.next_sequence;
+            self.next_sequence += 1;
+            let delayed = Delayed { release_at: now + delay, sequence, remote_id, type_id, bytes: bytes.clone() };
+            if incoming {
+                self.incoming.push(delayed);
+            } else {
+                self.outgoing.push(delayed);
+            }
+        }
+    }
+
+    /// Hand outgoing packets that are due to the inner transport
+    fn flush(&mut self) -> Result<(), TransportError> {
+        for delayed in take_due(&mut self.outgoing, None) {
+            let packet = (self.types.get(delayed.type_id)?.decode)(&delayed.bytes)?;
+            // Connections can close while packets are held back, which the game hears about on its next send
+            let _ = self.inner.send_any(delayed.remote_id, delayed.type_id, packet);
+        }
+        Ok(())
+    }
+}
+
+/// Remove the delayed packets that are due, optionally only of one type, in the order they should arrive
+fn take_due(delayed: &mut Vec<Delayed>, type_id: Option<TypeId>) -> Vec<Delayed> {
+    let now = Instant::now();
+    let is_due = |delayed: &Delayed| delayed.release_at <= now && type_id.map_or(true, |type_id| delayed.type_id == type_id);
+    let (mut due, held): (Vec<Delayed>, Vec<Delayed>) = std::mem::take(delayed).into_iter().partition(is_due);
+    *delayed = held;
+    due.sort_by_key(|delayed| (delayed.release_at, delayed.sequence));
+    due
+}
+
+impl Transport for SimulatedTransport {
+    fn send_any(&mut self, remote_id: Option<u32>, type_id: TypeId, packet: AnyPacket) -> Result<(), TransportError> {
+        self.flush()?;
+        let conditions = self.conditions.get();
+        // Packets still held back would be overtaken otherwise
+        if conditions.is_ideal() && self.outgoing.is_empty() {
+            return self.inner.send_any(remote_id, type_id, packet);
+        }
+        let bytes = (self.types.get(type_id)?.encode)(packet.as_ref())?;
+        self.delay(&conditions, false, remote_id, type_id, bytes);
+        self.flush()
+    }
+
+    fn received_any(&mut self, type_id: TypeId, blocking: bool) -> Result<Received<AnyPacket>, TransportError> {
+        self.flush()?;
+        let received = self.inner.received_any(type_id, blocking)?;
+        let conditions = self.conditions.get();
+        if conditions.is_ideal() && !self.incoming.iter().any(|delayed| delayed.type_id == type_id) {
+            return Ok(received);
+        }
+
+        let packet_type = *self.types.get(type_id)?;
+        let mut released: Received<AnyPacket> = Vec::with_capacity(received.len());
+        for (remote_id, packets) in received {
+            for packet in packets.into_iter().flatten() {
+                let bytes = (packet_type.encode)(packet.as_ref())?;
+                self.delay(&conditions, true, Some(remote_id), type_id, bytes);
+            }
+            released.push((remote_id, None));
+        }
+        for delayed in take_due(&mut self.incoming, Some(type_id)) {
+            // Dropped if the connection closed while it was held back
+            if let Some((_, packets)) = released.iter_mut().find(|(remote_id, _)| Some(*remote_id) == delayed.remote_id) {
+                packets.get_or_insert_with(Vec::new).push((packet_type.decode)(&delayed.bytes)?);
+            }
+        }
+        Ok(released)
+    }
+
+    fn get_remote_address(&self, remote_id: u32) -> Option<SocketAddr> {
+        self.inner.get_remote_address(remote_id)
+    }
+
+    fn close_connection(&mut self, remote_id: u32) -> Result<(), TransportError> {
+        self.outgoing.retain(|delayed| delayed.remote_id != Some(remote_id));
+        self.incoming.retain(|delayed| delayed.remote_id != Some(remote_id));
+        self.inner.close_connection(remote_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::networking::client_packets::{Movement, MovementPacketBuilder};
+    use crate::networking::transport::ChannelNetwork;
+
+    fn connect(conditions: LinkConditions) -> (NetworkConditions, Box<dyn Transport>, Box<dyn Transport>) {
+        let network = ChannelNetwork::new(LinkConditions::default(), 0);
+        let server_addr = "127.0.0.1:28154".parse().unwrap();
+        let server = network.listen(server_addr).unwrap();
+        let client = network.connect("127.0.0.1:5001".parse().unwrap(), server_addr).unwrap();
+        let shared = NetworkConditions::default();
+        shared.set(conditions);
+        (shared.clone(), Box::new(server), Box::new(SimulatedTransport::new(Box::new(client), shared, 0)))
+    }
+
+    fn send(client: &mut Box<dyn Transport>, sequence: u32) {
+        client.send(Movement { translation: [1.0, 0.0], sequence }).unwrap();
+    }
+
+    fn sequences(transport: &mut Box<dyn Transport>) -> Vec<u32> {
+        let packets = transport.received::<Movement, MovementPacketBuilder>(false).unwrap();
+        packets.unwrap_or_default().iter().map(|movement| movement.sequence).collect()
+    }
+
+    #[test]
+    fn ideal_conditions_pass_packets_through() {
+        let (_, mut server, mut client) = connect(LinkConditions::default());
+        send(&mut client, 1);
+        send(&mut client, 2);
+        assert_eq!(sequences(&mut server), vec![1, 2]);
+    }
+
+    #[test]
+    fn delayed_packets_are_held_back() {
+        let (conditions, mut server, mut client) = connect(LinkConditions { latency: Duration::from_secs(3600), ..LinkConditions::default() });
+        send(&mut client, 1);
+        assert!(sequences(&mut server).is_empty());
+        // Still held back after conditions improve, so later packets don't overtake it
+        conditions.set(LinkConditions::default());
+        send(&mut client, 2);
+        assert!(sequences(&mut server).is_empty());
+    }
+
+    #[test]
+    fn lost_packets_never_arrive() {
+        let (_, mut server, mut client) = connect(LinkConditions { loss: 1.0, ..LinkConditions::default() });
+        for sequence in 0..10 {
+            send(&mut client, sequence);
+        }
+        assert!(sequences(&mut server).is_empty());
+    }
+
+    #[test]
+    fn duplicated_packets_arrive_twice_in_both_directions() {
+        let (_, mut server, mut client) = connect(LinkConditions { duplicate: 1.0, ..LinkConditions::default() });
+        send(&mut client, 4);
+        assert_eq!(sequences(&mut server), vec![4, 4]);
+        server.send_to(0, Movement { translation: [1.0, 0.0], sequence: 5 }).unwrap();
+        assert_eq!(sequences(&mut client), vec![5, 5]);
+    }
+
+    #[test]
+    fn settings_only_apply_when_enabled() {
+        let settings = NetworkSimulationSettings { latency_ms: 100, loss: 0.5, ..NetworkSimulationSettings::default() };
+        assert!(settings.conditions().is_ideal());
+        let enabled = NetworkSimulationSettings { enabled: true, ..settings };
+        assert_eq!(enabled.conditions().latency, Duration::from_millis(100));
+        assert!(NetworkSimulationSettings { loss: 1.5, ..enabled }.validate().is_err());
+    }
+}
@@ -2,6 +2,7 @@ use bevy::prelude::Component;
 
 #[derive(Component, Copy, Clone)]
 pub struct PlayerData {
+    /// Persistent id, the same across connections.  Currently the player's account id.
     pub id: u32,
     pub handle_id: u8,
 }
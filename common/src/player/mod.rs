@@ -1,9 +1,12 @@
 use bevy::ecs::system::EntityCommands;
 use bevy::math::{Vec2, Vec3};
 use bevy::prelude::{default, AssetServer, Commands, Res, SceneBundle, Transform};
-use bevy_rapier3d::prelude::Collider;
+use bevy_rapier3d::prelude::{Collider, LockedAxes, RigidBody};
+
+use crate::physics::component::ColliderBundle;
 
 pub mod component;
+pub mod movement;
 
 pub static PLAYER_MODEL_HANDLE_IDS: [&str; 2] = ["models/amber/Amber.glb", "models/owl/scene.gltf"];
 
@@ -27,6 +30,16 @@ pub fn get_player_collider() -> Collider {
     Collider::capsule_y(1.0, 1.0)
 }
 
+/// Physics components for a player that is moved by a character controller
+pub fn get_player_collider_bundle() -> ColliderBundle {
+    ColliderBundle {
+        collider: get_player_collider(),
+        rigid_body: RigidBody::KinematicPositionBased,
+        rotation_constraints: LockedAxes::ROTATION_LOCKED,
+        ..default()
+    }
+}
+
 /// Sets the player's facing direction
 pub fn set_player_rotation(direction: Vec2, transform: &mut Transform) {
     if direction != Vec2::ZERO {
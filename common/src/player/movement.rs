@@ -0,0 +1,164 @@
+//! Player movement and collision shared by the server simulation and client-side prediction, so both sides step players
+//! the same way.
+
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy_rapier3d::control::KinematicCharacterControllerOutput;
+use bevy_rapier3d::prelude::{CharacterLength, Collider, KinematicCharacterController, QueryFilter, QueryFilterFlags, RapierContext, TOIStatus};
+use serde::{Deserialize, Serialize};
+
+use crate::component::MoveTarget;
+use crate::player::set_player_rotation;
+
+/// Movement tuning.  Configured on the server and sent to clients in
+/// [`ConnectAck`](crate::networking::server_packets::ConnectAck) so prediction matches the server.
+#[derive(Resource, Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct MovementSettings {
+    /// Horizontal units per second
+    pub speed: f32,
+    /// Units per second players fall when not grounded
+    pub gravity_step_speed: f32,
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        MovementSettings { speed: 100.0, gravity_step_speed: 100.0 }
+    }
+}
+
+/// Converts a [`Movement`](crate::networking::client_packets::Movement) input into the player's next move target
+pub fn move_target_from_input(translation: [f32; 2]) -> MoveTarget {
+    // TODO: path to spot in world?
+    let movement_vec = Vec2::new(translation[0], translation[1]).normalize() * 0.5;
+    MoveTarget { target: (movement_vec.x, movement_vec.y) }
+}
+
+/// Steps a move target forward by `delta_seconds`.  Returns the horizontal displacement for this step, and whether the
+/// target has been reached.
+pub fn step_move_target(move_target: &mut MoveTarget, settings: &MovementSettings, delta_seconds: f32) -> (Vec2, bool) {
+    let movement_vec = Vec2::new(move_target.target.0, move_target.target.1).normalize();
+    let dx = movement_vec.x * settings.speed * delta_seconds;
+    let dy = movement_vec.y * settings.speed * delta_seconds;
+    move_target.target.0 -= dx;
+    move_target.target.1 -= dy;
+    let reached = move_target.target.0.abs() < 0.05 && move_target.target.1.abs() < 0.05;
+    (Vec2::new(dx, dy), reached)
+}
+
+/// Applies one step of movement towards the move target onto the character controller.  Returns whether the target has
+/// been reached, in which case the caller should remove the [`MoveTarget`].
+pub fn apply_movement(
+    transform: &mut Transform,
+    move_target: &mut MoveTarget,
+    controller: &mut KinematicCharacterController,
+    settings: &MovementSettings,
+    delta_seconds: f32,
+) -> bool {
+    let (delta, reached) = step_move_target(move_target, settings, delta_seconds);
+    match controller.translation {
+        None => controller.translation = Some(delta.extend(0.0)),
+        Some(ref mut translation) => {
+            translation.x += delta.x;
+            translation.y += delta.y;
+        }
+    };
+    let translation = controller.translation.unwrap();
+    set_player_rotation(translation.xy(), transform);
+    reached
+}
+
+/// Player collision.  Keeps the player afloat colliders, but don't apply horizontal forces from collisions.
+pub fn apply_player_collision(
+    rapier_context: &RapierContext,
+    transform: &Transform,
+    collider: &Collider,
+    controller: &mut KinematicCharacterController,
+    controller_output: Option<&KinematicCharacterControllerOutput>,
+    settings: &MovementSettings,
+    delta_seconds: f32,
+) {
+    // Shape cast to check if we are grounded
+    // We do this manually instead of `output.grounded` so the grounded check is always consistent
+    if rapier_context.cast_shape(transform.translation, transform.rotation, Vec3::NEG_Z, collider, 1.9, QueryFilter::only_fixed()).is_none() {
+        match controller.translation {
+            None => controller.translation = Some(Vec3::NEG_Z * settings.gravity_step_speed * delta_seconds),
+            Some(ref mut translation) => translation.z -= settings.gravity_step_speed * delta_seconds,
+        }
+    } else {
+        // TODO: handle oscillating collisions, such as bouncing up and down between above and below colliders indefinitely
+        if let Some(output) = controller_output {
+            // Keep player above ground
+            for collision in &output.collisions {
+                // TODO: handle other statuses
+                if collision.toi.status == TOIStatus::Converged {
+                    let penetration = collision.translation_remaining.length();
+                    let angle = collision.toi.normal1.angle_between(Vec3::Z);
+                    // Since we only allow player controlled movement to be horizontal, penetration at this point is
+                    // also always horizontal.  We want to find the vertical distance to pop up from the end of the
+                    // penetration, assuming the normal surface is flat, it will pop us out of the current collision.
+                    // let p = penetration length
+                    // let h = vertical distance we want to pop out of
+                    // let theta = angle from normal vector to +Z
+                    // based on triangle symmetry, we can get
+                    //      h = p * tan(theta)
+                    // However, if we are at a low enough angle -> 0, tan function will start to decrease and at 0
+                    // this function will just give us 0 which is incorrect.  If there is penetration when
+                    // theta == 0, we are penetrating vertically, which shouldn't happen, but if it does, just keep
+                    //      h = p
+                    // to pop up the full penetration length.
+                    let mut dz = penetration;
+                    if angle > 10.0 {
+                        dz *= angle.tan()
+                    }
+                    // Add a small offset buffer so player floats slightly above ground
+                    //dz += 0.2;
+                    // divide by number of collisions to average it out
+                    // TODO: optimize and make this properly check toi status
+                    dz /= output.collisions.len() as f32;
+                    // If collided entity is above, push it down
+                    if collision.toi.normal1.z < 0.0 {
+                        dz = -dz;
+                    }
+                    match controller.translation {
+                        None => controller.translation = Some(Vec3::new(0.0, 0.0, dz)),
+                        Some(ref mut translation) => {
+                            if translation.z <= 0.0 {
+                                translation.z = dz;
+                            } else {
+                                translation.z += dz
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Character controller used for players on both the server and for client-side prediction
+pub fn player_character_controller() -> KinematicCharacterController {
+    // Our collision system manually handles a lot of what the character controller gives us, so we have more custom
+    // tuning and control over parameters and behavior.  The defaults don't work so well with dramatic terrains.
+    // This is mainly here so we can collect and act on collisions.
+    KinematicCharacterController {
+        up: Vec3::Z,
+        // Use a low value because our collision system above already factors in an offset
+        offset: CharacterLength::Absolute(2.0),
+        slide: false,
+        // Our collision system uses normal forces to automatically do autostep on slopes.
+        // We can add this back or modify the collision system to add step heights if needed
+        autostep: None,
+        // autostep: Some(CharacterAutostep {
+        //     max_height: CharacterLength::Absolute(200.0),
+        //     min_width: CharacterLength::Absolute(200.0),
+        //     include_dynamic_bodies: false,
+        // }),
+        max_slope_climb_angle: 85.0,
+        min_slope_slide_angle: 0.0,
+        apply_impulse_to_dynamic_bodies: false,
+        // Our collision system uses gravity to push to ground already
+        snap_to_ground: None, //Some(CharacterLength::Absolute(10000.0)),
+        filter_flags: QueryFilterFlags::ONLY_FIXED,
+        ..default()
+    }
+}
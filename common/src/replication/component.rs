@@ -0,0 +1,31 @@
+use bevy::prelude::Component;
+use serde::{Deserialize, Serialize};
+
+use crate::networking::compact::CompactTransform;
+use crate::replication::ReplicatedComponent;
+
+/// Marks an entity on the server to be replicated to clients, and entities on clients that were replicated
+#[derive(Component)]
+pub struct Replicated;
+
+/// Identifies a replicated entity between server and clients, since entity ids differ between worlds
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NetworkId(pub u32);
+
+/// Replicated form of a `Transform`, kept in sync with it on both sides
+#[derive(Component, Serialize, Deserialize, Copy, Clone, PartialEq)]
+pub struct ReplicatedTransform(pub CompactTransform);
+
+impl ReplicatedComponent for ReplicatedTransform {
+    const NAME: &'static str = "transform";
+}
+
+/// Scene to render a replicated entity with on clients
+#[derive(Component, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReplicatedModel {
+    pub handle_id: String,
+}
+
+impl ReplicatedComponent for ReplicatedModel {
+    const NAME: &'static str = "model";
+}
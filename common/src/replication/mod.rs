@@ -0,0 +1,100 @@
+//! Server to client replication of arbitrary components.
+//!
+//! Entities marked [`Replicated`](component::Replicated) on the server are spawned, updated and despawned on clients
+//! through the [`Replication`](crate::networking::server_packets::Replication) packet.  Any component registered with
+//! [`AppReplicationExt::replicate`] on both sides is sent along with them, so new kinds of replicated entities don't need
+//! their own packets or systems.
+
+use bevy::ecs::world::{EntityMut, EntityRef};
+use bevy::log::error;
+use bevy::prelude::{App, Component, Resource};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::networking::registry::fingerprint;
+use crate::replication::component::{ReplicatedModel, ReplicatedTransform};
+
+pub mod component;
+
+/// A component that can be replicated from server to clients
+pub trait ReplicatedComponent: Component + Serialize + DeserializeOwned {
+    /// Identifies the component on the wire, so must be unique and stay the same between builds
+    const NAME: &'static str;
+}
+
+/// How to read and write one kind of replicated component
+pub struct ComponentReplication {
+    pub name: &'static str,
+    /// Hash of the name, sent on the wire instead of it
+    pub kind: u32,
+    pub serialize: fn(&EntityRef) -> Option<Vec<u8>>,
+    pub write: fn(&mut EntityMut, &[u8]) -> Result<(), bincode::Error>,
+    pub remove: fn(&mut EntityMut),
+}
+
+/// Every component registered for replication
+#[derive(Resource, Default)]
+pub struct ReplicationRegistry {
+    pub components: Vec<ComponentReplication>,
+}
+
+impl ReplicationRegistry {
+    pub fn get(&self, kind: u32) -> Option<&ComponentReplication> {
+        self.components.iter().find(|component| component.kind == kind)
+    }
+
+    fn register<C: ReplicatedComponent>(&mut self) {
+        let kind = fingerprint(C::NAME.as_bytes()) as u32;
+        if let Some(existing) = self.get(kind) {
+            if existing.name != C::NAME {
+                panic!("Replicated components {} and {} have the same kind", existing.name, C::NAME);
+            }
+            return;
+        }
+        self.components.push(ComponentReplication {
+            name: C::NAME,
+            kind,
+            serialize: serialize_component::<C>,
+            write: write_component::<C>,
+            remove: remove_component::<C>,
+        });
+    }
+}
+
+pub trait AppReplicationExt {
+    /// Register a component for replication.  Must be registered on both the client and server.
+    fn replicate<C: ReplicatedComponent>(&mut self) -> &mut Self;
+}
+
+impl AppReplicationExt for App {
+    fn replicate<C: ReplicatedComponent>(&mut self) -> &mut Self {
+        self.world.get_resource_or_insert_with(ReplicationRegistry::default).register::<C>();
+        self
+    }
+}
+
+/// Registers the built-in replicated components, for both client and server
+pub fn register_replicated_components(app: &mut App) {
+    app.replicate::<ReplicatedTransform>().replicate::<ReplicatedModel>();
+}
+
+fn serialize_component<C: ReplicatedComponent>(entity: &EntityRef) -> Option<Vec<u8>> {
+    let component = entity.get::<C>()?;
+    match bincode::serialize(component) {
+        Ok(bytes) => Some(bytes),
+        Err(e) => {
+            error!("Could not serialize replicated component {}: {}", C::NAME, e);
+            None
+        }
+    }
+}
+
+fn write_component<C: ReplicatedComponent>(entity: &mut EntityMut, bytes: &[u8]) -> Result<(), bincode::Error> {
+    let component: C = bincode::deserialize(bytes)?;
+    entity.insert(component);
+    Ok(())
+}
+
+fn remove_component<C: ReplicatedComponent>(entity: &mut EntityMut) {
+    entity.remove::<C>();
+}
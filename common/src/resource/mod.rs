@@ -1,11 +1,29 @@
+use bevy::math::Vec3;
 use bevy::prelude::Resource;
 use serde::{Deserialize, Serialize};
 
 /// Level metadata
-#[derive(Resource, Serialize, Deserialize, Debug)]
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
 pub struct LevelInfo {
     pub handle_id: String,
     // x, y, z, x-rotation
     pub scene_transform: [f32; 4],
     pub scale: f32,
+    /// Replicated positions are quantized within these bounds
+    pub bounds: LevelBounds,
+}
+
+/// Axis aligned box that everything in a level stays within
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct LevelBounds {
+    // x, y, z
+    pub min: [f32; 3],
+    // x, y, z
+    pub max: [f32; 3],
+}
+
+impl LevelBounds {
+    pub fn size(&self) -> Vec3 {
+        Vec3::from(self.max) - Vec3::from(self.min)
+    }
 }
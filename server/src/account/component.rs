@@ -0,0 +1,14 @@
+use bevy::prelude::Component;
+use bevy::tasks::Task;
+
+use mangovillage_common::networking::client_packets::Connect;
+
+use crate::account::resource::LoginCheck;
+
+/// Connect whose credentials are being checked, see [`AccountStore::start_login`](crate::account::resource::AccountStore::start_login)
+#[derive(Component)]
+pub struct PendingLogin {
+    pub remote_id: u32,
+    pub connect: Connect,
+    pub task: Task<LoginCheck>,
+}
@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use crate::account::resource::AccountStore;
+
+pub mod component;
+pub mod resource;
+
+/// Username and password login against accounts stored on disk
+pub struct AccountPlugin {
+    pub path: PathBuf,
+    /// Let clients create accounts for usernames that don't exist yet
+    pub allow_registration: bool,
+}
+
+impl Plugin for AccountPlugin {
+    fn build(&self, app: &mut App) {
+        let accounts = AccountStore::load(self.path.clone(), self.allow_registration)
+            .unwrap_or_else(|e| panic!("Could not load accounts from {:?}: {}", self.path, e));
+        info!("[server] Loaded {} accounts from {:?}, registration allowed={}", accounts.account_count(), self.path, self.allow_registration);
+        app.insert_resource(accounts);
+    }
+}
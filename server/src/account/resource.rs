@@ -0,0 +1,223 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use bevy::log::error;
+use bevy::prelude::Resource;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use serde::{Deserialize, Serialize};
+
+use mangovillage_common::networking::client_packets::Credentials;
+use mangovillage_common::networking::server_packets::RejectReason;
+
+/// Shortest password accepted when registering
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Account {
+    /// Stable id, never reused
+    pub id: u32,
+    pub username: String,
+    /// Argon2 hash in PHC string format, which includes the salt
+    pub password_hash: String,
+}
+
+/// Layout of the accounts file
+#[derive(Serialize, Deserialize, Default)]
+struct AccountsFile {
+    accounts: Vec<Account>,
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    Hash(argon2::password_hash::Error),
+}
+
+impl Display for AccountError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccountError::Io(e) => write!(f, "{}", e),
+            AccountError::Parse(e) => write!(f, "invalid accounts file: {}", e),
+            AccountError::Serialize(e) => write!(f, "could not serialize accounts: {}", e),
+            AccountError::Hash(e) => write!(f, "could not hash password: {}", e),
+        }
+    }
+}
+
+/// Result of checking credentials off the main thread, see [`AccountStore::start_login`]
+pub enum LoginCheck {
+    /// Password matches the account with this id
+    Verified(u32),
+    /// Wrong password or unknown username
+    Failed(RejectReason),
+    /// Unknown username that may be registered with this password hash
+    Register { username: String, password_hash: String },
+    /// Refused without checking a password, e.g. an invalid username to register
+    Rejected(RejectReason),
+}
+
+/// Accounts, backed by a TOML file on the server's disk
+#[derive(Resource)]
+pub struct AccountStore {
+    path: PathBuf,
+    /// Create accounts for unknown usernames when asked to
+    allow_registration: bool,
+    accounts: Vec<Account>,
+    /// Hash of a random password that logins to unknown usernames are checked against, so they take as long as logins
+    /// to existing accounts and don't reveal which usernames exist
+    dummy_hash: String,
+}
+
+impl AccountStore {
+    /// Load accounts from `path`, starting with none if the file does not exist yet
+    pub fn load(path: PathBuf, allow_registration: bool) -> Result<Self, AccountError> {
+        let file = match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).map_err(AccountError::Parse)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => AccountsFile::default(),
+            Err(e) => return Err(AccountError::Io(e)),
+        };
+        let dummy_password = SaltString::generate(&mut OsRng);
+        let dummy_hash = hash_password(dummy_password.as_str()).map_err(AccountError::Hash)?;
+        Ok(AccountStore { path, allow_registration, accounts: file.accounts, dummy_hash })
+    }
+
+    pub fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// Check credentials on the [`AsyncComputeTaskPool`], since hashing passwords takes long enough to stall the server.
+    /// Finish with [`Self::finish_login`].
+    pub fn start_login(&self, credentials: Credentials) -> Task<LoginCheck> {
+        let account = self.accounts.iter().find(|account| account.username == credentials.username).cloned();
+        let allow_registration = self.allow_registration;
+        let dummy_hash = self.dummy_hash.clone();
+        AsyncComputeTaskPool::get().spawn(async move { check_credentials(&credentials, account.as_ref(), allow_registration, &dummy_hash) })
+    }
+
+    /// Apply a finished check, registering the account if it asked to.  Returns the account id.
+    pub fn finish_login(&mut self, check: LoginCheck) -> Result<u32, RejectReason> {
+        match check {
+            LoginCheck::Verified(id) => Ok(id),
+            LoginCheck::Failed(reason) | LoginCheck::Rejected(reason) => Err(reason),
+            LoginCheck::Register { username, password_hash } => self.register(username, password_hash),
+        }
+    }
+
+    fn register(&mut self, username: String, password_hash: String) -> Result<u32, RejectReason> {
+        // Someone else may have registered it while the password was hashed
+        if self.accounts.iter().any(|account| account.username == username) {
+            return Err(RejectReason::UsernameTaken);
+        }
+        let id = self.accounts.iter().map(|account| account.id + 1).max().unwrap_or(0);
+        self.accounts.push(Account { id, username, password_hash });
+        if let Err(e) = self.save() {
+            error!("[server] Could not save accounts to {:?}: {}", self.path, e);
+            self.accounts.pop();
+            return Err(RejectReason::ServerError);
+        }
+        Ok(id)
+    }
+
+    /// Write accounts to a temporary file and move it into place, so a crash can't leave a partially written file
+    fn save(&self) -> Result<(), AccountError> {
+        let contents = toml::to_string(&AccountsFile { accounts: self.accounts.clone() }).map_err(AccountError::Serialize)?;
+        let temp_path = self.path.with_extension("toml.tmp");
+        fs::write(&temp_path, contents).map_err(AccountError::Io)?;
+        fs::rename(&temp_path, &self.path).map_err(AccountError::Io)
+    }
+}
+
+/// Check credentials against `account`, or against `dummy_hash` if there is no such account
+fn check_credentials(credentials: &Credentials, account: Option<&Account>, allow_registration: bool, dummy_hash: &str) -> LoginCheck {
+    let Some(account) = account else {
+        if !credentials.register {
+            verify_password(&credentials.password, dummy_hash);
+            return LoginCheck::Failed(RejectReason::InvalidCredentials);
+        }
+        if !allow_registration {
+            return LoginCheck::Rejected(RejectReason::RegistrationDisabled);
+        }
+        let username = &credentials.username;
+        if !(3..=32).contains(&username.len()) || !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return LoginCheck::Rejected(RejectReason::InvalidUsername);
+        }
+        if credentials.password.chars().count() < MIN_PASSWORD_LENGTH {
+            return LoginCheck::Rejected(RejectReason::PasswordTooShort { min_length: MIN_PASSWORD_LENGTH });
+        }
+        return match hash_password(&credentials.password) {
+            Ok(password_hash) => LoginCheck::Register { username: username.clone(), password_hash },
+            Err(e) => {
+                error!("[server] {}", AccountError::Hash(e));
+                LoginCheck::Rejected(RejectReason::ServerError)
+            }
+        };
+    };
+    if verify_password(&credentials.password, &account.password_hash) {
+        LoginCheck::Verified(account.id)
+    } else if credentials.register {
+        LoginCheck::Failed(RejectReason::UsernameTaken)
+    } else {
+        LoginCheck::Failed(RejectReason::InvalidCredentials)
+    }
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash).map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok()).unwrap_or(false)
+}
+
+fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(username: &str, password: &str, register: bool) -> Credentials {
+        Credentials { username: username.to_string(), password: password.to_string(), register }
+    }
+
+    fn account() -> Account {
+        Account { id: 7, username: "alice".to_string(), password_hash: hash_password("password123").unwrap() }
+    }
+
+    #[test]
+    fn right_password_is_verified() {
+        let dummy_hash = hash_password("dummy").unwrap();
+        let check = check_credentials(&credentials("alice", "password123", false), Some(&account()), true, &dummy_hash);
+        assert!(matches!(check, LoginCheck::Verified(7)));
+    }
+
+    #[test]
+    fn wrong_password_and_unknown_username_fail_the_same_way() {
+        let dummy_hash = hash_password("dummy").unwrap();
+        let wrong_password = check_credentials(&credentials("alice", "password456", false), Some(&account()), true, &dummy_hash);
+        assert!(matches!(wrong_password, LoginCheck::Failed(RejectReason::InvalidCredentials)));
+        let unknown = check_credentials(&credentials("bob", "password123", false), None, true, &dummy_hash);
+        assert!(matches!(unknown, LoginCheck::Failed(RejectReason::InvalidCredentials)));
+    }
+
+    #[test]
+    fn registering_hashes_the_password() {
+        let dummy_hash = hash_password("dummy").unwrap();
+        match check_credentials(&credentials("bob", "password123", true), None, true, &dummy_hash) {
+            LoginCheck::Register { username, password_hash } => {
+                assert_eq!(username, "bob");
+                assert!(verify_password("password123", &password_hash));
+            }
+            _ => panic!("expected a registration"),
+        }
+        let disabled = check_credentials(&credentials("bob", "password123", true), None, false, &dummy_hash);
+        assert!(matches!(disabled, LoginCheck::Rejected(RejectReason::RegistrationDisabled)));
+        let short = check_credentials(&credentials("bob", "short", true), None, true, &dummy_hash);
+        assert!(matches!(short, LoginCheck::Rejected(RejectReason::PasswordTooShort { .. })));
+    }
+}
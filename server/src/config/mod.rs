@@ -0,0 +1,482 @@
+//! Server configuration, read from a TOML file with command line overrides
+
+use std::fs;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bevy::math::Vec3;
+use clap::Parser;
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+
+use mangovillage_common::networking::transport::{LinkConditions, NetworkSimulationArgs, NetworkSimulationSettings};
+use mangovillage_common::player::movement::MovementSettings;
+use mangovillage_common::player::PLAYER_MODEL_HANDLE_IDS;
+use mangovillage_common::resource::{LevelBounds, LevelInfo};
+
+use crate::player::resource::SpawnRules;
+
+/// Config file used when none is given.  Defaults are used if it doesn't exist.
+const DEFAULT_CONFIG_PATH: &str = "server.toml";
+
+#[derive(Parser)]
+#[command(about = "Mango Village server")]
+pub struct Cli {
+    /// Config file to read, defaults to server.toml if it exists
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Address to listen on, e.g. 0.0.0.0:28154
+    #[arg(long)]
+    pub bind: Option<String>,
+    #[arg(long)]
+    pub max_players: Option<usize>,
+    /// Simulation ticks per second
+    #[arg(long)]
+    pub tick_rate: Option<u32>,
+    /// Snapshots sent to clients per second
+    #[arg(long)]
+    pub send_rate: Option<u32>,
+    /// Scene handle of the level to load
+    #[arg(long)]
+    pub level: Option<String>,
+    #[arg(long)]
+    pub allow_registration: Option<bool>,
+    #[command(flatten)]
+    pub network_simulation: NetworkSimulationArgs,
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub network: NetworkSettings,
+    pub simulation: SimulationSettings,
+    pub physics: PhysicsSettings,
+    pub world: WorldSettings,
+    pub spawn: SpawnSettings,
+    pub accounts: AccountSettings,
+    pub persistence: PersistenceSettings,
+    pub network_simulation: NetworkSimulationSettings,
+    pub diagnostics: DiagnosticsSettings,
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSettings {
+    #[derivative(Default(value = "\"127.0.0.1:28154\".to_string()"))]
+    pub bind_address: String,
+    #[derivative(Default(value = "64"))]
+    pub max_players: usize,
+    #[derivative(Default(value = "30"))]
+    pub keep_alive_secs: u64,
+    /// Connections that don't complete the Connect handshake within this are closed
+    #[derivative(Default(value = "10"))]
+    pub handshake_timeout_secs: u64,
+    /// Players that lose connection are kept this long for their client to reconnect
+    #[derivative(Default(value = "60"))]
+    pub session_grace_period_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationSettings {
+    #[derivative(Default(value = "60"))]
+    pub tick_rate: u32,
+    #[derivative(Default(value = "30"))]
+    pub send_rate: u32,
+    /// Players further than this from a client are not sent to it
+    #[derivative(Default(value = "500.0"))]
+    pub interest_radius: f32,
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhysicsSettings {
+    /// Rapier gravity along z
+    #[derivative(Default(value = "-100.0"))]
+    pub gravity: f32,
+    #[derivative(Default(value = "100.0"))]
+    pub movement_speed: f32,
+    #[derivative(Default(value = "100.0"))]
+    pub gravity_step_speed: f32,
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldSettings {
+    #[derivative(Default(value = "\"models/small/big.glb#Scene0\".to_string()"))]
+    pub level: String,
+    /// x, y, z translation and rotation around x of the level scene
+    #[derivative(Default(value = "[0.0, 0.0, 0.0, std::f32::consts::PI / 2.0]"))]
+    pub scene_transform: [f32; 4],
+    #[derivative(Default(value = "1.0"))]
+    pub scale: f32,
+    #[derivative(Default(value = "[-2048.0, -2048.0, -512.0]"))]
+    pub bounds_min: [f32; 3],
+    #[derivative(Default(value = "[2048.0, 2048.0, 1536.0]"))]
+    pub bounds_max: [f32; 3],
+    /// Props the server spawns and replicates to clients near them
+    pub props: Vec<PropSettings>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PropSettings {
+    /// Scene to render the prop with
+    pub model: String,
+    pub position: [f32; 3],
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpawnSettings {
+    /// Where new characters spawn
+    #[derivative(Default(value = "[-10.0, 0.0, 150.0]"))]
+    pub point: [f32; 3],
+    /// Direction new characters face
+    #[derivative(Default(value = "[0.0, -1.0, 0.0]"))]
+    pub facing: [f32; 3],
+    /// Index into the player models new characters use
+    #[derivative(Default(value = "0"))]
+    pub player_model: u8,
+    /// Spawn returning characters where they were saved instead of at the spawn point
+    #[derivative(Default(value = "true"))]
+    pub restore_saved: bool,
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountSettings {
+    #[derivative(Default(value = "PathBuf::from(\"accounts.toml\")"))]
+    pub path: PathBuf,
+    /// Let clients create new accounts
+    #[derivative(Default(value = "true"))]
+    pub allow_registration: bool,
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistenceSettings {
+    /// Directory characters are saved in
+    #[derivative(Default(value = "PathBuf::from(\"characters\")"))]
+    pub characters_dir: PathBuf,
+    /// Characters are also saved when they leave and when the server exits
+    #[derivative(Default(value = "60"))]
+    pub autosave_interval_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Derivative)]
+#[derivative(Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiagnosticsSettings {
+    /// Log bandwidth, packet rates, drops and round trip times
+    #[derivative(Default(value = "false"))]
+    pub log: bool,
+    #[derivative(Default(value = "10"))]
+    pub log_interval_secs: u64,
+    /// Clients are pinged this often to measure round trip times
+    #[derivative(Default(value = "1"))]
+    pub ping_interval_secs: u64,
+}
+
+impl ServerSettings {
+    /// Read settings from the config file and command line, exiting with an error message if they are invalid
+    pub fn load() -> Self {
+        let cli = Cli::parse();
+        let path = cli.config.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+        // Only the default config is optional
+        let mut settings = match ServerSettings::read(&path, cli.config.is_none()) {
+            Ok(settings) => settings,
+            Err(e) => exit_with_errors(&path, &[e]),
+        };
+        settings.apply_overrides(cli);
+        if let Err(errors) = settings.validate() {
+            exit_with_errors(&path, &errors);
+        }
+        settings
+    }
+
+    /// Read settings from `path`, using defaults if it doesn't exist and is `optional`.  Not validated.
+    pub fn read(path: &Path, optional: bool) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str::<ServerSettings>(&contents).map_err(|e| e.to_string()),
+            Err(e) if e.kind() == ErrorKind::NotFound && optional => {
+                println!("[server] No config file at {:?}, using defaults", path);
+                Ok(ServerSettings::default())
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn apply_overrides(&mut self, cli: Cli) {
+        if let Some(bind) = cli.bind {
+            self.network.bind_address = bind;
+        }
+        if let Some(max_players) = cli.max_players {
+            self.network.max_players = max_players;
+        }
+        if let Some(tick_rate) = cli.tick_rate {
+            self.simulation.tick_rate = tick_rate;
+        }
+        if let Some(send_rate) = cli.send_rate {
+            self.simulation.send_rate = send_rate;
+        }
+        if let Some(level) = cli.level {
+            self.world.level = level;
+        }
+        if let Some(allow_registration) = cli.allow_registration {
+            self.accounts.allow_registration = allow_registration;
+        }
+        self.network_simulation.apply_overrides(&cli.network_simulation);
+    }
+
+    /// Every problem with the settings, so they can all be fixed at once
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if self.network.bind_address.parse::<SocketAddr>().is_err() {
+            errors.push(format!("network.bind_address {:?} is not an ip:port address", self.network.bind_address));
+        }
+        if self.network.max_players == 0 {
+            errors.push("network.max_players must be at least 1".to_string());
+        }
+        if self.network.keep_alive_secs == 0 {
+            errors.push("network.keep_alive_secs must be at least 1".to_string());
+        }
+        if !(1..=240).contains(&self.simulation.tick_rate) {
+            errors.push(format!("simulation.tick_rate must be between 1 and 240, got {}", self.simulation.tick_rate));
+        }
+        if self.simulation.send_rate == 0 || self.simulation.send_rate > self.simulation.tick_rate {
+            errors.push(format!("simulation.send_rate must be between 1 and tick_rate, got {}", self.simulation.send_rate));
+        }
+        if !positive(self.simulation.interest_radius) {
+            errors.push("simulation.interest_radius must be positive".to_string());
+        }
+        if !self.physics.gravity.is_finite() {
+            errors.push("physics.gravity must be a number".to_string());
+        }
+        if !positive(self.physics.movement_speed) || !positive(self.physics.gravity_step_speed) {
+            errors.push("physics.movement_speed and physics.gravity_step_speed must be positive".to_string());
+        }
+        if self.world.level.is_empty() {
+            errors.push("world.level must not be empty".to_string());
+        }
+        if !positive(self.world.scale) {
+            errors.push("world.scale must be positive".to_string());
+        }
+        if !finite(&self.world.scene_transform) {
+            errors.push(format!("world.scene_transform {:?} must be finite", self.world.scene_transform));
+        }
+        // Positions are only checked against the bounds if the bounds make sense
+        let bounds = self.level().bounds;
+        if !finite(&bounds.min) || !finite(&bounds.max) {
+            errors.push(format!("world.bounds_min {:?} and world.bounds_max {:?} must be finite", bounds.min, bounds.max));
+        } else if (0..3).any(|i| bounds.max[i] <= bounds.min[i]) {
+            errors.push(format!("world.bounds_min {:?} must be below world.bounds_max {:?}", bounds.min, bounds.max));
+        } else {
+            let within_bounds = |position: &[f32; 3]| (0..3).all(|i| (bounds.min[i]..=bounds.max[i]).contains(&position[i]));
+            if !within_bounds(&self.spawn.point) {
+                errors.push(format!("spawn.point {:?} is outside of the world bounds", self.spawn.point));
+            }
+            for (i, prop) in self.world.props.iter().enumerate() {
+                if !within_bounds(&prop.position) {
+                    errors.push(format!("world.props[{}].position {:?} is outside of the world bounds", i, prop.position));
+                }
+            }
+        }
+        for (i, prop) in self.world.props.iter().enumerate() {
+            if prop.model.is_empty() {
+                errors.push(format!("world.props[{}].model must not be empty", i));
+            }
+        }
+        if !finite(&self.spawn.facing) {
+            errors.push(format!("spawn.facing {:?} must be finite", self.spawn.facing));
+        } else if Vec3::from_array(self.spawn.facing).truncate().length_squared() == 0.0 {
+            errors.push("spawn.facing must point horizontally".to_string());
+        }
+        if self.spawn.player_model as usize >= PLAYER_MODEL_HANDLE_IDS.len() {
+            errors.push(format!("spawn.player_model must be less than {}", PLAYER_MODEL_HANDLE_IDS.len()));
+        }
+        if self.persistence.autosave_interval_secs == 0 {
+            errors.push("persistence.autosave_interval_secs must be at least 1".to_string());
+        }
+        if self.diagnostics.log_interval_secs == 0 || self.diagnostics.ping_interval_secs == 0 {
+            errors.push("diagnostics.log_interval_secs and diagnostics.ping_interval_secs must be at least 1".to_string());
+        }
+        if let Err(e) = self.network_simulation.validate() {
+            errors.push(format!("network_simulation.{}", e));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn level(&self) -> LevelInfo {
+        LevelInfo {
+            handle_id: self.world.level.clone(),
+            scene_transform: self.world.scene_transform,
+            scale: self.world.scale,
+            bounds: LevelBounds { min: self.world.bounds_min, max: self.world.bounds_max },
+        }
+    }
+
+    pub fn movement(&self) -> MovementSettings {
+        MovementSettings { speed: self.physics.movement_speed, gravity_step_speed: self.physics.gravity_step_speed }
+    }
+
+    pub fn spawn_rules(&self) -> SpawnRules {
+        SpawnRules {
+            point: Vec3::from_array(self.spawn.point),
+            facing: Vec3::from_array(self.spawn.facing),
+            handle_id: self.spawn.player_model,
+            restore_saved: self.spawn.restore_saved,
+        }
+    }
+
+    pub fn network_conditions(&self) -> LinkConditions {
+        self.network_simulation.conditions()
+    }
+
+    pub fn keep_alive(&self) -> Duration {
+        Duration::from_secs(self.network.keep_alive_secs)
+    }
+}
+
+fn positive(value: f32) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+fn finite(values: &[f32]) -> bool {
+    values.iter().all(|value| value.is_finite())
+}
+
+fn exit_with_errors(path: &Path, errors: &[String]) -> ! {
+    eprintln!("[server] Invalid server config {:?}:", path);
+    for error in errors {
+        eprintln!("  - {}", error);
+    }
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Validation errors of the default settings changed by `configure`
+    fn errors(configure: impl FnOnce(&mut ServerSettings)) -> Vec<String> {
+        let mut settings = ServerSettings::default();
+        configure(&mut settings);
+        settings.validate().err().unwrap_or_default()
+    }
+
+    fn assert_rejected(field: &str, configure: impl FnOnce(&mut ServerSettings)) {
+        let errors = errors(configure);
+        assert!(errors.len() == 1 && errors[0].contains(field), "expected one error about {}, got {:?}", field, errors);
+    }
+
+    fn overridden(args: &[&str]) -> ServerSettings {
+        let mut settings = ServerSettings::default();
+        settings.apply_overrides(Cli::parse_from([&["mangovillage_server"][..], args].concat()));
+        settings
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(ServerSettings::default().validate().is_ok());
+        assert!(errors(|settings| settings.world.props.push(PropSettings { model: "prop.glb".to_string(), position: [0.0; 3] })).is_empty());
+    }
+
+    #[test]
+    fn invalid_network_settings_are_rejected() {
+        assert_rejected("network.bind_address", |settings| settings.network.bind_address = "localhost".to_string());
+        assert_rejected("network.max_players", |settings| settings.network.max_players = 0);
+        assert_rejected("network.keep_alive_secs", |settings| settings.network.keep_alive_secs = 0);
+    }
+
+    #[test]
+    fn invalid_simulation_settings_are_rejected() {
+        assert_rejected("simulation.tick_rate", |settings| settings.simulation.tick_rate = 241);
+        assert_rejected("simulation.send_rate", |settings| settings.simulation.send_rate = 0);
+        assert_rejected("simulation.send_rate", |settings| settings.simulation.send_rate = settings.simulation.tick_rate + 1);
+        assert_rejected("simulation.interest_radius", |settings| settings.simulation.interest_radius = f32::NAN);
+        assert_rejected("physics.gravity", |settings| settings.physics.gravity = f32::INFINITY);
+        assert_rejected("physics.movement_speed", |settings| settings.physics.movement_speed = 0.0);
+        assert_rejected("physics.movement_speed", |settings| settings.physics.gravity_step_speed = -1.0);
+    }
+
+    #[test]
+    fn invalid_world_settings_are_rejected() {
+        assert_rejected("world.level", |settings| settings.world.level.clear());
+        assert_rejected("world.scale", |settings| settings.world.scale = f32::NAN);
+        assert_rejected("world.scene_transform", |settings| settings.world.scene_transform[3] = f32::NAN);
+        assert_rejected("must be finite", |settings| settings.world.bounds_max[0] = f32::INFINITY);
+        assert_rejected("must be below", |settings| settings.world.bounds_min[2] = settings.world.bounds_max[2]);
+        assert_rejected("world.props[0].model", |settings| settings.world.props.push(PropSettings { model: String::new(), position: [0.0; 3] }));
+        assert_rejected("world.props[0].position", |settings| {
+            settings.world.props.push(PropSettings { model: "prop.glb".to_string(), position: [0.0, 0.0, 1e6] })
+        });
+    }
+
+    #[test]
+    fn invalid_spawn_settings_are_rejected() {
+        assert_rejected("spawn.point", |settings| settings.spawn.point = [1e6, 0.0, 0.0]);
+        assert_rejected("spawn.point", |settings| settings.spawn.point = [f32::NAN, 0.0, 0.0]);
+        assert_rejected("spawn.facing", |settings| settings.spawn.facing = [0.0, 0.0, 1.0]);
+        assert_rejected("spawn.facing", |settings| settings.spawn.facing = [f32::NAN, 1.0, 0.0]);
+        assert_rejected("spawn.player_model", |settings| settings.spawn.player_model = PLAYER_MODEL_HANDLE_IDS.len() as u8);
+    }
+
+    #[test]
+    fn invalid_other_settings_are_rejected() {
+        assert_rejected("persistence.autosave_interval_secs", |settings| settings.persistence.autosave_interval_secs = 0);
+        assert_rejected("diagnostics.log_interval_secs", |settings| settings.diagnostics.ping_interval_secs = 0);
+        assert_rejected("network_simulation.loss", |settings| settings.network_simulation.loss = 1.5);
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let errors = errors(|settings| {
+            settings.network.max_players = 0;
+            settings.world.level.clear();
+            settings.spawn.facing = [0.0, 0.0, 1.0];
+        });
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+    }
+
+    #[test]
+    fn command_line_overrides_settings() {
+        assert_eq!(overridden(&["--bind", "0.0.0.0:1234"]).network.bind_address, "0.0.0.0:1234");
+        assert_eq!(overridden(&["--max-players", "8"]).network.max_players, 8);
+        assert_eq!(overridden(&["--tick-rate", "30"]).simulation.tick_rate, 30);
+        assert_eq!(overridden(&["--send-rate", "10"]).simulation.send_rate, 10);
+        assert_eq!(overridden(&["--level", "models/other.glb#Scene0"]).world.level, "models/other.glb#Scene0");
+        assert!(!overridden(&["--allow-registration", "false"]).accounts.allow_registration);
+    }
+
+    #[test]
+    fn command_line_network_simulation_turns_it_on() {
+        let defaults = overridden(&[]);
+        assert!(!defaults.network_simulation.enabled);
+        assert!(defaults.network_conditions().is_ideal());
+
+        let simulation =
+            overridden(&["--sim-latency", "100", "--sim-jitter", "20", "--sim-loss", "0.1", "--sim-duplicate", "0.2", "--sim-reorder", "0.3"])
+                .network_simulation;
+        assert!(simulation.enabled);
+        assert_eq!(simulation.latency_ms, 100);
+        assert_eq!(simulation.jitter_ms, 20);
+        assert_eq!(simulation.loss, 0.1);
+        assert_eq!(simulation.duplicate, 0.2);
+        assert_eq!(simulation.reorder, 0.3);
+    }
+}
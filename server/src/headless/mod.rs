@@ -0,0 +1,65 @@
+//! Engine plugins for running the server without a display server or GPU
+
+use bevy::animation::{AnimationClip, AnimationPlayer};
+use bevy::app::PluginGroupBuilder;
+use bevy::diagnostic::DiagnosticsPlugin;
+use bevy::gltf::GltfPlugin;
+use bevy::pbr::{
+    CascadeShadowConfig, Cascades, CascadesVisibleEntities, CubemapVisibleEntities, DirectionalLight, PointLight, SpotLight, StandardMaterial,
+};
+use bevy::prelude::*;
+use bevy::render::mesh::morph::{MeshMorphWeights, MorphWeights};
+use bevy::render::mesh::MeshPlugin;
+use bevy::render::primitives::{Aabb, CascadesFrusta, CubemapFrusta, Frustum};
+use bevy::render::view::{ComputedVisibility, VisibleEntities};
+use bevy::scene::ScenePlugin;
+use bevy_embedded_assets::EmbeddedAssetPlugin;
+
+/// Everything the server needs on top of [`MinimalPlugins`] to load glTF levels and player models and build colliders
+/// from their meshes.  Leaves out windowing, input, audio and rendering.
+pub struct HeadlessPlugins;
+
+impl PluginGroup for HeadlessPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(TransformPlugin)
+            .add(DiagnosticsPlugin)
+            .add(HierarchyPlugin)
+            .add(EmbeddedAssetPlugin)
+            .add(AssetPlugin::default())
+            .add(MeshPlugin)
+            .add(ScenePlugin)
+            .add(GltfPlugin::default())
+            .add(SceneTypesPlugin)
+    }
+}
+
+/// Asset and component types glTF scenes are made of.  The render plugins register these on clients, and scenes
+/// can't be spawned with any of them missing.  Cameras in scenes aren't supported.
+pub struct SceneTypesPlugin;
+
+impl Plugin for SceneTypesPlugin {
+    fn build(&self, app: &mut App) {
+        // Loaded along with meshes even though the server never draws them
+        app.add_asset::<Image>()
+            .add_asset::<StandardMaterial>()
+            .add_asset::<AnimationClip>()
+            .register_type::<AnimationPlayer>()
+            .register_type::<Visibility>()
+            .register_type::<ComputedVisibility>()
+            .register_type::<Aabb>()
+            .register_type::<MorphWeights>()
+            .register_type::<MeshMorphWeights>()
+            .register_type::<DirectionalLight>()
+            .register_type::<PointLight>()
+            .register_type::<SpotLight>()
+            .register_type::<CascadeShadowConfig>()
+            .register_type::<Cascades>()
+            .register_type::<CascadesFrusta>()
+            .register_type::<CascadesVisibleEntities>()
+            .register_type::<CubemapFrusta>()
+            .register_type::<CubemapVisibleEntities>()
+            .register_type::<Frustum>()
+            .register_type::<VisibleEntities>();
+    }
+}
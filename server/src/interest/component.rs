@@ -0,0 +1,14 @@
+use bevy::prelude::Component;
+use bevy::utils::{HashMap, HashSet};
+
+use mangovillage_common::networking::server_packets::LeaveReason;
+
+/// Player ids a client currently knows about, so we can tell it why players leave its area of interest
+#[derive(Component, Default)]
+pub struct InterestSet {
+    /// Players in the last snapshot sent to the client
+    pub visible: HashSet<u32>,
+    /// Players that left the server while the client could see them.  Sent with their removal in every snapshot until
+    /// the client acknowledges one without them, otherwise they left because they went out of range.
+    pub left_reasons: HashMap<u32, LeaveReason>,
+}
@@ -0,0 +1,18 @@
+use bevy::prelude::*;
+
+use crate::interest::resource::{InterestSettings, SpatialGrid};
+
+pub mod component;
+pub mod resource;
+
+/// Area of interest filtering, so clients are only sent players near them
+pub struct InterestPlugin {
+    /// Players further than this from a client's player are not replicated to it
+    pub radius: f32,
+}
+
+impl Plugin for InterestPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(InterestSettings { radius: self.radius }).insert_resource(SpatialGrid::new(self.radius));
+    }
+}
@@ -0,0 +1,49 @@
+use bevy::math::Vec2;
+use bevy::prelude::Resource;
+use bevy::utils::HashMap;
+
+#[derive(Resource)]
+pub struct InterestSettings {
+    pub radius: f32,
+}
+
+/// Uniform grid over the horizontal plane for finding players within a radius without checking every pair
+#[derive(Resource)]
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<(u32, Vec2)>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialGrid { cell_size: cell_size.max(f32::EPSILON), cells: HashMap::default() }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    pub fn insert(&mut self, id: u32, position: Vec2) {
+        self.cells.entry(self.cell(position)).or_default().push((id, position));
+    }
+
+    /// Ids of everything within `radius` of `center`
+    pub fn query(&self, center: Vec2, radius: f32) -> Vec<u32> {
+        let (min_x, min_y) = self.cell(center - Vec2::splat(radius));
+        let (max_x, max_y) = self.cell(center + Vec2::splat(radius));
+        let radius_squared = radius * radius;
+        let mut found = Vec::new();
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                if let Some(cell) = self.cells.get(&(x, y)) {
+                    found.extend(cell.iter().filter(|(_, position)| position.distance_squared(center) <= radius_squared).map(|(id, _)| *id));
+                }
+            }
+        }
+        found
+    }
+
+    fn cell(&self, position: Vec2) -> (i32, i32) {
+        ((position.x / self.cell_size).floor() as i32, (position.y / self.cell_size).floor() as i32)
+    }
+}
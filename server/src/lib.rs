@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use bevy::diagnostic::LogDiagnosticsPlugin;
+use bevy::prelude::*;
+
+use mangovillage_common::networking::diagnostics::NetworkDiagnosticsPlugin;
+
+use crate::config::ServerSettings;
+use crate::state::ServerState;
+
+pub mod account;
+pub mod config;
+pub mod headless;
+pub mod interest;
+pub mod networking;
+pub mod persistence;
+pub mod physics;
+pub mod player;
+pub mod replication;
+pub mod shutdown;
+pub mod state;
+pub mod tick;
+pub mod validation;
+pub mod world;
+
+/// The whole server simulation, on top of whatever engine plugins the app runs with.  Used by the server binary and
+/// by clients hosting a game.
+pub struct GameServerPlugin {
+    pub settings: ServerSettings,
+}
+
+impl Plugin for GameServerPlugin {
+    fn build(&self, app: &mut App) {
+        let settings = &self.settings;
+        app.add_state::<ServerState>().add_plugins((
+            tick::TickPlugin { tick_rate: settings.simulation.tick_rate, send_rate: settings.simulation.send_rate },
+            networking::ServerPlugin {
+                server_addr: settings.network.bind_address.clone(),
+                keep_alive: settings.keep_alive(),
+                max_players: settings.network.max_players,
+                conditions: settings.network_conditions(),
+                handshake_timeout: Duration::from_secs(settings.network.handshake_timeout_secs),
+                session_grace_period: Duration::from_secs(settings.network.session_grace_period_secs),
+                ping_interval: Duration::from_secs(settings.diagnostics.ping_interval_secs),
+            },
+            validation::ValidationPlugin { movement_rate: settings.simulation.tick_rate },
+            account::AccountPlugin { path: settings.accounts.path.clone(), allow_registration: settings.accounts.allow_registration },
+            persistence::PersistencePlugin {
+                dir: settings.persistence.characters_dir.clone(),
+                autosave_interval: Duration::from_secs(settings.persistence.autosave_interval_secs),
+            },
+            interest::InterestPlugin { radius: settings.simulation.interest_radius },
+            world::WorldPlugin { level: settings.level(), props: settings.world.props.clone() },
+            physics::PhysicsPlugin { gravity: settings.physics.gravity },
+            player::PlayerPlugin { movement: settings.movement(), spawn: settings.spawn_rules() },
+            replication::ReplicationPlugin,
+            NetworkDiagnosticsPlugin,
+        ));
+        if settings.diagnostics.log {
+            app.add_plugins(LogDiagnosticsPlugin {
+                wait_duration: Duration::from_secs(settings.diagnostics.log_interval_secs),
+                filter: Some(NetworkDiagnosticsPlugin::DIAGNOSTICS.to_vec()),
+                ..default()
+            });
+        }
+    }
+}
@@ -0,0 +1,9 @@
+use bevy::prelude::Event;
+
+/// Disconnect a client and remove its player
+#[derive(Event)]
+pub struct KickClient {
+    pub remote_id: u32,
+    /// Logged, not sent to the client
+    pub reason: String,
+}
@@ -1,91 +1,284 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use bevy::prelude::*;
-use bevy::utils::HashSet;
-use durian::{register_receive, register_send, PacketManager, ServerConfig};
+use bevy::utils::HashMap;
+use durian::ServerConfig;
+use futures_lite::future;
 
 use mangovillage_common::networking::client_packets::{
-    Connect, ConnectPacketBuilder, Disconnect, DisconnectPacketBuilder, Movement, MovementPacketBuilder,
+    Connect, ConnectPacketBuilder, Disconnect, DisconnectPacketBuilder, Pong, PongPacketBuilder,
 };
-use mangovillage_common::networking::server_packets::{ConnectAck, Players, SpawnScene};
+use mangovillage_common::networking::diagnostics::resource::NetworkStats;
+use mangovillage_common::networking::registry::SCHEMA_FINGERPRINT;
+use mangovillage_common::networking::server_packets::{
+    ConnectAck, ConnectReject, LeaveReason, Ping, PlayerLeft, PlayersLeft, RejectReason, SpawnScene,
+};
+use mangovillage_common::networking::transport::{
+    insert_network_conditions, DurianTransport, LinkConditions, MeteredTransport, NetworkConditions, SimulatedTransport,
+};
+use mangovillage_common::networking::{BUILD_HASH, PROTOCOL_VERSION};
 use mangovillage_common::player::component::PlayerData;
+use mangovillage_common::player::movement::MovementSettings;
 use mangovillage_common::resource::LevelInfo;
-use mangovillage_common::util;
 
-use crate::networking::resource::{ServerInfo, ServerPacketManager};
+use crate::account::component::PendingLogin;
+use crate::account::resource::{AccountStore, LoginCheck};
+use crate::interest::component::InterestSet;
+use crate::networking::event::KickClient;
+use crate::networking::resource::{PendingConnections, Pings, ServerInfo, ServerMetrics, ServerPacketManager, SessionSettings};
+use crate::persistence;
+use crate::persistence::resource::CharacterStore;
 use crate::player;
-use crate::player::component::ServerPlayer;
+use crate::player::component::{CharacterStats, Disconnected, ServerPlayer, SnapshotHistory};
+use crate::player::resource::SpawnRules;
 use crate::state::ServerState;
+use crate::tick::resource::{ServerTick, TickConfig};
+use crate::validation::{Validate, Validator, Violation};
 
+pub mod event;
 pub mod resource;
 
 pub struct ServerPlugin {
     pub server_addr: String,
+    pub keep_alive: Duration,
+    /// Logins beyond this are rejected, players waiting to resume their session count towards it
+    pub max_players: usize,
+    /// How long a new connection has to complete the Connect/ConnectAck handshake before it is closed
+    pub handshake_timeout: Duration,
+    /// How long a player whose connection was lost is kept around for its client to resume the session
+    pub session_grace_period: Duration,
+    /// Simulated on every connection, see [`insert_network_conditions`]
+    pub conditions: LinkConditions,
+    /// How often clients are pinged to measure round trip times
+    pub ping_interval: Duration,
 }
 
 impl Plugin for ServerPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(ServerInfo { server_addr: self.server_addr.clone() })
-            .add_systems(Startup, init_server)
+        insert_network_conditions(app, &self.conditions, false);
+        app.init_resource::<NetworkStats>()
+            .insert_resource(ServerInfo { server_addr: self.server_addr.clone(), keep_alive: self.keep_alive })
+            .init_resource::<ServerMetrics>()
+            .insert_resource(PendingConnections::new(self.handshake_timeout.as_secs_f64()))
+            .insert_resource(SessionSettings { grace_period: self.session_grace_period.as_secs_f64(), max_players: self.max_players })
+            .insert_resource(Pings::new(self.ping_interval.as_secs_f64()))
+            .add_event::<KickClient>()
+            // Unless a transport was inserted before the plugin, e.g. for tests
+            .add_systems(Startup, init_server.run_if(not(resource_exists::<ServerPacketManager>())))
             .add_systems(Update, transition_load_world.run_if(in_state(ServerState::StartUp)))
-            .add_systems(Update, (handle_leaves, handle_connects).run_if(in_state(ServerState::Running)));
+            .add_systems(
+                Update,
+                (handle_leaves, handle_connects, finish_logins, evict_pending_connections).chain().run_if(in_state(ServerState::Running)),
+            )
+            .add_systems(Update, (receive_pongs, send_pings).chain().run_if(in_state(ServerState::Running)));
     }
 }
 
-fn init_server(mut commands: Commands, server_info: Res<ServerInfo>) {
-    let mut manager = PacketManager::new();
-    // register server side packets
-    let receives = util::validate_register_results(
-        false,
-        register_receive!(manager, (Connect, ConnectPacketBuilder), (Disconnect, DisconnectPacketBuilder), (Movement, MovementPacketBuilder)),
-    );
-    let sends = util::validate_register_results(false, register_send!(manager, ConnectAck, SpawnScene, Players));
-    // TODO: better error handling
-    if !receives {
-        panic!("Failed to register all receive packets");
-    }
-    if !sends {
-        panic!("Failed to register all send packets");
-    }
+fn init_server(mut commands: Commands, server_info: Res<ServerInfo>, conditions: Res<NetworkConditions>, stats: Res<NetworkStats>) {
     let mut server_config = ServerConfig::new(server_info.server_addr.clone(), 0, None, 3, 3);
-    server_config.with_keep_alive_interval(Duration::from_secs(30));
-    manager.init_server(server_config).unwrap();
+    server_config.with_keep_alive_interval(server_info.keep_alive);
+    // TODO: better error handling
+    let manager = DurianTransport::server(server_config).unwrap_or_else(|e| panic!("Could not start server on {}: {}", server_info.server_addr, e));
 
     info!("[server] Initialized server");
-    commands.insert_resource(ServerPacketManager { manager });
+    let manager = SimulatedTransport::new(Box::new(manager), conditions.clone(), rand::random());
+    let manager = MeteredTransport::new(Box::new(manager), stats.clone());
+    commands.insert_resource(ServerPacketManager { manager: Box::new(manager) });
 }
 
-// TODO: sweep for clients that did not send legit Connect packet and disconnect them
-fn handle_connects(mut manager: ResMut<ServerPacketManager>, mut commands: Commands, asset_server: Res<AssetServer>) {
+/// Check the protocol of each Connect, then start checking its credentials, see [`finish_logins`]
+#[allow(clippy::too_many_arguments)]
+fn handle_connects(
+    mut manager: ResMut<ServerPacketManager>,
+    mut commands: Commands,
+    tick: Res<ServerTick>,
+    mut validator: Validator,
+    mut pending: ResMut<PendingConnections>,
+    accounts: Res<AccountStore>,
+    logins: Query<&PendingLogin>,
+    time: Res<Time>,
+) {
     let connect_packets = manager.received_all::<Connect, ConnectPacketBuilder>(false).unwrap();
-    for (remote_id, leaves) in connect_packets.into_iter() {
-        if matches!(leaves, Some(connects) if !connects.is_empty()) {
-            let addr = manager.get_remote_address(remote_id).unwrap();
-            info!("[server] Client with addr={}, remote_id={} connected", addr, remote_id);
-            player::spawn_player(&mut commands, addr, remote_id, &asset_server);
-            info!("Sending ConnectAck to client {}", remote_id);
-            manager.send_to(remote_id, ConnectAck { id: remote_id }).unwrap();
-            // TODO: refactor this out of here
-            info!("[server] Sending SpawnScene command to client {}", remote_id);
-            manager
-                .send_to(
-                    remote_id,
-                    SpawnScene {
-                        level: LevelInfo {
-                            handle_id: "models/small/big.glb#Scene0".to_string(),
-                            scene_transform: [0.0, 0.0, 0.0, std::f32::consts::PI / 2.0],
-                            scale: 1.0,
-                        },
-                    },
-                )
-                .unwrap();
+    for (remote_id, connects) in connect_packets.into_iter() {
+        // Every open connection is listed, whether it sent anything or not
+        pending.observe(remote_id, time.elapsed_seconds_f64());
+        if let Some(connect) = connects.and_then(|connects| validator.filter(remote_id, connects).pop()) {
+            if pending.accepted.contains(&remote_id) || logins.iter().any(|login| login.remote_id == remote_id) {
+                debug!("[server] Ignoring Connect from client {}, which is already logged in or logging in", remote_id);
+                continue;
+            }
+            let reason = if connect.protocol_version != PROTOCOL_VERSION {
+                RejectReason::ProtocolMismatch { server_version: PROTOCOL_VERSION, client_version: connect.protocol_version }
+            } else if connect.schema_fingerprint != SCHEMA_FINGERPRINT {
+                RejectReason::SchemaMismatch { server_fingerprint: SCHEMA_FINGERPRINT, client_fingerprint: connect.schema_fingerprint }
+            } else {
+                let task = accounts.start_login(connect.credentials.clone());
+                commands.spawn(PendingLogin { remote_id, connect, task });
+                continue;
+            };
+            reject(&mut manager, &tick, remote_id, &connect, reason);
+        }
+    }
+}
+
+/// Client disconnects once it receives the rejection
+fn reject(manager: &mut ServerPacketManager, tick: &ServerTick, remote_id: u32, connect: &Connect, reason: RejectReason) {
+    warn!(
+        "[server] Rejecting client with addr={:?}, remote_id={}, build={}, username={}: {}",
+        manager.get_remote_address(remote_id),
+        remote_id,
+        connect.build_hash,
+        connect.credentials.username,
+        reason
+    );
+    if let Err(e) = manager.send_to(remote_id, ConnectReject { tick: tick.0, reason }) {
+        debug!("[server] Could not send ConnectReject to client {}.  Error: {:?}", remote_id, e);
+    }
+}
+
+/// Log in clients whose credentials were checked, spawning their player or resuming its session
+#[allow(clippy::too_many_arguments)]
+fn finish_logins(
+    mut manager: ResMut<ServerPacketManager>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    tick: Res<ServerTick>,
+    tick_config: Res<TickConfig>,
+    level: Res<LevelInfo>,
+    mut validator: Validator,
+    mut pending: ResMut<PendingConnections>,
+    mut accounts: ResMut<AccountStore>,
+    characters: Res<CharacterStore>,
+    session_settings: Res<SessionSettings>,
+    movement_settings: Res<MovementSettings>,
+    spawn_rules: Res<SpawnRules>,
+    mut logins: Query<(Entity, &mut PendingLogin)>,
+    mut players: Query<(Entity, &mut ServerPlayer, &PlayerData, &mut InterestSet, &mut SnapshotHistory)>,
+) {
+    for (login_entity, mut pending_login) in logins.iter_mut() {
+        let Some(check) = future::block_on(future::poll_once(&mut pending_login.task)) else {
+            continue;
+        };
+        commands.entity(login_entity).despawn();
+        let remote_id = pending_login.remote_id;
+        let connect = &pending_login.connect;
+        // Gave up on the handshake while we were hashing
+        let Some(addr) = manager.get_remote_address(remote_id) else {
+            continue;
+        };
+        if matches!(check, LoginCheck::Failed(_)) {
+            validator.record(remote_id, Connect::NAME, Violation::FailedLogin);
+        }
+        // Only the client holding the session token may take over the account's player.  Resuming a session takes the
+        // slot it already holds.
+        let login = accounts.finish_login(check).and_then(|account_id| {
+            match players.iter().find(|(_, _, player_data, ..)| player_data.id == account_id) {
+                Some((_, server_player, ..)) if connect.session_token != Some(server_player.session_token) => Err(RejectReason::AlreadyLoggedIn),
+                Some(_) => Ok(account_id),
+                None if players.iter().count() >= session_settings.max_players => {
+                    Err(RejectReason::ServerFull { max_players: session_settings.max_players })
+                }
+                None => Ok(account_id),
+            }
+        });
+        let account_id = match login {
+            Ok(account_id) => account_id,
+            Err(reason) => {
+                reject(&mut manager, &tick, remote_id, connect, reason);
+                continue;
+            }
+        };
+        if connect.build_hash != BUILD_HASH {
+            warn!("[server] Client remote_id={} is on build {} but server is on build {}", remote_id, connect.build_hash, BUILD_HASH);
+        }
+        pending.accept(remote_id);
+
+        // Players are identified by their account, so an account that already has a player resumes its session
+        let session_token = match players.iter_mut().find(|(_, _, player_data, ..)| player_data.id == account_id) {
+            Some((entity, mut server_player, player_data, mut interest_set, mut snapshot_history)) => {
+                info!(
+                    "[server] Client with addr={}, remote_id={} resumed player {} from remote_id={}",
+                    addr, remote_id, player_data.id, server_player.remote_id
+                );
+                // Either we haven't noticed the old connection is gone yet, or the account logged in from elsewhere
+                if manager.get_remote_address(server_player.remote_id).is_some() {
+                    if let Err(e) = manager.close_connection(server_player.remote_id) {
+                        error!("[server] Could not close stale connection with remote_id={}.  Error: {}", server_player.remote_id, e);
+                    }
+                }
+                server_player.addr = addr;
+                server_player.remote_id = remote_id;
+                // Client starts over with an empty world, so resend everything
+                interest_set.visible.clear();
+                interest_set.left_reasons.clear();
+                snapshot_history.clear();
+                commands.entity(entity).remove::<Disconnected>();
+                server_player.session_token
+            }
+            None => {
+                info!("[server] Client with addr={}, remote_id={} logged in to account {}", addr, remote_id, account_id);
+                let character = persistence::load_character(&characters, account_id);
+                let session_token = rand::random();
+                let server_player = ServerPlayer { addr, remote_id, session_token };
+                player::spawn_player(&mut commands, server_player, account_id, character, &spawn_rules, &level, &asset_server);
+                session_token
+            }
+        };
+        info!("Sending ConnectAck to client {}", remote_id);
+        let connect_ack =
+            ConnectAck { tick: tick.0, id: account_id, tick_rate: tick_config.tick_rate, movement: *movement_settings, session_token };
+        // The connection can close while the login is finishing, in which case the player waits out its grace period
+        if let Err(e) = manager.send_to(remote_id, connect_ack) {
+            debug!("[server] Could not send ConnectAck to client {}.  Error: {}", remote_id, e);
+            continue;
+        }
+        // TODO: refactor this out of here
+        info!("[server] Sending SpawnScene command to client {}", remote_id);
+        if let Err(e) = manager.send_to(remote_id, SpawnScene { tick: tick.0, level: level.clone() }) {
+            debug!("[server] Could not send SpawnScene to client {}.  Error: {}", remote_id, e);
         }
     }
 }
 
-fn handle_leaves(mut manager: ResMut<ServerPacketManager>, mut commands: Commands, players_query: Query<(Entity, &ServerPlayer, &PlayerData)>) {
+/// Remove players that disconnected, were kicked, or lost connection and did not resume their session in time, and tell
+/// the clients that could see them why they left
+#[allow(clippy::too_many_arguments)]
+fn handle_leaves(
+    mut manager: ResMut<ServerPacketManager>,
+    mut commands: Commands,
+    tick: Res<ServerTick>,
+    mut metrics: ResMut<ServerMetrics>,
+    session_settings: Res<SessionSettings>,
+    time: Res<Time>,
+    mut kicks: EventReader<KickClient>,
+    characters: Res<CharacterStore>,
+    level: Res<LevelInfo>,
+    mut players_query: Query<(Entity, &ServerPlayer, &PlayerData, &Transform, &CharacterStats, &mut InterestSet, Option<&Disconnected>)>,
+) {
     let leave_packets = manager.received_all::<Disconnect, DisconnectPacketBuilder>(false).unwrap();
-    let mut players_to_remove = HashSet::new();
+    // By remote id
+    let mut players_to_remove = HashMap::new();
+
+    for kick in kicks.iter() {
+        metrics.kicks += 1;
+        let addr = manager.get_remote_address(kick.remote_id);
+        warn!(
+            "[server] Kicking client {} with addr={:?}: {}.  {} kicks and {} violations so far",
+            kick.remote_id, addr, kick.reason, metrics.kicks, metrics.violations
+        );
+        // Let the client know it was kicked rather than have it wait for a timeout
+        let player = players_query.iter().find(|(_, server_player, ..)| server_player.remote_id == kick.remote_id);
+        if let Some((_, _, player_data, ..)) = player {
+            let players = vec![PlayerLeft { id: player_data.id, reason: LeaveReason::Kicked }];
+            if let Err(e) = manager.send_to(kick.remote_id, PlayersLeft { tick: tick.0, players }) {
+                debug!("[server] Could not notify client {} of kick.  Error: {:?}", kick.remote_id, e);
+            }
+        }
+        if let Err(e) = manager.close_connection(kick.remote_id) {
+            error!("[server] Could not close connection with remote_id={}, addr={:?}.  Error: {}", kick.remote_id, addr, e);
+        }
+        players_to_remove.insert(kick.remote_id, LeaveReason::Kicked);
+    }
 
     for (remote_id, leaves) in leave_packets {
         if let Some(leaves) = leaves {
@@ -95,17 +288,112 @@ fn handle_leaves(mut manager: ResMut<ServerPacketManager>, mut commands: Command
                 if let Err(e) = manager.close_connection(remote_id) {
                     error!("[server] Could not close connection with remote_id={}, addr={:?}.  Error: {}", remote_id, addr, e);
                 }
-                players_to_remove.insert(remote_id);
+                players_to_remove.insert(remote_id, LeaveReason::Disconnected);
             }
         }
     }
 
     // Remove disconnected players
-    for (entity, player, player_data) in &players_query {
-        // also handle clients that did not gracefully disconnect
-        if players_to_remove.contains(&player_data.id) || manager.get_remote_address(player_data.id).is_none() {
-            info!("[server] Removing player with remote_id={}, addr={}", player_data.id, player.addr);
-            commands.entity(entity).despawn_recursive();
+    let now = time.elapsed_seconds_f64();
+    let mut left = Vec::new();
+    for (entity, player, player_data, transform, stats, _, disconnected) in players_query.iter() {
+        let reason = match (players_to_remove.get(&player.remote_id), disconnected) {
+            (Some(reason), _) => *reason,
+            (None, Some(disconnected)) if now - disconnected.since > session_settings.grace_period => LeaveReason::TimedOut,
+            (None, Some(_)) => continue,
+            // also handle clients that did not gracefully disconnect, giving them a chance to resume their session
+            (None, None) if manager.get_remote_address(player.remote_id).is_none() => {
+                info!("[server] Lost connection to player {} with remote_id={}, addr={}", player_data.id, player.remote_id, player.addr);
+                commands.entity(entity).insert(Disconnected { since: now });
+                continue;
+            }
+            (None, None) => continue,
+        };
+        info!("[server] Removing player {} with remote_id={}, addr={}: {}", player_data.id, player.remote_id, player.addr, reason);
+        persistence::save_character(&characters, player_data, transform, stats, &level);
+        commands.entity(entity).despawn_recursive();
+        left.push(PlayerLeft { id: player_data.id, reason });
+    }
+    if left.is_empty() {
+        return;
+    }
+
+    // Sent with the players' removal in the next snapshots of clients that could see them
+    for (.., mut interest_set, _) in players_query.iter_mut() {
+        for player_left in left.iter().filter(|player_left| interest_set.visible.contains(&player_left.id)) {
+            interest_set.left_reasons.insert(player_left.id, player_left.reason);
+        }
+    }
+}
+
+/// Close connections that did not complete the handshake in time, e.g. ones that never sent a valid Connect or ignored
+/// a ConnectReject
+fn evict_pending_connections(
+    mut manager: ResMut<ServerPacketManager>,
+    mut pending: ResMut<PendingConnections>,
+    mut metrics: ResMut<ServerMetrics>,
+    time: Res<Time>,
+) {
+    let closed: Vec<u32> = pending
+        .pending
+        .keys()
+        .chain(pending.accepted.iter())
+        .filter(|remote_id| manager.get_remote_address(**remote_id).is_none())
+        .copied()
+        .collect();
+    for remote_id in closed {
+        pending.forget(remote_id);
+    }
+
+    for remote_id in pending.expired(time.elapsed_seconds_f64()) {
+        metrics.evictions += 1;
+        let addr = manager.get_remote_address(remote_id);
+        warn!(
+            "[server] Evicting client {} with addr={:?} for not completing handshake within {}s.  {} evictions so far",
+            remote_id, addr, pending.handshake_timeout, metrics.evictions
+        );
+        if let Err(e) = manager.close_connection(remote_id) {
+            error!("[server] Could not close connection with remote_id={}, addr={:?}.  Error: {}", remote_id, addr, e);
+        }
+    }
+}
+
+/// Ping every connected player, telling it the round trip time last measured
+fn send_pings(
+    mut manager: ResMut<ServerPacketManager>,
+    mut pings: ResMut<Pings>,
+    stats: Res<NetworkStats>,
+    tick: Res<ServerTick>,
+    players: Query<&ServerPlayer, Without<Disconnected>>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_seconds_f64();
+    if now - pings.last_sent < pings.interval {
+        return;
+    }
+    pings.last_sent = now;
+    pings.outstanding.retain(|remote_id, _| manager.get_remote_address(*remote_id).is_some());
+    for player in players.iter() {
+        let id = pings.next_id;
+        pings.next_id = pings.next_id.wrapping_add(1);
+        let rtt_ms = stats.connection(player.remote_id).and_then(|connection| connection.rtt).map(|rtt| rtt.as_secs_f32() * 1000.0);
+        if let Err(e) = manager.send_to(player.remote_id, Ping { tick: tick.0, id, rtt_ms }) {
+            debug!("[server] Could not ping client {}.  Error: {}", player.remote_id, e);
+            continue;
+        }
+        pings.outstanding.insert(player.remote_id, (id, Instant::now()));
+    }
+}
+
+/// Measure round trip times from answers to the latest pings
+fn receive_pongs(mut manager: ResMut<ServerPacketManager>, mut validator: Validator, mut pings: ResMut<Pings>, stats: Res<NetworkStats>) {
+    let pong_packets = manager.received_all::<Pong, PongPacketBuilder>(false).unwrap();
+    for (remote_id, pongs) in pong_packets {
+        for pong in pongs.map(|pongs| validator.filter(remote_id, pongs)).unwrap_or_default() {
+            if pings.outstanding.get(&remote_id).is_some_and(|(id, _)| *id == pong.id) {
+                let (_, sent_at) = pings.outstanding.remove(&remote_id).unwrap();
+                stats.record_rtt(remote_id, sent_at.elapsed());
+            }
         }
     }
 }
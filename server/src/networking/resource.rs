@@ -1,19 +1,101 @@
 use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
 use bevy::prelude::Resource;
-use durian::PacketManager;
+use bevy::utils::{HashMap, HashSet};
+use mangovillage_common::networking::transport::Transport;
 
 #[derive(Resource)]
 pub struct ServerInfo {
-    pub server_addr: String
+    pub server_addr: String,
+    pub keep_alive: Duration,
+}
+
+#[derive(Resource)]
+pub struct SessionSettings {
+    /// Seconds a player whose connection was lost is kept for its client to resume the session
+    pub grace_period: f64,
+    pub max_players: usize,
+}
+
+/// Counters for monitoring misbehaving clients
+#[derive(Resource, Default)]
+pub struct ServerMetrics {
+    /// Packets dropped by validation, and failed logins
+    pub violations: u64,
+    pub kicks: u64,
+    /// Connections closed for not completing the handshake in time
+    pub evictions: u64,
+}
+
+/// Tracks connections that have not completed the Connect/ConnectAck handshake, so ones that never do can be closed
+/// instead of holding a slot forever
+#[derive(Resource)]
+pub struct PendingConnections {
+    /// Seconds a connection has to send a valid Connect
+    pub handshake_timeout: f64,
+    /// Remote ids by the time they were first seen
+    pub pending: HashMap<u32, f64>,
+    /// Remote ids that completed the handshake
+    pub accepted: HashSet<u32>,
+}
+
+impl PendingConnections {
+    pub fn new(handshake_timeout: f64) -> Self {
+        PendingConnections { handshake_timeout, pending: HashMap::default(), accepted: HashSet::default() }
+    }
+
+    /// Start the handshake deadline of a remote id, if it hasn't been started or completed already
+    pub fn observe(&mut self, remote_id: u32, now: f64) {
+        if !self.accepted.contains(&remote_id) {
+            self.pending.entry(remote_id).or_insert(now);
+        }
+    }
+
+    pub fn accept(&mut self, remote_id: u32) {
+        self.pending.remove(&remote_id);
+        self.accepted.insert(remote_id);
+    }
+
+    pub fn forget(&mut self, remote_id: u32) {
+        self.pending.remove(&remote_id);
+        self.accepted.remove(&remote_id);
+    }
+
+    /// Remote ids past their handshake deadline.  These are no longer tracked.
+    pub fn expired(&mut self, now: f64) -> Vec<u32> {
+        let expired: Vec<u32> = self.pending.iter().filter(|(_, seen)| now - **seen > self.handshake_timeout).map(|(id, _)| *id).collect();
+        for remote_id in expired.iter() {
+            self.pending.remove(remote_id);
+        }
+        expired
+    }
+}
+
+/// Pings sent to clients to measure round trip times
+#[derive(Resource)]
+pub struct Pings {
+    /// Seconds between pings to each client
+    pub interval: f64,
+    pub last_sent: f64,
+    pub next_id: u32,
+    /// Id of the latest ping sent to each remote id and when it was sent.  Answers to older pings are ignored.
+    pub outstanding: HashMap<u32, (u32, Instant)>,
+}
+
+impl Pings {
+    pub fn new(interval: f64) -> Self {
+        // Ping as soon as there is someone to ping
+        Pings { interval, last_sent: f64::NEG_INFINITY, next_id: 0, outstanding: HashMap::default() }
+    }
 }
 
 #[derive(Resource)]
 pub struct ServerPacketManager {
-    pub manager: PacketManager
+    pub manager: Box<dyn Transport>,
 }
 
 impl Deref for ServerPacketManager {
-    type Target = PacketManager;
+    type Target = dyn Transport;
 
     fn deref(&self) -> &Self::Target {
         &self.manager
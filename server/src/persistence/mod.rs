@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+
+use mangovillage_common::player::component::PlayerData;
+use mangovillage_common::resource::LevelInfo;
+
+use crate::persistence::resource::{AutosaveTimer, CharacterRecord, CharacterStore};
+use crate::player::component::{CharacterStats, ServerPlayer};
+use crate::state::ServerState;
+
+pub mod resource;
+
+/// Saves characters to disk when they leave, periodically, and when the server shuts down, and restores them on login
+pub struct PersistencePlugin {
+    /// Directory characters are saved in
+    pub dir: PathBuf,
+    pub autosave_interval: Duration,
+}
+
+impl Plugin for PersistencePlugin {
+    fn build(&self, app: &mut App) {
+        let store = CharacterStore::new(self.dir.clone()).unwrap_or_else(|e| panic!("Could not open character store {:?}: {}", self.dir, e));
+        app.insert_resource(store)
+            .insert_resource(AutosaveTimer(Timer::new(self.autosave_interval, TimerMode::Repeating)))
+            .add_systems(Update, (autosave, save_on_exit).run_if(in_state(ServerState::Running)));
+    }
+}
+
+/// Save a character, logging instead of failing since the player is usually on their way out
+pub fn save_character(store: &CharacterStore, player_data: &PlayerData, transform: &Transform, stats: &CharacterStats, level: &LevelInfo) {
+    let character = CharacterRecord::new(player_data, transform, stats, &level.handle_id);
+    if let Err(e) = store.save(&character) {
+        error!("[server] Could not save character {}: {}", player_data.id, e);
+    }
+}
+
+/// Saved character for a player to restore, if it was saved before
+pub fn load_character(store: &CharacterStore, id: u32) -> Option<CharacterRecord> {
+    match store.load(id) {
+        Ok(character) => character,
+        Err(e) => {
+            error!("[server] Could not load character {}: {}", id, e);
+            None
+        }
+    }
+}
+
+// TODO: move file writes off the main thread if autosaves get slow with many players
+fn autosave(
+    mut timer: ResMut<AutosaveTimer>,
+    time: Res<Time>,
+    store: Res<CharacterStore>,
+    level: Res<LevelInfo>,
+    players: Query<(&PlayerData, &Transform, &CharacterStats), With<ServerPlayer>>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    for (player_data, transform, stats) in players.iter() {
+        save_character(&store, player_data, transform, stats, &level);
+    }
+    debug!("[server] Autosaved {} characters", players.iter().count());
+}
+
+/// Save everyone in the frame the app exits, e.g. from [`ShutdownPlugin`](crate::shutdown::ShutdownPlugin) on a shutdown signal
+fn save_on_exit(
+    exit: EventReader<AppExit>,
+    store: Res<CharacterStore>,
+    level: Res<LevelInfo>,
+    players: Query<(&PlayerData, &Transform, &CharacterStats), With<ServerPlayer>>,
+) {
+    if exit.is_empty() {
+        return;
+    }
+    info!("[server] Saving {} characters before exiting", players.iter().count());
+    for (player_data, transform, stats) in players.iter() {
+        save_character(&store, player_data, transform, stats, &level);
+    }
+}
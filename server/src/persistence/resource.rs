@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use bevy::prelude::{Quat, Resource, Timer, Transform, Vec3};
+use serde::{Deserialize, Serialize};
+
+use mangovillage_common::player::component::PlayerData;
+
+use crate::player::component::CharacterStats;
+
+/// Everything about a character that outlives its session
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CharacterRecord {
+    /// Player id, see [`PlayerData::id`]
+    pub id: u32,
+    /// Handle id of the level the character was in
+    pub level: String,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub handle_id: u8,
+    /// Free-form stats, so new ones don't need a new file layout
+    #[serde(default)]
+    pub stats: BTreeMap<String, f32>,
+}
+
+impl CharacterRecord {
+    pub fn new(player_data: &PlayerData, transform: &Transform, stats: &CharacterStats, level: &str) -> Self {
+        CharacterRecord {
+            id: player_data.id,
+            level: level.to_string(),
+            translation: transform.translation.to_array(),
+            rotation: transform.rotation.to_array(),
+            handle_id: player_data.handle_id,
+            stats: stats.0.clone(),
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(Vec3::from_array(self.translation)).with_rotation(Quat::from_array(self.rotation).normalize())
+    }
+}
+
+#[derive(Debug)]
+pub enum PersistenceError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+}
+
+impl Display for PersistenceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PersistenceError::Io(e) => write!(f, "{}", e),
+            PersistenceError::Parse(e) => write!(f, "invalid character file: {}", e),
+            PersistenceError::Serialize(e) => write!(f, "could not serialize character: {}", e),
+        }
+    }
+}
+
+/// Characters saved as one TOML file each in a directory on the server's disk
+#[derive(Resource)]
+pub struct CharacterStore {
+    dir: PathBuf,
+}
+
+impl CharacterStore {
+    pub fn new(dir: PathBuf) -> Result<Self, PersistenceError> {
+        fs::create_dir_all(&dir).map_err(PersistenceError::Io)?;
+        Ok(CharacterStore { dir })
+    }
+
+    /// Saved character for a player, if it has been saved before
+    pub fn load(&self, id: u32) -> Result<Option<CharacterRecord>, PersistenceError> {
+        match fs::read_to_string(self.path(id)) {
+            Ok(contents) => toml::from_str(&contents).map(Some).map_err(PersistenceError::Parse),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(PersistenceError::Io(e)),
+        }
+    }
+
+    /// Write to a temporary file and move it into place, so a crash can't leave a partially written character
+    pub fn save(&self, character: &CharacterRecord) -> Result<(), PersistenceError> {
+        let contents = toml::to_string(character).map_err(PersistenceError::Serialize)?;
+        let path = self.path(character.id);
+        let temp_path = path.with_extension("toml.tmp");
+        fs::write(&temp_path, contents).map_err(PersistenceError::Io)?;
+        fs::rename(&temp_path, &path).map_err(PersistenceError::Io)
+    }
+
+    fn path(&self, id: u32) -> PathBuf {
+        self.dir.join(format!("{}.toml", id))
+    }
+}
+
+/// Saves every character when it finishes
+#[derive(Resource)]
+pub struct AutosaveTimer(pub Timer);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn characters_round_trip_through_the_store() {
+        let dir = std::env::temp_dir().join(format!("mangovillage-characters-{}", std::process::id()));
+        let store = CharacterStore::new(dir.clone()).unwrap();
+        assert!(store.load(3).unwrap().is_none());
+
+        let transform = Transform::from_xyz(1.0, -2.0, 3.5).with_rotation(Quat::from_rotation_z(0.5));
+        let stats = CharacterStats(BTreeMap::from([("health".to_string(), 75.0), ("coins".to_string(), 12.0)]));
+        let character = CharacterRecord::new(&PlayerData { id: 3, handle_id: 1 }, &transform, &stats, "models/small/big.glb#Scene0");
+        store.save(&character).unwrap();
+        let loaded = store.load(3).unwrap().unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(loaded.id, 3);
+        assert_eq!(loaded.handle_id, 1);
+        assert_eq!(loaded.level, "models/small/big.glb#Scene0");
+        assert_eq!(loaded.transform().translation, transform.translation);
+        // Normalized again when loaded
+        assert!(loaded.transform().rotation.abs_diff_eq(transform.rotation, 1e-6));
+        assert_eq!(loaded.stats, stats.0);
+    }
+}
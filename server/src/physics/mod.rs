@@ -1,14 +1,35 @@
 use bevy::prelude::*;
-use bevy_rapier3d::prelude::{NoUserData, RapierConfiguration, RapierPhysicsPlugin};
+use bevy_rapier3d::plugin::PhysicsSet;
+use bevy_rapier3d::prelude::{NoUserData, RapierConfiguration, RapierPhysicsPlugin, TimestepMode};
 use mangovillage_common::physics;
 
 use crate::state::ServerState;
+use crate::tick::resource::TickConfig;
+
+/// Steps physics once per simulation tick, so must be added after [`TickPlugin`](crate::tick::TickPlugin)
+pub struct PhysicsPlugin {
+    /// Acceleration along z
+    pub gravity: f32,
+}
 
-pub struct PhysicsPlugin;
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
-            .insert_resource(RapierConfiguration { gravity: Vec3::new(0.0, 0.0, -100.0), ..default() })
+        let tick_rate = app.world.resource::<TickConfig>().tick_rate;
+        // Run rapier in the fixed tick instead of every frame.  Sets are ordered by the TickPlugin.
+        app.add_plugins(RapierPhysicsPlugin::<NoUserData>::default().with_default_system_setup(false))
+            .insert_resource(RapierConfiguration {
+                gravity: Vec3::new(0.0, 0.0, self.gravity),
+                timestep_mode: TimestepMode::Fixed { dt: 1.0 / tick_rate as f32, substeps: 1 },
+                ..default()
+            })
+            .add_systems(
+                FixedUpdate,
+                (
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::SyncBackend).in_set(PhysicsSet::SyncBackend),
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::StepSimulation).in_set(PhysicsSet::StepSimulation),
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsSet::Writeback).in_set(PhysicsSet::Writeback),
+                ),
+            )
             .add_systems(Update, load_colliders.run_if(in_state(ServerState::LoadPhysics)));
     }
 }
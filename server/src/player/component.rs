@@ -1,16 +1,92 @@
+use std::collections::{BTreeMap, VecDeque};
+
 use bevy::prelude::{Bundle, Component};
+use bevy::utils::HashMap;
 
+use mangovillage_common::networking::server_packets::Player;
 use mangovillage_common::physics::component::ColliderBundle;
 use mangovillage_common::player::component::PlayerData;
 
+use crate::interest::component::InterestSet;
+
 #[derive(Bundle)]
 pub struct ServerPlayerBundle {
     pub server_player: ServerPlayer,
     pub player_data: PlayerData,
+    pub last_processed_input: LastProcessedInput,
+    pub interest_set: InterestSet,
+    pub snapshot_history: SnapshotHistory,
+    pub stats: CharacterStats,
     pub colliders: ColliderBundle,
 }
 
 #[derive(Component)]
 pub struct ServerPlayer {
     pub addr: String,
+    /// Connection the player is controlled through.  Changes when the client resumes its session from a new connection,
+    /// so never sent to clients, which know players by [`PlayerData::id`] instead.
+    pub remote_id: u32,
+    /// Lets the client reclaim this player after losing its connection, on top of logging in to the account
+    pub session_token: u64,
+}
+
+/// Free-form stats of a player's character, restored from and saved to its
+/// [`CharacterRecord`](crate::persistence::resource::CharacterRecord)
+#[derive(Component, Default, Clone)]
+pub struct CharacterStats(pub BTreeMap<String, f32>);
+
+/// Player whose connection was lost without a Disconnect.  Kept around for a grace window so the client can log in again
+/// with its session token and resume its session.
+#[derive(Component)]
+pub struct Disconnected {
+    /// Local seconds the connection was lost at
+    pub since: f64,
+}
+
+/// Sequence number of the last [`Movement`](mangovillage_common::networking::client_packets::Movement) input processed
+/// for a player
+#[derive(Component)]
+pub struct LastProcessedInput(pub u32);
+
+/// Max snapshots kept per client waiting to be acknowledged.  If a client falls further behind, it gets a full snapshot.
+const MAX_SNAPSHOT_HISTORY: usize = 64;
+
+/// Snapshots sent to a client, so later snapshots can be diffed against the latest one it acknowledged
+#[derive(Component, Default)]
+pub struct SnapshotHistory {
+    /// Ordered by tick, oldest first
+    sent: VecDeque<(u32, HashMap<u32, Player>)>,
+    acked: Option<u32>,
+}
+
+impl SnapshotHistory {
+    pub fn push(&mut self, tick: u32, players: HashMap<u32, Player>) {
+        if self.sent.len() >= MAX_SNAPSHOT_HISTORY {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((tick, players));
+    }
+
+    /// Forget everything sent, so the next snapshot is a full one
+    pub fn clear(&mut self) {
+        self.sent.clear();
+        self.acked = None;
+    }
+
+    pub fn acknowledge(&mut self, tick: u32) {
+        if matches!(self.acked, Some(acked) if acked >= tick) {
+            return;
+        }
+        self.acked = Some(tick);
+        // Never diff against anything older than the latest ack
+        while matches!(self.sent.front(), Some((sent_tick, _)) if *sent_tick < tick) {
+            self.sent.pop_front();
+        }
+    }
+
+    /// Latest acknowledged snapshot we still have, to diff against
+    pub fn baseline(&self) -> Option<(u32, &HashMap<u32, Player>)> {
+        let acked = self.acked?;
+        self.sent.iter().find(|(tick, _)| *tick == acked).map(|(tick, players)| (*tick, players))
+    }
 }
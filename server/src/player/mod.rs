@@ -1,32 +1,47 @@
 use bevy::math::Vec3Swizzles;
 use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
 use bevy_rapier3d::control::KinematicCharacterControllerOutput;
-use bevy_rapier3d::prelude::{
-    CharacterLength, Collider, KinematicCharacterController, LockedAxes, QueryFilter, QueryFilterFlags, RapierContext, RigidBody, TOIStatus,
-};
+use bevy_rapier3d::prelude::{Collider, KinematicCharacterController, RapierContext};
 
 use mangovillage_common::component::MoveTarget;
-use mangovillage_common::networking::client_packets::{Movement, MovementPacketBuilder};
-use mangovillage_common::networking::server_packets::{Player, Players};
-use mangovillage_common::physics::component::ColliderBundle;
+use mangovillage_common::networking::client_packets::{Movement, MovementPacketBuilder, SnapshotAck, SnapshotAckPacketBuilder};
+use mangovillage_common::networking::compact::CompactTransform;
+use mangovillage_common::networking::server_packets::{LeaveReason, Player, PlayerLeft, Players};
 use mangovillage_common::player;
 use mangovillage_common::player::component::PlayerData;
-use mangovillage_common::player::set_player_rotation;
-use player::get_player_collider;
+use mangovillage_common::player::movement;
+use mangovillage_common::player::movement::MovementSettings;
+use mangovillage_common::resource::LevelInfo;
+use player::get_player_collider_bundle;
 
+use crate::interest::component::InterestSet;
+use crate::interest::resource::{InterestSettings, SpatialGrid};
 use crate::networking::resource::ServerPacketManager;
-use crate::player::component::{ServerPlayer, ServerPlayerBundle};
+use crate::persistence::resource::CharacterRecord;
+use crate::player::component::{CharacterStats, Disconnected, LastProcessedInput, ServerPlayer, ServerPlayerBundle, SnapshotHistory};
+use crate::player::resource::SpawnRules;
 use crate::state::ServerState;
+use crate::tick;
+use crate::tick::resource::ServerTick;
+use crate::tick::TickSet;
+use crate::validation::Validator;
 
 pub mod component;
+pub mod resource;
 
-const PLAYER_MOVEMENT_SPEED: f32 = 100.0;
-const GRAVITY_STEP_SPEED: f32 = 100.0;
+pub struct PlayerPlugin {
+    pub movement: MovementSettings,
+    pub spawn: SpawnRules,
+}
 
-pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (broadcast_players, players_move, movement, player_collision).run_if(in_state(ServerState::Running)));
+        app.insert_resource(self.movement)
+            .insert_resource(self.spawn)
+            .add_systems(FixedUpdate, (players_move, snapshot_acks).in_set(TickSet::Input).run_if(in_state(ServerState::Running)))
+            .add_systems(FixedUpdate, (movement, player_collision).in_set(TickSet::Simulate).run_if(in_state(ServerState::Running)))
+            .add_systems(FixedUpdate, broadcast_players.in_set(TickSet::Send).run_if(in_state(ServerState::Running).and_then(tick::is_send_tick)));
         // Run collision handling in substep schedule
         //.add_systems(SubstepSchedule, player_collision.run_if(in_state(ServerState::Running)).in_set(SubstepSet::SolveUserConstraints));
     }
@@ -35,44 +50,33 @@ impl Plugin for PlayerPlugin {
 fn movement(
     mut commands: Commands,
     mut players: Query<(Entity, &mut Transform, &mut MoveTarget, &mut KinematicCharacterController), With<ServerPlayer>>,
-    time: Res<Time>,
+    movement_settings: Res<MovementSettings>,
+    fixed_time: Res<FixedTime>,
 ) {
     for (entity, mut transform, mut move_target, mut controller) in players.iter_mut() {
-        let movement_vec = Vec2::new(move_target.target.0, move_target.target.1).normalize();
-        let dx = movement_vec.x * PLAYER_MOVEMENT_SPEED * time.delta_seconds();
-        let dy = movement_vec.y * PLAYER_MOVEMENT_SPEED * time.delta_seconds();
-        match controller.translation {
-            None => controller.translation = Some(Vec3::new(dx, dy, 0.0)),
-            Some(ref mut translation) => {
-                translation.x += dx;
-                translation.y += dy;
-            }
-        };
-        let translation = controller.translation.unwrap();
-        //println!("trans: {:?}", transform.translation);
-        set_player_rotation(translation.xy(), &mut transform);
-        move_target.target.0 -= dx;
-        move_target.target.1 -= dy;
-        if move_target.target.0.abs() < 0.05 && move_target.target.1.abs() < 0.05 {
+        if movement::apply_movement(&mut transform, &mut move_target, &mut controller, &movement_settings, fixed_time.period.as_secs_f32()) {
             commands.entity(entity).remove::<MoveTarget>();
         }
-        //println!("move: {:?}", controller.translation);
     }
 }
 
-fn players_move(mut manager: ResMut<ServerPacketManager>, mut commands: Commands, mut players: Query<(Entity, &PlayerData)>) {
+fn players_move(
+    mut manager: ResMut<ServerPacketManager>,
+    mut commands: Commands,
+    mut players: Query<(Entity, &ServerPlayer, &mut LastProcessedInput)>,
+    mut validator: Validator,
+) {
     let move_packets = manager.received_all::<Movement, MovementPacketBuilder>(false).unwrap();
     for (remote_id, move_packets) in move_packets {
-        if let Some(move_packet) = move_packets {
-            let movement = move_packet.last().unwrap();
+        if let Some(movement) = move_packets.and_then(|move_packets| validator.filter(remote_id, move_packets).pop()) {
             // Find player
             let mut found = false;
-            for (entity, player_data) in players.iter_mut() {
-                if player_data.id == remote_id {
+            for (entity, server_player, mut last_processed_input) in players.iter_mut() {
+                if server_player.remote_id == remote_id {
                     found = true;
-                    // TODO: path to spot in world?
-                    let movement_vec = Vec2::new(movement.translation[0], movement.translation[1]).normalize() * 0.5;
-                    commands.entity(entity).insert(MoveTarget { target: (movement_vec.x, movement_vec.y) });
+                    commands.entity(entity).insert(movement::move_target_from_input(movement.translation));
+                    // Echoed back to the client so it can replay inputs we haven't processed yet
+                    last_processed_input.0 = movement.sequence;
                     break;
                 }
             }
@@ -83,137 +87,148 @@ fn players_move(mut manager: ResMut<ServerPacketManager>, mut commands: Commands
     }
 }
 
+/// Track the latest snapshot each client has acknowledged
+fn snapshot_acks(mut manager: ResMut<ServerPacketManager>, mut players: Query<(&ServerPlayer, &mut SnapshotHistory)>, mut validator: Validator) {
+    let ack_packets = manager.received_all::<SnapshotAck, SnapshotAckPacketBuilder>(false).unwrap();
+    for (remote_id, acks) in ack_packets {
+        if let Some(ack) = acks.and_then(|acks| validator.filter(remote_id, acks).into_iter().map(|ack| ack.tick).max()) {
+            if let Some((_, mut snapshot_history)) = players.iter_mut().find(|(server_player, _)| server_player.remote_id == remote_id) {
+                snapshot_history.acknowledge(ack);
+            }
+        }
+    }
+}
+
 /// Player collision system.  Keeps the player afloat colliders, but don't apply horizontal forces from collisions.
 ///
 /// Automatically does not run in parallel with the player movement system since they access the same data mutably.
 fn player_collision(
     rapier_context: Res<RapierContext>,
     mut players: Query<
-        (&Transform, &Collider, &mut KinematicCharacterController, Option<&mut KinematicCharacterControllerOutput>),
+        (&Transform, &Collider, &mut KinematicCharacterController, Option<&KinematicCharacterControllerOutput>),
         With<ServerPlayer>,
     >,
-    time: Res<Time>,
+    movement_settings: Res<MovementSettings>,
+    fixed_time: Res<FixedTime>,
 ) {
     for (transform, collider, mut controller, controller_output) in players.iter_mut() {
-        //println!("output: {:?}", controller_output);
-        // Shape cast to check if we are grounded
-        // We do this manually instead of `output.grounded` so the grounded check is always consistent
-        if rapier_context.cast_shape(transform.translation, transform.rotation, Vec3::NEG_Z, collider, 1.9, QueryFilter::only_fixed()).is_none() {
-            match controller.translation {
-                None => controller.translation = Some(Vec3::NEG_Z * GRAVITY_STEP_SPEED * time.delta_seconds()),
-                Some(mut translation) => translation.z -= GRAVITY_STEP_SPEED * time.delta_seconds(),
-            }
-            //println!("gravity {:?}", controller.translation);
-        } else {
-            // TODO: handle oscillating collisions, such as bouncing up and down between above and below colliders indefinitely
-            if let Some(output) = controller_output {
-                //println!("collisions: {:?}", output);
-                // Keep player above ground
-                for collision in &output.collisions {
-                    //println!("collision: {:?}", collision);
-                    // TODO: handle other statuses
-                    if collision.toi.status == TOIStatus::Converged {
-                        let penetration = collision.translation_remaining.length();
-                        let angle = collision.toi.normal1.angle_between(Vec3::Z);
-                        // Since we only allow player controlled movement to be horizontal, penetration at this point is
-                        // also always horizontal.  We want to find the vertical distance to pop up from the end of the
-                        // penetration, assuming the normal surface is flat, it will pop us out of the current collision.
-                        // let p = penetration length
-                        // let h = vertical distance we want to pop out of
-                        // let theta = angle from normal vector to +Z
-                        // based on triangle symmetry, we can get
-                        //      h = p * tan(theta)
-                        // However, if we are at a low enough angle -> 0, tan function will start to decrease and at 0
-                        // this function will just give us 0 which is incorrect.  If there is penetration when
-                        // theta == 0, we are penetrating vertically, which shouldn't happen, but if it does, just keep
-                        //      h = p
-                        // to pop up the full penetration length.
-                        let mut dz = penetration;
-                        if angle > 10.0 {
-                            dz *= angle.tan()
-                        }
-                        // Add a small offset buffer so player floats slightly above ground
-                        //dz += 0.2;
-                        // divide by number of collisions to average it out
-                        // TODO: optimize and make this properly check toi status
-                        dz /= output.collisions.len() as f32;
-                        // If collided entity is above, push it down
-                        if collision.toi.normal1.z < 0.0 {
-                            dz = -dz;
-                        }
-                        match controller.translation {
-                            None => controller.translation = Some(Vec3::new(0.0, 0.0, dz)),
-                            Some(ref mut translation) => {
-                                if translation.z <= 0.0 {
-                                    translation.z = dz;
-                                } else {
-                                    translation.z += dz
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        //println!("after collision: {:?}", controller.translation);
+        movement::apply_player_collision(
+            &rapier_context,
+            transform,
+            collider,
+            &mut controller,
+            controller_output,
+            &movement_settings,
+            fixed_time.period.as_secs_f32(),
+        );
     }
 }
 
-fn broadcast_players(mut manager: ResMut<ServerPacketManager>, player_query: Query<(&PlayerData, &Transform)>) {
+/// Send each client the players within its area of interest.  Snapshots only carry what changed since the last snapshot
+/// the client acknowledged, so players entering or leaving that area are resent until the client has seen them.
+fn broadcast_players(
+    mut manager: ResMut<ServerPacketManager>,
+    tick: Res<ServerTick>,
+    interest_settings: Res<InterestSettings>,
+    level: Res<LevelInfo>,
+    mut grid: ResMut<SpatialGrid>,
+    player_query: Query<(&PlayerData, &Transform, &LastProcessedInput)>,
+    mut clients: Query<(&ServerPlayer, &PlayerData, &Transform, &mut InterestSet, &mut SnapshotHistory), Without<Disconnected>>,
+) {
     // TODO: optimize
-    // TODO: make Copy instead of Cloned
-    let players = player_query
-        .iter()
-        .map(|(player_data, transform)| Player {
-            id: player_data.id,
-            handle_id: player_data.handle_id,
-            transform: [transform.translation.x, transform.translation.y, transform.translation.z],
-            scale: transform.scale.x,
-        })
-        .collect();
-    manager.broadcast(Players { players }).unwrap();
+    grid.clear();
+    let mut players = HashMap::new();
+    for (player_data, transform, last_processed_input) in player_query.iter() {
+        grid.insert(player_data.id, transform.translation.xy());
+        players.insert(
+            player_data.id,
+            Player {
+                id: player_data.id,
+                handle_id: player_data.handle_id,
+                transform: CompactTransform::from_transform(transform, &level.bounds),
+                last_input: last_processed_input.0,
+            },
+        );
+    }
+
+    for (server_player, client_data, client_transform, mut interest_set, mut snapshot_history) in clients.iter_mut() {
+        let mut visible: HashSet<u32> = grid.query(client_transform.translation.xy(), interest_settings.radius).into_iter().collect();
+        // Clients always see themselves
+        visible.insert(client_data.id);
+
+        let snapshot: HashMap<u32, Player> = visible.iter().filter_map(|id| players.get(id).map(|player| (*id, *player))).collect();
+        let left_reasons = &mut interest_set.left_reasons;
+        let packet = match snapshot_history.baseline() {
+            Some((baseline_tick, baseline)) => Players {
+                tick: tick.0,
+                baseline: Some(baseline_tick),
+                added: snapshot.iter().filter(|(id, _)| !baseline.contains_key(id)).map(|(_, player)| *player).collect(),
+                changed: snapshot
+                    .iter()
+                    .filter_map(|(id, player)| baseline.get(id).and_then(|baseline_player| player.diff(baseline_player)))
+                    .collect(),
+                removed: baseline
+                    .keys()
+                    .filter(|id| !snapshot.contains_key(id))
+                    .map(|id| PlayerLeft { id: *id, reason: left_reasons.get(id).copied().unwrap_or(LeaveReason::OutOfRange) })
+                    .collect(),
+            },
+            None => Players { tick: tick.0, baseline: None, added: snapshot.values().copied().collect(), changed: vec![], removed: vec![] },
+        };
+        // Reasons are only needed until the client acknowledges a snapshot without the player
+        let baseline = snapshot_history.baseline().map(|(_, baseline)| baseline);
+        left_reasons.retain(|id, _| !snapshot.contains_key(id) && baseline.is_some_and(|baseline| baseline.contains_key(id)));
+        // The connection can close before the player is marked disconnected
+        if let Err(e) = manager.send_to(server_player.remote_id, packet) {
+            debug!("[server] Could not send Players to client {}.  Error: {}", server_player.remote_id, e);
+            continue;
+        }
+        snapshot_history.push(tick.0, snapshot);
+        interest_set.visible = visible;
+    }
 }
 
-pub fn spawn_player(commands: &mut Commands, addr: String, id: u32, asset_server: &Res<AssetServer>) {
-    info!("[server] Spawning player with addr={}, id={}", addr, id);
-    let player_data = PlayerData { id, handle_id: 0 };
-    let mut transform = Transform::from_xyz(-10.0, 0.0, 150.0).with_scale(Vec3::splat(1.0));
-    transform.look_to(Vec3::NEG_Y, Vec3::Z);
+/// Spawn a player with its saved character, where it left off if allowed and it was saved in the current level, or at
+/// the spawn point otherwise
+pub fn spawn_player(
+    commands: &mut Commands,
+    server_player: ServerPlayer,
+    id: u32,
+    character: Option<CharacterRecord>,
+    spawn_rules: &SpawnRules,
+    level: &LevelInfo,
+    asset_server: &Res<AssetServer>,
+) {
+    info!("[server] Spawning player with addr={}, remote_id={}, id={}", server_player.addr, server_player.remote_id, id);
+    let mut transform = Transform::from_translation(spawn_rules.point).with_scale(Vec3::splat(1.0));
+    transform.look_to(spawn_rules.facing, Vec3::Z);
+    let (player_data, stats) = match character {
+        Some(character) => {
+            if spawn_rules.restore_saved && character.level == level.handle_id {
+                transform = character.transform();
+            } else if spawn_rules.restore_saved {
+                info!("[server] Character {} was saved in level {}, spawning at the default spawn point", id, character.level);
+            }
+            // Saved model may have been removed since
+            let handle_id = match (character.handle_id as usize) < player::PLAYER_MODEL_HANDLE_IDS.len() {
+                true => character.handle_id,
+                false => spawn_rules.handle_id,
+            };
+            (PlayerData { id, handle_id }, CharacterStats(character.stats))
+        }
+        None => (PlayerData { id, handle_id: spawn_rules.handle_id }, CharacterStats::default()),
+    };
     let mut entity = player::spawn_player(commands, transform, player_data.handle_id, asset_server);
     debug!("Player EntityId={:?}", entity.id());
     entity
         .insert(ServerPlayerBundle {
-            server_player: ServerPlayer { addr },
+            server_player,
             player_data,
-            colliders: ColliderBundle {
-                collider: get_player_collider(),
-                rigid_body: RigidBody::KinematicPositionBased,
-                rotation_constraints: LockedAxes::ROTATION_LOCKED,
-                ..default()
-            },
+            last_processed_input: LastProcessedInput(0),
+            interest_set: InterestSet::default(),
+            snapshot_history: SnapshotHistory::default(),
+            stats,
+            colliders: get_player_collider_bundle(),
         })
-        // Our collision system manually handles a lot of what the character controller gives us, so we have more custom
-        // tuning and control over parameters and behavior.  The defaults don't work so well with dramatic terrains.
-        // This is mainly here so we can collect and act on collisions.
-        .insert(KinematicCharacterController {
-            up: Vec3::Z,
-            // Use a low value because our collision system above already factors in an offset
-            offset: CharacterLength::Absolute(2.0),
-            slide: false,
-            // Our collision system uses normal forces to automatically do autostep on slopes.
-            // We can add this back or modify the collision system to add step heights if needed
-            autostep: None,
-            // autostep: Some(CharacterAutostep {
-            //     max_height: CharacterLength::Absolute(200.0),
-            //     min_width: CharacterLength::Absolute(200.0),
-            //     include_dynamic_bodies: false,
-            // }),
-            max_slope_climb_angle: 85.0,
-            min_slope_slide_angle: 0.0,
-            apply_impulse_to_dynamic_bodies: false,
-            // Our collision system uses gravity to push to ground already
-            snap_to_ground: None, //Some(CharacterLength::Absolute(10000.0)),
-            filter_flags: QueryFilterFlags::ONLY_FIXED,
-            ..default()
-        });
+        .insert(movement::player_character_controller());
 }
@@ -0,0 +1,13 @@
+use bevy::math::Vec3;
+use bevy::prelude::Resource;
+
+/// Where and how characters without a saved position spawn
+#[derive(Resource, Copy, Clone)]
+pub struct SpawnRules {
+    pub point: Vec3,
+    pub facing: Vec3,
+    /// Player model of new characters
+    pub handle_id: u8,
+    /// Spawn returning characters where they were saved instead of at the spawn point
+    pub restore_saved: bool,
+}
@@ -0,0 +1,158 @@
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use mangovillage_common::networking::client_packets::{ReplicationAck, ReplicationAckPacketBuilder};
+use mangovillage_common::networking::compact::CompactTransform;
+use mangovillage_common::networking::server_packets::{EntityState, Replication};
+use mangovillage_common::replication;
+use mangovillage_common::replication::component::{NetworkId, Replicated, ReplicatedTransform};
+use mangovillage_common::replication::ReplicationRegistry;
+use mangovillage_common::resource::LevelInfo;
+
+use crate::interest::resource::InterestSettings;
+use crate::networking::resource::ServerPacketManager;
+use crate::player::component::{Disconnected, ServerPlayer};
+use crate::replication::resource::{EntityStates, NextNetworkId, ReplicationState};
+use crate::state::ServerState;
+use crate::tick;
+use crate::tick::resource::ServerTick;
+use crate::tick::TickSet;
+use crate::validation::Validator;
+
+pub mod resource;
+
+/// Replicates entities marked [`Replicated`] to clients near them
+pub struct ReplicationPlugin;
+impl Plugin for ReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        replication::register_replicated_components(app);
+        app.init_resource::<NextNetworkId>()
+            .init_resource::<ReplicationState>()
+            .add_systems(FixedUpdate, replication_acks.in_set(TickSet::Input).run_if(in_state(ServerState::Running)))
+            .add_systems(
+                FixedUpdate,
+                (assign_network_ids, sync_replicated_transforms, replicate)
+                    .chain()
+                    .in_set(TickSet::Send)
+                    .run_if(in_state(ServerState::Running).and_then(tick::is_send_tick)),
+            );
+    }
+}
+
+fn assign_network_ids(mut commands: Commands, mut next_id: ResMut<NextNetworkId>, entities: Query<Entity, (With<Replicated>, Without<NetworkId>)>) {
+    for entity in entities.iter() {
+        commands.entity(entity).insert(NetworkId(next_id.0));
+        next_id.0 = next_id.0.wrapping_add(1);
+    }
+}
+
+fn sync_replicated_transforms(
+    mut commands: Commands,
+    level: Res<LevelInfo>,
+    entities: Query<(Entity, &Transform), (With<Replicated>, Changed<Transform>)>,
+) {
+    for (entity, transform) in entities.iter() {
+        commands.entity(entity).insert(ReplicatedTransform(CompactTransform::from_transform(transform, &level.bounds)));
+    }
+}
+
+/// Track the latest replication state each client has acknowledged
+fn replication_acks(mut manager: ResMut<ServerPacketManager>, mut state: ResMut<ReplicationState>, mut validator: Validator) {
+    let ack_packets = manager.received_all::<ReplicationAck, ReplicationAckPacketBuilder>(false).unwrap();
+    for (remote_id, acks) in ack_packets {
+        if let Some(ack) = acks.and_then(|acks| validator.filter(remote_id, acks).into_iter().map(|ack| ack.tick).max()) {
+            if let Some(history) = state.clients.get_mut(&remote_id) {
+                history.acknowledge(ack);
+            }
+        }
+    }
+}
+
+/// Sends each client the replicated entities within its area of interest.  Packets only carry what changed since the
+/// last state the client acknowledged, and are resent until it has acknowledged the latest one, so lost packets can't
+/// leave the client out of date.
+fn replicate(world: &mut World) {
+    let tick = world.resource::<ServerTick>().0;
+    let radius = world.resource::<InterestSettings>().radius;
+    let clients: Vec<(u32, Vec2)> = world
+        .query_filtered::<(&ServerPlayer, &Transform), Without<Disconnected>>()
+        .iter(world)
+        .map(|(server_player, transform)| (server_player.remote_id, transform.translation.xy()))
+        .collect();
+
+    // Serialize every replicated component once, then diff per client
+    let mut entities = world.query_filtered::<(Entity, &NetworkId, Option<&Transform>), With<Replicated>>();
+    let registry = world.resource::<ReplicationRegistry>();
+    let states: Vec<(u32, Option<Vec2>, HashMap<u32, Vec<u8>>)> = entities
+        .iter(world)
+        .map(|(entity, network_id, transform)| {
+            let entity = world.entity(entity);
+            let components =
+                registry.components.iter().filter_map(|component| (component.serialize)(&entity).map(|bytes| (component.kind, bytes))).collect();
+            (network_id.0, transform.map(|transform| transform.translation.xy()), components)
+        })
+        .collect();
+
+    let mut packets = Vec::with_capacity(clients.len());
+    world.resource_scope(|_, mut state: Mut<ReplicationState>| {
+        state.clients.retain(|remote_id, _| clients.iter().any(|(id, _)| id == remote_id));
+        let nothing = EntityStates::default();
+        for (remote_id, client_position) in clients.iter() {
+            let history = state.clients.entry(*remote_id).or_default();
+            // Entities without a position are visible everywhere
+            let visible: EntityStates = states
+                .iter()
+                .filter(|(_, position, _)| position.map_or(true, |position| position.distance(*client_position) <= radius))
+                .map(|(id, _, components)| (*id, components.clone()))
+                .collect();
+            let (baseline_tick, baseline) = match history.baseline() {
+                Some((baseline_tick, baseline)) => (Some(baseline_tick), baseline),
+                None => (None, &nothing),
+            };
+
+            let mut packet = Replication { tick, baseline: baseline_tick, spawned: Vec::new(), updated: Vec::new(), despawned: Vec::new() };
+            for (id, components) in visible.iter() {
+                match baseline.get(id) {
+                    None => {
+                        let components = components.iter().map(|(kind, bytes)| (*kind, bytes.clone())).collect();
+                        packet.spawned.push(EntityState { id: *id, components, removed: Vec::new() });
+                    }
+                    Some(baseline_components) => {
+                        let changed: Vec<(u32, Vec<u8>)> = components
+                            .iter()
+                            .filter(|(kind, bytes)| baseline_components.get(*kind) != Some(*bytes))
+                            .map(|(kind, bytes)| (*kind, bytes.clone()))
+                            .collect();
+                        let removed: Vec<u32> = baseline_components.keys().filter(|kind| !components.contains_key(*kind)).copied().collect();
+                        if !changed.is_empty() || !removed.is_empty() {
+                            packet.updated.push(EntityState { id: *id, components: changed, removed });
+                        }
+                    }
+                }
+            }
+            packet.despawned = baseline.keys().filter(|id| !visible.contains_key(*id)).copied().collect();
+
+            // Keep sending until the client has acknowledged where it is, even if nothing changed since
+            if packet.spawned.is_empty() && packet.updated.is_empty() && packet.despawned.is_empty() && history.settled() {
+                continue;
+            }
+            history.push(tick, visible);
+            packets.push((*remote_id, packet));
+        }
+    });
+
+    let mut manager = world.resource_mut::<ServerPacketManager>();
+    let mut failed = Vec::new();
+    for (remote_id, packet) in packets {
+        if let Err(e) = manager.send_to(remote_id, packet) {
+            debug!("[server] Could not send Replication to client {}.  Error: {}", remote_id, e);
+            failed.push(remote_id);
+        }
+    }
+    // The connection closed before the player was marked disconnected, start over if the client resumes
+    let mut state = world.resource_mut::<ReplicationState>();
+    for remote_id in failed {
+        state.clients.remove(&remote_id);
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::Resource;
+use bevy::utils::HashMap;
+
+/// Serialized components of replicated entities, keyed by network id then component kind
+pub type EntityStates = HashMap<u32, HashMap<u32, Vec<u8>>>;
+
+/// Max replication states kept per client waiting to be acknowledged.  If a client falls further behind, it gets
+/// everything in its area of interest again.
+const MAX_REPLICATION_HISTORY: usize = 64;
+
+/// Next [`NetworkId`](mangovillage_common::replication::component::NetworkId) to hand out
+#[derive(Resource, Default)]
+pub struct NextNetworkId(pub u32);
+
+/// Replication state of each client, keyed by remote id
+#[derive(Resource, Default)]
+pub struct ReplicationState {
+    pub clients: HashMap<u32, ReplicationHistory>,
+}
+
+/// Entity states sent to a client, so later packets can be diffed against the latest one it acknowledged
+#[derive(Default)]
+pub struct ReplicationHistory {
+    /// Ordered by tick, oldest first
+    sent: VecDeque<(u32, EntityStates)>,
+    acked: Option<u32>,
+}
+
+impl ReplicationHistory {
+    pub fn push(&mut self, tick: u32, states: EntityStates) {
+        if self.sent.len() >= MAX_REPLICATION_HISTORY {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((tick, states));
+    }
+
+    pub fn acknowledge(&mut self, tick: u32) {
+        if matches!(self.acked, Some(acked) if acked >= tick) {
+            return;
+        }
+        self.acked = Some(tick);
+        // Never diff against anything older than the latest ack
+        while matches!(self.sent.front(), Some((sent_tick, _)) if *sent_tick < tick) {
+            self.sent.pop_front();
+        }
+    }
+
+    /// Latest acknowledged states we still have, to diff against
+    pub fn baseline(&self) -> Option<(u32, &EntityStates)> {
+        let acked = self.acked?;
+        self.sent.iter().find(|(tick, _)| *tick == acked).map(|(tick, states)| (*tick, states))
+    }
+
+    /// Whether the client acknowledged everything it was sent, so it can't be holding on to anything out of date
+    pub fn settled(&self) -> bool {
+        match self.acked {
+            Some(acked) => matches!(self.sent.back(), Some((tick, _)) if *tick == acked),
+            None => self.sent.is_empty(),
+        }
+    }
+}
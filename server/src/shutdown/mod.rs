@@ -0,0 +1,31 @@
+use std::sync::atomic::Ordering;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+
+use crate::shutdown::resource::ShutdownRequested;
+
+pub mod resource;
+
+/// Exits the app on SIGINT or SIGTERM instead of being killed, so characters are saved on the way out.  Only one
+/// handler can be installed per process, so this is left out of [`GameServerPlugin`](crate::GameServerPlugin).
+pub struct ShutdownPlugin;
+
+impl Plugin for ShutdownPlugin {
+    fn build(&self, app: &mut App) {
+        let requested = ShutdownRequested::default();
+        let flag = requested.0.clone();
+        if let Err(e) = ctrlc::set_handler(move || flag.store(true, Ordering::Relaxed)) {
+            warn!("[server] Could not handle shutdown signals, characters will only be autosaved: {}", e);
+        }
+        // Before Update, so characters are saved in the same frame the app exits after
+        app.insert_resource(requested).add_systems(PreUpdate, exit_on_signal);
+    }
+}
+
+fn exit_on_signal(requested: Res<ShutdownRequested>, mut exit: EventWriter<AppExit>) {
+    if requested.0.swap(false, Ordering::Relaxed) {
+        info!("[server] Received shutdown signal, exiting");
+        exit.send(AppExit);
+    }
+}
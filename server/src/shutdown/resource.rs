@@ -0,0 +1,8 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use bevy::prelude::Resource;
+
+/// Set by the signal handler, which runs outside of the app
+#[derive(Resource, Default)]
+pub struct ShutdownRequested(pub Arc<AtomicBool>);
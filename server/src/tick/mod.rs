@@ -0,0 +1,58 @@
+use bevy::prelude::*;
+use bevy_rapier3d::plugin::PhysicsSet;
+
+use crate::tick::resource::{ServerTick, TickConfig};
+
+pub mod resource;
+
+/// Runs the simulation at a fixed tick rate in [`FixedUpdate`], independent of how fast the server loop spins
+pub struct TickPlugin {
+    /// Simulation ticks per second
+    pub tick_rate: u32,
+    /// Snapshots sent to clients per second
+    pub send_rate: u32,
+}
+
+/// Order of everything that runs within a tick
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TickSet {
+    /// Advance the tick counter
+    Advance,
+    /// Apply client input received since the last tick
+    Input,
+    /// Gameplay that feeds into the physics step
+    Simulate,
+    /// Send out the results of the tick, after physics
+    Send,
+}
+
+impl Plugin for TickPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(FixedTime::new_from_secs(1.0 / self.tick_rate as f32))
+            .insert_resource(TickConfig { tick_rate: self.tick_rate, send_rate: self.send_rate })
+            .init_resource::<ServerTick>()
+            .configure_sets(
+                FixedUpdate,
+                (
+                    TickSet::Advance,
+                    TickSet::Input,
+                    TickSet::Simulate,
+                    PhysicsSet::SyncBackend,
+                    PhysicsSet::StepSimulation,
+                    PhysicsSet::Writeback,
+                    TickSet::Send,
+                )
+                    .chain(),
+            )
+            .add_systems(FixedUpdate, advance_tick.in_set(TickSet::Advance));
+    }
+}
+
+fn advance_tick(mut tick: ResMut<ServerTick>) {
+    tick.0 += 1;
+}
+
+/// Run condition for systems that send snapshots, so the network send rate is independent of the tick rate
+pub fn is_send_tick(tick: Res<ServerTick>, tick_config: Res<TickConfig>) -> bool {
+    tick.0 % tick_config.send_interval() == 0
+}
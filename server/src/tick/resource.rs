@@ -0,0 +1,18 @@
+use bevy::prelude::Resource;
+
+/// Monotonically increasing simulation tick, sent out with every server packet
+#[derive(Resource, Default)]
+pub struct ServerTick(pub u32);
+
+#[derive(Resource)]
+pub struct TickConfig {
+    pub tick_rate: u32,
+    pub send_rate: u32,
+}
+
+impl TickConfig {
+    /// Number of ticks between snapshots sent to clients
+    pub fn send_interval(&self) -> u32 {
+        (self.tick_rate / self.send_rate.max(1)).max(1)
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt::{Display, Formatter};
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use mangovillage_common::networking::client_packets::{Connect, Movement, Pong, ReplicationAck, SnapshotAck};
+use mangovillage_common::networking::diagnostics::resource::NetworkStats;
+
+use crate::networking::event::KickClient;
+use crate::networking::resource::{ServerMetrics, ServerPacketManager};
+use crate::state::ServerState;
+use crate::validation::resource::{PacketValidator, RateLimiter, ValidationSettings};
+
+pub mod resource;
+
+/// Checks packets from clients before they are acted on, and kicks clients that keep sending bad ones
+pub struct ValidationPlugin {
+    /// Movement packets a client may send per second, which is one per simulation tick
+    pub movement_rate: u32,
+}
+
+impl Plugin for ValidationPlugin {
+    fn build(&self, app: &mut App) {
+        let movement_rate = self.movement_rate as f32;
+        app.insert_resource(ValidationSettings { movement_rate, movement_burst: movement_rate * 2.0, ..default() })
+            .init_resource::<PacketValidator>()
+            .add_systems(Update, kick_offenders.run_if(in_state(ServerState::Running)));
+    }
+}
+
+#[derive(Debug)]
+pub enum Violation {
+    NonFinite { field: &'static str },
+    OutOfRange { field: &'static str },
+    RateLimited,
+    /// Wrong password, or unknown username
+    FailedLogin,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::NonFinite { field } => write!(f, "{} is not finite", field),
+            Violation::OutOfRange { field } => write!(f, "{} is out of range", field),
+            Violation::RateLimited => write!(f, "rate limited"),
+            Violation::FailedLogin => write!(f, "failed login"),
+        }
+    }
+}
+
+/// A packet received from clients
+pub trait Validate {
+    /// Name of the packet, for logging and rate limiting
+    const NAME: &'static str;
+
+    fn validate(&self, settings: &ValidationSettings) -> Result<(), Violation>;
+
+    /// Average packets per second and burst size clients may send, if limited
+    fn rate_limit(_settings: &ValidationSettings) -> Option<(f32, f32)> {
+        None
+    }
+}
+
+impl Validate for Connect {
+    const NAME: &'static str = "Connect";
+
+    fn validate(&self, settings: &ValidationSettings) -> Result<(), Violation> {
+        if self.build_hash.len() > settings.max_build_hash_len {
+            return Err(Violation::OutOfRange { field: "build_hash" });
+        }
+        // Checked again when registering, this only keeps hashing cheap
+        if self.credentials.username.len() > settings.max_username_len {
+            return Err(Violation::OutOfRange { field: "username" });
+        }
+        if self.credentials.password.len() > settings.max_password_len {
+            return Err(Violation::OutOfRange { field: "password" });
+        }
+        Ok(())
+    }
+
+    fn rate_limit(settings: &ValidationSettings) -> Option<(f32, f32)> {
+        Some((settings.connect_rate, settings.connect_burst))
+    }
+}
+
+impl Validate for Movement {
+    const NAME: &'static str = "Movement";
+
+    fn validate(&self, settings: &ValidationSettings) -> Result<(), Violation> {
+        if !self.translation.iter().all(|value| value.is_finite()) {
+            return Err(Violation::NonFinite { field: "translation" });
+        }
+        // A zero translation has no direction to move in
+        if self.translation == [0.0, 0.0] || self.translation.iter().any(|value| value.abs() > settings.max_movement) {
+            return Err(Violation::OutOfRange { field: "translation" });
+        }
+        Ok(())
+    }
+
+    fn rate_limit(settings: &ValidationSettings) -> Option<(f32, f32)> {
+        Some((settings.movement_rate, settings.movement_burst))
+    }
+}
+
+impl Validate for SnapshotAck {
+    const NAME: &'static str = "SnapshotAck";
+
+    fn validate(&self, _settings: &ValidationSettings) -> Result<(), Violation> {
+        // Acks for ticks we don't have a snapshot for are ignored by SnapshotHistory
+        Ok(())
+    }
+}
+
+impl Validate for ReplicationAck {
+    const NAME: &'static str = "ReplicationAck";
+
+    fn validate(&self, _settings: &ValidationSettings) -> Result<(), Violation> {
+        // Acks for ticks we don't have a replication state for are ignored by ReplicationHistory
+        Ok(())
+    }
+}
+
+impl Validate for Pong {
+    const NAME: &'static str = "Pong";
+
+    fn validate(&self, _settings: &ValidationSettings) -> Result<(), Violation> {
+        // Answers to pings we aren't waiting for are ignored
+        Ok(())
+    }
+
+    fn rate_limit(settings: &ValidationSettings) -> Option<(f32, f32)> {
+        Some((settings.pong_rate, settings.pong_burst))
+    }
+}
+
+/// Validates and rate limits packets received from clients
+#[derive(SystemParam)]
+pub struct Validator<'w> {
+    validator: ResMut<'w, PacketValidator>,
+    settings: Res<'w, ValidationSettings>,
+    metrics: ResMut<'w, ServerMetrics>,
+    stats: Res<'w, NetworkStats>,
+    time: Res<'w, Time>,
+}
+
+impl<'w> Validator<'w> {
+    /// Packets from `remote_id` that are valid and within its rate limit.  Every dropped packet counts as a violation.
+    pub fn filter<T: Validate>(&mut self, remote_id: u32, packets: Vec<T>) -> Vec<T> {
+        let now = self.time.elapsed_seconds_f64();
+        let settings = &*self.settings;
+        let record = self.validator.clients.entry(remote_id).or_default();
+        let mut valid = Vec::with_capacity(packets.len());
+        for packet in packets {
+            let result = match T::rate_limit(settings) {
+                Some((rate, burst)) => {
+                    let limiter = record.rate_limits.entry(T::NAME).or_insert_with(|| RateLimiter::new(burst, now));
+                    if limiter.try_acquire(now, rate, burst) {
+                        packet.validate(settings)
+                    } else {
+                        Err(Violation::RateLimited)
+                    }
+                }
+                None => packet.validate(settings),
+            };
+            match result {
+                Ok(()) => valid.push(packet),
+                Err(violation) => {
+                    debug!("[server] Dropping {} from client {}: {}", T::NAME, remote_id, violation);
+                    record.record_violation(now, settings.violation_window);
+                    self.metrics.violations += 1;
+                    self.stats.record_dropped(remote_id);
+                }
+            }
+        }
+        valid
+    }
+
+    /// Count a violation that only shows once a packet is acted on, like a failed login
+    pub fn record(&mut self, remote_id: u32, packet: &'static str, violation: Violation) {
+        debug!("[server] Bad {} from client {}: {}", packet, remote_id, violation);
+        let record = self.validator.clients.entry(remote_id).or_default();
+        record.record_violation(self.time.elapsed_seconds_f64(), self.settings.violation_window);
+        self.metrics.violations += 1;
+    }
+}
+
+/// Kick clients with too many recent violations, and forget clients that are gone
+fn kick_offenders(
+    manager: Res<ServerPacketManager>,
+    mut validator: ResMut<PacketValidator>,
+    settings: Res<ValidationSettings>,
+    mut kicks: EventWriter<KickClient>,
+) {
+    validator.clients.retain(|remote_id, record| {
+        if manager.get_remote_address(*remote_id).is_none() {
+            return false;
+        }
+        if record.violations.len() >= settings.max_violations {
+            let reason = format!("{} invalid packets within {} seconds", record.violations.len(), settings.violation_window);
+            kicks.send(KickClient { remote_id: *remote_id, reason });
+            return false;
+        }
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movement(translation: [f32; 2]) -> Result<(), Violation> {
+        Movement { translation, sequence: 0 }.validate(&ValidationSettings::default())
+    }
+
+    #[test]
+    fn movement_within_range_is_valid() {
+        assert!(movement([100.0, -100.0]).is_ok());
+        let max_movement = ValidationSettings::default().max_movement;
+        assert!(movement([max_movement, -max_movement]).is_ok());
+    }
+
+    #[test]
+    fn non_finite_movement_is_rejected() {
+        for translation in [[f32::NAN, 0.0], [1.0, f32::INFINITY], [f32::NEG_INFINITY, 1.0]] {
+            assert!(matches!(movement(translation), Err(Violation::NonFinite { field: "translation" })), "{:?} was accepted", translation);
+        }
+    }
+
+    #[test]
+    fn out_of_range_movement_is_rejected() {
+        let max_movement = ValidationSettings::default().max_movement;
+        for translation in [[0.0, 0.0], [max_movement + 1.0, 0.0], [0.0, -max_movement - 1.0]] {
+            assert!(matches!(movement(translation), Err(Violation::OutOfRange { field: "translation" })), "{:?} was accepted", translation);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::Resource;
+use bevy::utils::HashMap;
+use derivative::Derivative;
+
+#[derive(Resource, Derivative)]
+#[derivative(Default)]
+pub struct ValidationSettings {
+    /// Movement packets a client may send per second, on average
+    #[derivative(Default(value = "60.0"))]
+    pub movement_rate: f32,
+    /// Movement packets a client may send at once before being rate limited
+    #[derivative(Default(value = "120.0"))]
+    pub movement_burst: f32,
+    /// Pongs a client may send per second, on average.  Clients only answer pings, so this is generous.
+    #[derivative(Default(value = "4.0"))]
+    pub pong_rate: f32,
+    #[derivative(Default(value = "8.0"))]
+    pub pong_burst: f32,
+    /// Connects a client may send per second, on average.  Each one can cost a password hash, so this is strict.
+    #[derivative(Default(value = "1.0"))]
+    pub connect_rate: f32,
+    #[derivative(Default(value = "3.0"))]
+    pub connect_burst: f32,
+    /// Largest Movement translation accepted on either axis.  Clients send cursor offsets from the window center.
+    #[derivative(Default(value = "8192.0"))]
+    pub max_movement: f32,
+    /// Longest build hash accepted in a Connect
+    #[derivative(Default(value = "64"))]
+    pub max_build_hash_len: usize,
+    #[derivative(Default(value = "32"))]
+    pub max_username_len: usize,
+    #[derivative(Default(value = "128"))]
+    pub max_password_len: usize,
+    /// Clients with this many violations within [`Self::violation_window`] are kicked
+    #[derivative(Default(value = "20"))]
+    pub max_violations: usize,
+    /// Seconds violations are remembered for
+    #[derivative(Default(value = "10.0"))]
+    pub violation_window: f64,
+}
+
+/// Rate limits and recent violations of each client, by remote id
+#[derive(Resource, Default)]
+pub struct PacketValidator {
+    pub clients: HashMap<u32, ClientRecord>,
+}
+
+#[derive(Default)]
+pub struct ClientRecord {
+    /// Rate limiter for each packet that has one, by packet name
+    pub rate_limits: HashMap<&'static str, RateLimiter>,
+    /// Times of violations within the violation window
+    pub violations: VecDeque<f64>,
+}
+
+impl ClientRecord {
+    pub fn record_violation(&mut self, now: f64, window: f64) {
+        self.violations.push_back(now);
+        while matches!(self.violations.front(), Some(time) if now - *time > window) {
+            self.violations.pop_front();
+        }
+    }
+}
+
+/// Token bucket
+pub struct RateLimiter {
+    tokens: f32,
+    last_refill: f64,
+}
+
+impl RateLimiter {
+    pub fn new(burst: f32, now: f64) -> Self {
+        RateLimiter { tokens: burst, last_refill: now }
+    }
+
+    /// Take a token if there is one, refilling at `rate` tokens per second up to `burst`
+    pub fn try_acquire(&mut self, now: f64, rate: f32, burst: f32) -> bool {
+        self.tokens = (self.tokens + (now - self.last_refill) as f32 * rate).min(burst);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_is_available_at_once() {
+        let mut limiter = RateLimiter::new(3.0, 0.0);
+        for _ in 0..3 {
+            assert!(limiter.try_acquire(0.0, 1.0, 3.0));
+        }
+        assert!(!limiter.try_acquire(0.0, 1.0, 3.0));
+    }
+
+    #[test]
+    fn tokens_refill_at_rate() {
+        let mut limiter = RateLimiter::new(1.0, 0.0);
+        assert!(limiter.try_acquire(0.0, 2.0, 1.0));
+        assert!(!limiter.try_acquire(0.25, 2.0, 1.0));
+        // Half a token from the last try plus half a token since
+        assert!(limiter.try_acquire(0.5, 2.0, 1.0));
+        assert!(!limiter.try_acquire(0.5, 2.0, 1.0));
+    }
+
+    #[test]
+    fn refill_is_capped_at_burst() {
+        let mut limiter = RateLimiter::new(2.0, 0.0);
+        assert!(limiter.try_acquire(0.0, 1.0, 2.0));
+        assert!(limiter.try_acquire(0.0, 1.0, 2.0));
+        // Long enough to refill far more than the burst
+        for _ in 0..2 {
+            assert!(limiter.try_acquire(100.0, 1.0, 2.0));
+        }
+        assert!(!limiter.try_acquire(100.0, 1.0, 2.0));
+    }
+}
@@ -1,21 +1,45 @@
+use crate::config::PropSettings;
 use crate::state::ServerState;
+use crate::world::resource::Props;
 use bevy::prelude::*;
+use mangovillage_common::replication::component::{Replicated, ReplicatedModel};
 use mangovillage_common::resource::LevelInfo;
 use mangovillage_common::world;
 
-pub struct WorldPlugin;
+pub mod resource;
+
+pub struct WorldPlugin {
+    pub level: LevelInfo,
+    /// Spawned along with the level and replicated to clients
+    pub props: Vec<PropSettings>,
+}
+
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, load_world.run_if(in_state(ServerState::LoadWorld)));
+        // Sent to clients when they join
+        app.insert_resource(self.level.clone())
+            .insert_resource(Props(self.props.clone()))
+            .add_systems(Update, load_world.run_if(in_state(ServerState::LoadWorld)));
     }
 }
 
 /// Loads world into server
-fn load_world(mut commands: Commands, asset_server: Res<AssetServer>, mut server_state: ResMut<NextState<ServerState>>) {
-    let level =
-        LevelInfo { handle_id: "models/small/big.glb#Scene0".to_string(), scene_transform: [0.0, 0.0, 0.0, std::f32::consts::PI / 2.0], scale: 1.0 };
+fn load_world(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    level: Res<LevelInfo>,
+    props: Res<Props>,
+    mut server_state: ResMut<NextState<ServerState>>,
+) {
     info!("[server] Spawning level {}", level.handle_id);
     world::load_level(&mut commands, &asset_server, &level);
+    for prop in props.0.iter() {
+        commands.spawn((
+            Replicated,
+            ReplicatedModel { handle_id: prop.model.clone() },
+            TransformBundle::from_transform(Transform::from_translation(Vec3::from_array(prop.position))),
+        ));
+    }
     info!("[server] Transitioning state to LoadPhysics");
     server_state.set(ServerState::LoadPhysics);
 }
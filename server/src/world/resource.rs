@@ -0,0 +1,7 @@
+use bevy::prelude::Resource;
+
+use crate::config::PropSettings;
+
+/// Props to spawn along with the level
+#[derive(Resource)]
+pub struct Props(pub Vec<PropSettings>);